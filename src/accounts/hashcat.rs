@@ -0,0 +1,77 @@
+//! Reader for hashcat-style `user:hash` lists
+//!
+//! This is the layout hashcat expects with its `--username` switch, and a convenient lowest common
+//! denominator for hashes gathered by other means.
+
+use super::{is_hash, Account, Line, ParseError};
+
+/// Parse a `user:hash` line
+///
+/// These lists carry no IDs or account flags, so every account gets an ID of 0 and is assumed to
+/// be active.
+pub(crate) fn parse_line(line: &str) -> Result<Line, ParseError> {
+    let Some((name, hash)) = line.trim().rsplit_once(':') else {
+        return Err(ParseError::new(None, "expected \"user:hash\""));
+    };
+    if !is_hash(hash) {
+        return Err(ParseError::new(
+            Some(2),
+            "hash is not 32 hexadecimal characters",
+        ));
+    }
+
+    Ok(Line::Account(Account {
+        rid: 0,
+        name: name.to_string(),
+        password: hash.to_ascii_uppercase(),
+        history: Vec::new(),
+        lm: None,
+        uac: 0,
+        note: None,
+    }))
+}
+
+/// Check if a line looks like a `user:hash` line
+pub(crate) fn sniff(line: &str) -> bool {
+    let split: Vec<_> = line.trim().split(':').collect();
+    split.len() == 2 && is_hash(split[1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NT_HASH: &str = "8846f7eaee8fb117ad06bdd830b7586c";
+
+    #[test]
+    fn parses_account() {
+        let line = format!("CONTOSO\\alice:{NT_HASH}");
+        let Ok(Line::Account(account)) = parse_line(&line) else {
+            panic!("\"{line}\" is not an account");
+        };
+        assert_eq!(account.rid, 0);
+        assert_eq!(account.name, "CONTOSO\\alice");
+        assert_eq!(account.password, NT_HASH.to_ascii_uppercase());
+    }
+
+    #[test]
+    fn keeps_colons_in_user_name() {
+        let line = format!("odd:name:{NT_HASH}");
+        let Ok(Line::Account(account)) = parse_line(&line) else {
+            panic!("\"{line}\" is not an account");
+        };
+        assert_eq!(account.name, "odd:name");
+    }
+
+    #[test]
+    fn rejects_bad_hash() {
+        assert_eq!(parse_line("alice:1234").err().unwrap().column, Some(2));
+        assert_eq!(parse_line("alice").err().unwrap().column, None);
+    }
+
+    #[test]
+    fn sniffs_user_hash_lines() {
+        assert!(sniff(&format!("alice:{NT_HASH}")));
+        assert!(!sniff(&format!("alice:1104:{NT_HASH}:{NT_HASH}:::")));
+    }
+}
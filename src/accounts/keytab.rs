@@ -0,0 +1,278 @@
+//! Reader for MIT Kerberos keytab files
+//!
+//! A keytab holds the long-term keys of one or more principals, typically service accounts on
+//! Linux hosts joined to Active Directory. The RC4-HMAC key (encryption type 23) of a principal is
+//! its NT hash, so it can be checked just like the hashes from a domain controller dump. The
+//! format is described at https://web.mit.edu/kerberos/krb5-devel/doc/formats/keytab_file_format.html
+
+use std::fs;
+use std::io;
+
+use super::{to_hex, Account};
+
+/// Magic number and version at the start of a version 2 keytab, the only version in use today
+pub(crate) const MAGIC: [u8; 2] = [0x05, 0x02];
+
+/// Encryption type of RC4-HMAC keys
+const ETYPE_RC4_HMAC: u16 = 23;
+
+/// Build an error for a malformed keytab
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Malformed keytab: {reason}"),
+    )
+}
+
+/// A cursor over the big-endian fields of a keytab entry
+struct Fields<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if len > self.data.len() {
+            return Err(invalid("entry is truncated"));
+        }
+        let (field, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(field)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// A 16-bit length followed by that many bytes
+    fn counted(&mut self) -> io::Result<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+}
+
+/// Check if a file looks like a keytab from its first bytes
+pub(crate) fn sniff(start: &[u8]) -> bool {
+    start.starts_with(&MAGIC)
+}
+
+/// Read every RC4-HMAC key from a keytab file
+///
+/// Each key becomes an account named after its principal, with an ID of 0, noting the keytab it
+/// came from and its key version number (KVNO). Keys of other encryption types are counted and
+/// reported together.
+pub(crate) fn read(path: &str) -> io::Result<Vec<Account>> {
+    let data = fs::read(path)?;
+    if !sniff(&data) {
+        return Err(invalid("not a version 0x502 keytab"));
+    }
+
+    let mut accounts = Vec::new();
+    let mut other_keys = 0;
+    let mut rest = &data[MAGIC.len()..];
+    while !rest.is_empty() {
+        let size = i32::from_be_bytes(
+            rest.get(..4)
+                .ok_or_else(|| invalid("entry size is truncated"))?
+                .try_into()
+                .unwrap(),
+        );
+        rest = &rest[4..];
+        let len = size.unsigned_abs() as usize;
+        if len > rest.len() {
+            return Err(invalid("entry runs past the end of the file"));
+        }
+        let (entry, next) = rest.split_at(len);
+        rest = next;
+        // A negative size marks a hole left by a deleted entry
+        if size <= 0 {
+            continue;
+        }
+
+        let mut fields = Fields { data: entry };
+        let components = fields.u16()?;
+        let realm = String::from_utf8_lossy(fields.counted()?).into_owned();
+        let mut principal = Vec::with_capacity(components as usize);
+        for _ in 0..components {
+            principal.push(String::from_utf8_lossy(fields.counted()?).into_owned());
+        }
+        let _name_type = fields.u32()?;
+        let _timestamp = fields.u32()?;
+        let mut kvno = fields.u8()? as u32;
+        let etype = fields.u16()?;
+        let key = fields.counted()?;
+        // Newer keytabs follow the key with the full 32-bit KVNO, which wins unless it is zero
+        if fields.data.len() >= 4 {
+            let long_kvno = fields.u32()?;
+            if long_kvno != 0 {
+                kvno = long_kvno;
+            }
+        }
+
+        if etype != ETYPE_RC4_HMAC {
+            other_keys += 1;
+            continue;
+        }
+        if key.len() != 16 {
+            return Err(invalid("RC4-HMAC key is not 16 bytes"));
+        }
+
+        accounts.push(Account {
+            rid: 0,
+            name: format!("{}@{realm}", principal.join("/")),
+            password: to_hex(key),
+            history: Vec::new(),
+            lm: None,
+            uac: 0,
+            note: Some(format!("{path} KVNO {kvno}")),
+        });
+    }
+
+    if other_keys > 0 {
+        eprintln!("Skipped {other_keys} keytab keys that are not RC4-HMAC");
+    }
+
+    Ok(accounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    const NT_HASH: &str = "8846F7EAEE8FB117AD06BDD830B7586C";
+
+    fn counted(data: &mut Vec<u8>, value: &[u8]) {
+        data.extend((value.len() as u16).to_be_bytes());
+        data.extend(value);
+    }
+
+    /// Build a keytab entry, with its size, for a principal in `EXAMPLE.COM`
+    fn entry(
+        principal: &[&str],
+        kvno: u8,
+        etype: u16,
+        key: &[u8],
+        long_kvno: Option<u32>,
+    ) -> Vec<u8> {
+        let mut data = (principal.len() as u16).to_be_bytes().to_vec();
+        counted(&mut data, b"EXAMPLE.COM");
+        for component in principal {
+            counted(&mut data, component.as_bytes());
+        }
+        data.extend(1u32.to_be_bytes());
+        data.extend(0x5f5e1a2bu32.to_be_bytes());
+        data.push(kvno);
+        data.extend(etype.to_be_bytes());
+        counted(&mut data, key);
+        if let Some(long_kvno) = long_kvno {
+            data.extend(long_kvno.to_be_bytes());
+        }
+
+        let mut sized = (data.len() as i32).to_be_bytes().to_vec();
+        sized.extend(data);
+        sized
+    }
+
+    fn keytab(entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        for entry in entries {
+            data.extend(entry);
+        }
+        data
+    }
+
+    fn hash() -> Vec<u8> {
+        (0..16)
+            .map(|i| u8::from_str_radix(&NT_HASH[i * 2..i * 2 + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn reads_rc4_hmac_keys() {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "http.keytab",
+            keytab(&[
+                entry(
+                    &["HTTP", "web.example.com"],
+                    3,
+                    ETYPE_RC4_HMAC,
+                    &hash(),
+                    None,
+                ),
+                // AES256-CTS-HMAC-SHA1-96 keys are not NT hashes
+                entry(&["HTTP", "web.example.com"], 3, 18, &[0; 32], None),
+            ]),
+        );
+        let accounts = read(&path).unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].name, "HTTP/web.example.com@EXAMPLE.COM");
+        assert_eq!(accounts[0].password, NT_HASH);
+        assert_eq!(accounts[0].note, Some(format!("{path} KVNO 3")));
+    }
+
+    #[test]
+    fn prefers_the_long_kvno_unless_it_is_zero() {
+        let dir = TempDir::new();
+        let path = dir.write(
+            "host.keytab",
+            keytab(&[
+                entry(&["host"], 4, ETYPE_RC4_HMAC, &hash(), Some(260)),
+                entry(&["cifs"], 5, ETYPE_RC4_HMAC, &hash(), Some(0)),
+            ]),
+        );
+        let notes: Vec<_> = read(&path)
+            .unwrap()
+            .into_iter()
+            .map(|account| account.note.unwrap())
+            .collect();
+        assert_eq!(
+            notes,
+            [format!("{path} KVNO 260"), format!("{path} KVNO 5")]
+        );
+    }
+
+    #[test]
+    fn skips_holes_left_by_deleted_entries() {
+        let dir = TempDir::new();
+        let mut hole = (-8i32).to_be_bytes().to_vec();
+        hole.extend([0; 8]);
+        let path = dir.write(
+            "holes.keytab",
+            keytab(&[hole, entry(&["host"], 1, ETYPE_RC4_HMAC, &hash(), None)]),
+        );
+        assert_eq!(read(&path).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn refuses_malformed_keytabs() {
+        let dir = TempDir::new();
+        let path = dir.write("v1.keytab", [0x05, 0x01, 0, 0]);
+        assert!(read(&path).is_err());
+
+        let mut truncated = keytab(&[entry(&["host"], 1, ETYPE_RC4_HMAC, &hash(), None)]);
+        truncated.truncate(truncated.len() - 4);
+        let path = dir.write("truncated.keytab", truncated);
+        assert!(read(&path).is_err());
+
+        let path = dir.write(
+            "short.keytab",
+            keytab(&[entry(&["host"], 1, ETYPE_RC4_HMAC, &[0; 8], None)]),
+        );
+        assert!(read(&path).is_err());
+    }
+
+    #[test]
+    fn sniffs_keytabs() {
+        assert!(sniff(&[0x05, 0x02, 0, 0, 0, 0x40]));
+        assert!(!sniff(b"regf"));
+    }
+}
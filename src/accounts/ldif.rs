@@ -0,0 +1,383 @@
+//! Reader for LDIF exports of FreeIPA and OpenLDAP (Samba schema) directories
+//!
+//! FreeIPA stores each user's NT hash, base64-encoded, in the `ipaNTHash` attribute; directories
+//! using the Samba schema store it as hexadecimal in `sambaNTPassword`. An export such as
+//! `ldapsearch -LLL -o ldif-wrap=no '(objectClass=posixAccount)' uid uidNumber ipaNTHash
+//! sambaNTPassword nsAccountLock sambaAcctFlags` holds everything we need.
+//!
+//! Unlike the other formats, an LDIF account spans several lines, so this format has its own
+//! reader rather than a line parser.
+
+use std::io;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+
+use super::{is_hash, smbpasswd, to_hex, Account, OnError, ParseError};
+use crate::consts;
+
+/// A single LDIF entry, with its attributes decoded
+struct Entry {
+    /// The entry's lines as they were read, for the rejects file
+    raw: Vec<String>,
+    /// Line numbers, names and values of the attributes, with continuation lines unfolded and
+    /// base64 values decoded
+    attributes: Vec<(usize, String, Vec<u8>)>,
+}
+
+impl Entry {
+    /// Get the first value of the given attribute, whose name is matched case-insensitively
+    fn get(&self, name: &str) -> Option<&[u8]> {
+        self.attributes
+            .iter()
+            .find(|(_, attribute, _)| attribute.eq_ignore_ascii_case(name))
+            .map(|(_, _, value)| value.as_slice())
+    }
+
+    /// Get the first value of the given attribute as text
+    fn get_str(&self, name: &str) -> Option<String> {
+        self.get(name)
+            .map(|value| String::from_utf8_lossy(value).trim().to_string())
+    }
+
+    /// Build an error for the given attribute, pointing at the line it starts on
+    fn invalid(&self, name: &str, reason: impl Into<String>) -> ParseError {
+        let line = self
+            .attributes
+            .iter()
+            .find(|(_, attribute, _)| attribute.eq_ignore_ascii_case(name))
+            .map_or(0, |(line, _, _)| *line);
+        ParseError {
+            line,
+            column: None,
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Split an unfolded LDIF line into its attribute name and decoded value
+fn parse_attribute(line: &str) -> Result<(String, Vec<u8>), ParseError> {
+    let Some((name, value)) = line.split_once(':') else {
+        return Err(ParseError::new(None, "expected \"attribute: value\""));
+    };
+    let value = if let Some(encoded) = value.strip_prefix(':') {
+        BASE64
+            .decode(encoded.trim())
+            .map_err(|_| ParseError::new(None, format!("{name} is not valid base64")))?
+    } else {
+        value.trim_start().as_bytes().to_vec()
+    };
+    Ok((name.to_string(), value))
+}
+
+/// Parse a pending attribute line into its entry, keeping the entry's first error
+fn finish_attribute(
+    pending: Option<(usize, String)>,
+    entry: Option<&mut Entry>,
+    error: &mut Option<ParseError>,
+) {
+    let (Some((line, attribute)), Some(entry)) = (pending, entry) else {
+        return;
+    };
+    match parse_attribute(&attribute) {
+        Ok((name, value)) => entry.attributes.push((line, name, value)),
+        Err(mut attribute_error) => {
+            attribute_error.line = line;
+            error.get_or_insert(attribute_error);
+        }
+    }
+}
+
+/// Build an account from an entry, if it holds an NT hash
+fn to_account(entry: &Entry) -> Result<Option<Account>, ParseError> {
+    let hash = if let Some(hash) = entry.get("ipaNTHash") {
+        if hash.len() != 16 {
+            return Err(entry.invalid("ipaNTHash", "ipaNTHash is not 16 bytes"));
+        }
+        to_hex(hash)
+    } else if let Some(hash) = entry.get_str("sambaNTPassword") {
+        if !is_hash(&hash) {
+            return Err(entry.invalid(
+                "sambaNTPassword",
+                "sambaNTPassword is not 32 hexadecimal characters",
+            ));
+        }
+        hash.to_ascii_uppercase()
+    } else {
+        return Ok(None);
+    };
+
+    let name = entry
+        .get_str("uid")
+        .or_else(|| entry.get_str("dn"))
+        .unwrap_or_default();
+    let rid = match entry.get_str("uidNumber") {
+        Some(uid) => uid
+            .parse()
+            .map_err(|_| entry.invalid("uidNumber", format!("invalid uidNumber \"{uid}\"")))?,
+        None => 0,
+    };
+
+    let mut uac = entry
+        .get_str("sambaAcctFlags")
+        .map_or(0, |flags| smbpasswd::account_flags(&flags));
+    if entry
+        .get_str("nsAccountLock")
+        .is_some_and(|lock| lock.eq_ignore_ascii_case("TRUE"))
+    {
+        uac |= consts::UAC_ACCOUNT_DISABLE;
+    }
+
+    Ok(Some(Account {
+        rid,
+        name,
+        password: hash,
+        history: Vec::new(),
+        lm: None,
+        uac,
+        note: None,
+    }))
+}
+
+/// Read every account from the lines of an LDIF file
+///
+/// Entries without an NT hash, such as groups or service entries, are counted and reported
+/// together. Entries that cannot be parsed are reported with the line number of the attribute at
+/// fault, and then handled according to `on_error`; rejected entries are returned whole.
+pub(crate) fn read<I>(
+    lines: I,
+    on_error: OnError,
+) -> Result<(Vec<Account>, Vec<String>), ParseError>
+where
+    I: Iterator<Item = io::Result<String>>,
+{
+    let mut accounts = Vec::new();
+    let mut rejected = Vec::new();
+    let mut without_hash = 0;
+
+    let mut handle = |entry: Entry, error: Option<ParseError>| -> Result<(), ParseError> {
+        let error = match error {
+            Some(error) => error,
+            None => match to_account(&entry) {
+                Ok(Some(account)) => {
+                    accounts.push(account);
+                    return Ok(());
+                }
+                Ok(None) => {
+                    without_hash += 1;
+                    return Ok(());
+                }
+                Err(error) => error,
+            },
+        };
+        if on_error == OnError::Stop {
+            return Err(error);
+        }
+        eprintln!("Skipping invalid ldif entry: {error}");
+        rejected.push(entry.raw.join("\n"));
+        Ok(())
+    };
+
+    let mut entry: Option<Entry> = None;
+    // The first error found in the current entry, if any
+    let mut error = None;
+    // The current attribute line, with any continuation lines appended, and its line number
+    let mut pending: Option<(usize, String)> = None;
+
+    for (idx, line) in lines.enumerate() {
+        let line_number = idx + 1;
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                error.get_or_insert(ParseError {
+                    line: line_number,
+                    column: None,
+                    reason: format!("unable to read line: {err}"),
+                });
+                continue;
+            }
+        };
+
+        // Continuation lines start with a single space
+        if let (Some(continued), Some((_, pending))) = (line.strip_prefix(' '), pending.as_mut()) {
+            pending.push_str(continued);
+            if let Some(entry) = entry.as_mut() {
+                entry.raw.push(line);
+            }
+            continue;
+        }
+
+        // Any other line completes the pending attribute
+        finish_attribute(pending.take(), entry.as_mut(), &mut error);
+
+        if line.trim().is_empty() {
+            // A blank line ends the entry
+            if let Some(entry) = entry.take() {
+                handle(entry, error.take())?;
+            }
+        } else if line.starts_with('#') {
+            // Comments are ignored
+        } else if entry.is_none() && line.to_ascii_lowercase().starts_with("version:") {
+            // So is the LDIF version line at the top of the file
+        } else {
+            let entry = entry.get_or_insert_with(|| Entry {
+                raw: Vec::new(),
+                attributes: Vec::new(),
+            });
+            entry.raw.push(line.clone());
+            pending = Some((line_number, line));
+        }
+    }
+    finish_attribute(pending.take(), entry.as_mut(), &mut error);
+    if let Some(entry) = entry.take() {
+        handle(entry, error.take())?;
+    }
+
+    if without_hash > 0 {
+        eprintln!("Skipped {without_hash} ldif entries without an NT hash");
+    }
+
+    Ok((accounts, rejected))
+}
+
+/// Check if a line looks like part of an LDIF entry holding an NT hash
+pub(crate) fn sniff(line: &str) -> bool {
+    let line = line.to_ascii_lowercase();
+    line.starts_with("dn:")
+        || line.starts_with("ipanthash:")
+        || line.starts_with("sambantpassword:")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NT_HASH: &str = "8846F7EAEE8FB117AD06BDD830B7586C";
+    const NT_HASH_BASE64: &str = "iEb36u6PsRetBr3YMLdYbA==";
+
+    fn read_str(ldif: &str, on_error: OnError) -> Result<(Vec<Account>, Vec<String>), ParseError> {
+        read(ldif.lines().map(|line| Ok(line.to_string())), on_error)
+    }
+
+    #[test]
+    fn reads_freeipa_and_samba_entries() {
+        let ldif = format!(
+            "version: 1\n\
+             \n\
+             dn: uid=alice,cn=users,cn=accounts,dc=example,dc=com\n\
+             uid: alice\n\
+             uidNumber: 1001\n\
+             ipaNTHash:: {NT_HASH_BASE64}\n\
+             \n\
+             # A legacy OpenLDAP account\n\
+             dn: uid=bob,ou=people,dc=example,dc=com\n\
+             uid: bob\n\
+             uidNumber: 1002\n\
+             sambaNTPassword: {}\n",
+            NT_HASH.to_lowercase()
+        );
+        let (accounts, rejected) = read_str(&ldif, OnError::Stop).unwrap();
+        assert!(rejected.is_empty());
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(
+            (accounts[0].rid, accounts[0].name.as_str()),
+            (1001, "alice")
+        );
+        assert_eq!(accounts[0].password, NT_HASH);
+        assert_eq!((accounts[1].rid, accounts[1].name.as_str()), (1002, "bob"));
+        assert_eq!(accounts[1].password, NT_HASH);
+        assert_eq!(accounts[1].uac, 0);
+    }
+
+    #[test]
+    fn unfolds_continuation_lines() {
+        let ldif = format!(
+            "dn: uid=alice,cn=users,\n \
+             cn=accounts,dc=example,dc=com\n\
+             ipaNTHash:: {}\n {}\n",
+            &NT_HASH_BASE64[..10],
+            &NT_HASH_BASE64[10..]
+        );
+        let (accounts, _) = read_str(&ldif, OnError::Stop).unwrap();
+        assert_eq!(accounts[0].password, NT_HASH);
+        // Without a uid, the entry is named after its DN
+        assert_eq!(
+            accounts[0].name,
+            "uid=alice,cn=users,cn=accounts,dc=example,dc=com"
+        );
+    }
+
+    #[test]
+    fn maps_locks_and_samba_flags_to_user_account_control() {
+        let ldif = format!(
+            "dn: uid=alice\nuid: alice\nipaNTHash:: {NT_HASH_BASE64}\nnsAccountLock: TRUE\n\n\
+             dn: uid=bob\nuid: bob\nsambaNTPassword: {NT_HASH}\nsambaAcctFlags: [UX         ]\n\n\
+             dn: uid=carol\nuid: carol\nipaNTHash:: {NT_HASH_BASE64}\nnsAccountLock: false\n"
+        );
+        let (accounts, _) = read_str(&ldif, OnError::Stop).unwrap();
+        assert_eq!(accounts[0].uac, consts::UAC_ACCOUNT_DISABLE);
+        assert_eq!(
+            accounts[1].uac,
+            consts::UAC_NORMAL_ACCOUNT | consts::UAC_DONT_EXPIRE_PASSWD
+        );
+        assert_eq!(accounts[2].uac, 0);
+    }
+
+    #[test]
+    fn skips_entries_without_a_hash() {
+        let ldif = format!(
+            "dn: cn=admins,cn=groups\ncn: admins\n\n\
+             dn: uid=alice\nuid: alice\nipaNTHash:: {NT_HASH_BASE64}\n"
+        );
+        let (accounts, _) = read_str(&ldif, OnError::Stop).unwrap();
+        assert_eq!(accounts.len(), 1);
+    }
+
+    #[test]
+    fn reports_the_line_of_a_bad_attribute() {
+        let ldif = format!(
+            "dn: uid=alice\nuid: alice\nsambaNTPassword: {}\n\n\
+             dn: uid=bob\nuid: bob\nipaNTHash:: {NT_HASH_BASE64}\n",
+            &NT_HASH[..30]
+        );
+        let err = read_str(&ldif, OnError::Stop).err().unwrap();
+        assert_eq!(err.line, 3);
+        assert!(err.reason.contains("sambaNTPassword"));
+
+        let err = read_str("dn: uid=alice\nipaNTHash:: not base64!\n", OnError::Stop)
+            .err()
+            .unwrap();
+        assert_eq!(err.line, 2);
+        let err = read_str("dn: uid=alice\nipaNTHash:: AAAA\n", OnError::Stop)
+            .err()
+            .unwrap();
+        assert!(err.reason.contains("16 bytes"));
+    }
+
+    #[test]
+    fn rejects_whole_entries() {
+        let ldif = format!(
+            "dn: uid=alice\nuidNumber: many\nipaNTHash:: {NT_HASH_BASE64}\n\n\
+             dn: uid=bob\nuid: bob\nipaNTHash:: {NT_HASH_BASE64}\n"
+        );
+        let (accounts, rejected) = read_str(&ldif, OnError::Reject).unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(
+            rejected,
+            [format!(
+                "dn: uid=alice\nuidNumber: many\nipaNTHash:: {NT_HASH_BASE64}"
+            )]
+        );
+
+        let (accounts, _) = read_str(&ldif, OnError::Skip).unwrap();
+        assert_eq!(accounts.len(), 1);
+    }
+
+    #[test]
+    fn sniffs_ldif_lines() {
+        assert!(sniff("dn: uid=alice,cn=users"));
+        assert!(sniff("ipaNTHash:: iEb36u6PsRetBr3YMLdYbA=="));
+        assert!(sniff("sambaNTPassword: 8846F7EAEE8FB117AD06BDD830B7586C"));
+        assert!(!sniff("alice:1104:aad3b435b51404eeaad3b435b51404ee:::"));
+    }
+}
@@ -0,0 +1,164 @@
+//! Reader for Mimikatz's `dcsync` output with the `/csv` switch
+//!
+//! The raw console output of Mimikatz may be used as-is: the banner, the command echo lines, the
+//! `[DC]` and `[rpc]` status lines and the `Bye!` trailer are recognised and skipped.
+
+use super::{is_hash, Account, Line, ParseError};
+
+/// Lines of the Mimikatz banner start with its ASCII-art logo
+const BANNER_PREFIXES: [&str; 6] = [
+    ".#####.",
+    ".## ^ ##.",
+    "## / \\ ##",
+    "## \\ / ##",
+    "'## v ##'",
+    "'#####'",
+];
+
+/// Classify a line of Mimikatz console output that is not part of the CSV output
+fn noise(line: &str) -> Option<&'static str> {
+    let line = line.trim();
+    if BANNER_PREFIXES
+        .iter()
+        .any(|prefix| line.starts_with(prefix))
+    {
+        Some("Mimikatz banner")
+    } else if line.starts_with("mimikatz(commandline) #") || line.starts_with("mimikatz #") {
+        Some("Mimikatz command echo")
+    } else if line.starts_with("[DC]") || line.starts_with("[rpc]") {
+        Some("Mimikatz status line")
+    } else if line == "Bye!" {
+        Some("Mimikatz trailer")
+    } else {
+        None
+    }
+}
+
+/// Parse a line of Mimikatz `dcsync` CSV output
+///
+/// The line is expected to be whitespace-delineated and contain (at least) 4 columns:
+/// RID  AccountName    HashedPassword  userAccountControl
+///
+/// Accounts without a password hash have only 3 columns, and are skipped.
+pub(crate) fn parse_line(line: &str) -> Result<Line, ParseError> {
+    if let Some(reason) = noise(line) {
+        return Ok(Line::Skipped(reason));
+    }
+
+    let split: Vec<_> = line.split_whitespace().collect();
+    if split.len() == 3 && split[0].parse::<usize>().is_ok() && split[2].parse::<u32>().is_ok() {
+        return Ok(Line::Skipped("account without a password hash"));
+    }
+    if split.len() < 4 {
+        return Err(ParseError::new(
+            None,
+            format!("expected at least 4 columns, found {}", split.len()),
+        ));
+    }
+
+    let rid = split[0]
+        .parse()
+        .map_err(|_| ParseError::new(Some(1), format!("invalid RID \"{}\"", split[0])))?;
+    if !is_hash(split[2]) {
+        return Err(ParseError::new(
+            Some(3),
+            "password hash is not 32 hexadecimal characters",
+        ));
+    }
+    let uac = split[3].parse().map_err(|_| {
+        ParseError::new(
+            Some(4),
+            format!("invalid userAccountControl \"{}\"", split[3]),
+        )
+    })?;
+
+    Ok(Line::Account(Account {
+        rid,
+        name: split[1].to_string(),
+        password: split[2].to_ascii_uppercase(),
+        history: Vec::new(),
+        lm: None,
+        uac,
+        note: None,
+    }))
+}
+
+/// Check if a line looks like a line of Mimikatz `dcsync` CSV output
+pub(crate) fn sniff(line: &str) -> bool {
+    let split: Vec<_> = line.split_whitespace().collect();
+    split.len() >= 4
+        && split[0].parse::<usize>().is_ok()
+        && is_hash(split[2])
+        && split[3].parse::<u32>().is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NT_HASH: &str = "8846f7eaee8fb117ad06bdd830b7586c";
+
+    #[test]
+    fn parses_account() {
+        let line = format!("1104\talice\t{NT_HASH}\t66048");
+        let Ok(Line::Account(account)) = parse_line(&line) else {
+            panic!("\"{line}\" is not an account");
+        };
+        assert_eq!(account.rid, 1104);
+        assert_eq!(account.name, "alice");
+        assert_eq!(account.password, NT_HASH.to_ascii_uppercase());
+        assert_eq!(account.uac, 66048);
+    }
+
+    #[test]
+    fn skips_console_noise() {
+        for line in [
+            "  .#####.   mimikatz 2.2.0 (x64) #19041 Sep 19 2022 17:44:08",
+            " .## ^ ##.  \"A La Vie, A L'Amour\" - (oe.eo)",
+            " ## / \\ ##  /*** Benjamin DELPY `gentilkiwi` ( benjamin@gentilkiwi.com )",
+            " ## \\ / ##       > https://blog.gentilkiwi.com/mimikatz",
+            " '## v ##'       Vincent LE TOUX             ( vincent.letoux@gmail.com )",
+            "  '#####'        > https://pingcastle.com / https://mysmartlogon.com ***/",
+            "mimikatz(commandline) # lsadump::dcsync /all /csv",
+            "mimikatz # exit",
+            "[DC] 'contoso.com' will be the domain",
+            "[rpc] Service  : ldap",
+            "Bye!",
+        ] {
+            assert!(
+                matches!(parse_line(line), Ok(Line::Skipped(_))),
+                "\"{line}\" was not skipped"
+            );
+        }
+    }
+
+    #[test]
+    fn skips_account_without_hash() {
+        assert!(matches!(
+            parse_line("502\tkrbtgt_old\t514"),
+            Ok(Line::Skipped(_))
+        ));
+    }
+
+    #[test]
+    fn reports_bad_columns() {
+        assert_eq!(parse_line("1104\talice").err().unwrap().column, None);
+        let rid = parse_line(&format!("x\talice\t{NT_HASH}\t512"))
+            .err()
+            .unwrap();
+        assert_eq!(rid.column, Some(1));
+        let hash = parse_line("1104\talice\tnothex\t512").err().unwrap();
+        assert_eq!(hash.column, Some(3));
+        let uac = parse_line(&format!("1104\talice\t{NT_HASH}\tx"))
+            .err()
+            .unwrap();
+        assert_eq!(uac.column, Some(4));
+    }
+
+    #[test]
+    fn sniffs_account_lines_only() {
+        assert!(sniff(&format!("1104\talice\t{NT_HASH}\t512")));
+        assert!(!sniff("[DC] 'contoso.com' will be the domain"));
+        assert!(!sniff("502\tkrbtgt_old\t514"));
+    }
+}
@@ -0,0 +1,580 @@
+//! This module defines the `Account` record and the readers for the account dump formats we
+//! understand
+
+use std::fmt;
+use std::io;
+
+use clap::ValueEnum;
+
+use crate::ese::Database;
+use crate::hive::Hive;
+
+mod hashcat;
+pub(crate) mod keytab;
+mod ldif;
+mod mimikatz;
+pub(crate) mod ntds;
+mod pwdump;
+pub(crate) mod sam;
+mod secretsdump;
+mod smbpasswd;
+
+/// Number of lines at the start of the accounts file to inspect when detecting its format
+pub(crate) const SNIFF_LINES: usize = 50;
+
+/// A single account read from an account dump
+pub(crate) struct Account {
+    /// Relative ID
+    pub(crate) rid: usize,
+    /// Account name
+    pub(crate) name: String,
+    /// Account password hash, in uppercase hexadecimal
+    pub(crate) password: String,
+    /// Password history hashes, in uppercase hexadecimal, most recent first
+    ///
+    /// The history holds previous passwords only, as `secretsdump.py` numbers it: index 0 is the
+    /// password before the current one. A match of the current hash anywhere in the history means
+    /// a password has been reused.
+    pub(crate) history: Vec<String>,
+    /// LM hash, in uppercase hexadecimal, if the account has one stored
+    ///
+    /// LM hashes are trivial to crack, so any stored LM hash is a finding of its own.
+    pub(crate) lm: Option<String>,
+    /// Account `userAccountControl` flags
+    ///
+    /// https://learn.microsoft.com/en-us/troubleshoot/windows-server/identity/useraccountcontrol-manipulate-account-properties
+    pub(crate) uac: u32,
+    /// Where the account's hash was found, for formats where the name alone does not say, such
+    /// as the keytab and key version number of a Kerberos key
+    pub(crate) note: Option<String>,
+}
+
+/// What a single line of an account dump holds
+pub(crate) enum Line {
+    /// An account
+    Account(Account),
+    /// A line that is expected in this format but does not describe an account, such as a banner
+    /// or status message; the description is used when reporting skipped lines
+    Skipped(&'static str),
+}
+
+/// A line of an account dump that could not be parsed
+#[derive(Debug)]
+pub(crate) struct ParseError {
+    /// Line number, starting from 1
+    pub(crate) line: usize,
+    /// Column number, starting from 1, if the problem lies with a single column
+    pub(crate) column: Option<usize>,
+    /// Description of the problem
+    pub(crate) reason: String,
+}
+
+impl ParseError {
+    /// Create a new error for the line being parsed; the reader fills in the line number
+    pub(crate) fn new(column: Option<usize>, reason: impl Into<String>) -> Self {
+        ParseError {
+            line: 0,
+            column,
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.column {
+            Some(column) => write!(f, "line {}, column {}: {}", self.line, column, self.reason),
+            None => write!(f, "line {}: {}", self.line, self.reason),
+        }
+    }
+}
+
+/// What to do with account dump lines that cannot be parsed
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum OnError {
+    /// Stop at the first line that cannot be parsed
+    Stop,
+    /// Report the line and carry on without it
+    Skip,
+    /// Report the line, keep it for the rejects file, and carry on without it
+    Reject,
+}
+
+/// Formats of account dump files we know how to read
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum AccountsFormat {
+    /// Detect the format from the first lines of the file
+    Auto,
+    /// Mimikatz `lsadump::dcsync /all /csv` output
+    Mimikatz,
+    /// Impacket `secretsdump.py` NTDS output
+    Secretsdump,
+    /// pwdump-style `user:rid:lmhash:nthash:comment:homedir:` lines
+    Pwdump,
+    /// Samba `smbpasswd` file or `pdbedit -L -w` output
+    Smbpasswd,
+    /// Hashcat-style `user:hash` lines
+    Hashcat,
+    /// LDIF export of a FreeIPA (`ipaNTHash`) or OpenLDAP Samba schema (`sambaNTPassword`) directory
+    Ldif,
+    /// Offline `NTDS.dit` database, decrypted with the `SYSTEM` hive given by `--system`
+    Ntds,
+    /// MIT Kerberos keytab, whose RC4-HMAC keys are NT hashes
+    Keytab,
+    /// Offline `SAM` registry hive of local accounts, decrypted with the `SYSTEM` hive given by
+    /// `--system`
+    Sam,
+}
+
+/// Concrete formats, in the order they are preferred when detection is ambiguous
+const DETECTABLE: [AccountsFormat; 6] = [
+    AccountsFormat::Mimikatz,
+    AccountsFormat::Secretsdump,
+    AccountsFormat::Smbpasswd,
+    AccountsFormat::Pwdump,
+    AccountsFormat::Hashcat,
+    AccountsFormat::Ldif,
+];
+
+impl AccountsFormat {
+    /// Parse a single line of an account dump in this format
+    pub(crate) fn parse_line(self, line: &str) -> Result<Line, ParseError> {
+        match self {
+            AccountsFormat::Auto => unreachable!("the accounts format must be detected first"),
+            AccountsFormat::Mimikatz => mimikatz::parse_line(line),
+            AccountsFormat::Secretsdump => secretsdump::parse_line(line),
+            AccountsFormat::Pwdump => pwdump::parse_line(line),
+            AccountsFormat::Smbpasswd => smbpasswd::parse_line(line),
+            AccountsFormat::Hashcat => hashcat::parse_line(line),
+            AccountsFormat::Ldif => unreachable!("{self} entries span several lines"),
+            AccountsFormat::Ntds | AccountsFormat::Keytab | AccountsFormat::Sam => {
+                unreachable!("{self} is not a text format")
+            }
+        }
+    }
+
+    /// Check if a line looks like an account in this format
+    fn sniff(self, line: &str) -> bool {
+        match self {
+            AccountsFormat::Auto => false,
+            AccountsFormat::Mimikatz => mimikatz::sniff(line),
+            AccountsFormat::Secretsdump => secretsdump::sniff(line),
+            AccountsFormat::Pwdump => pwdump::sniff(line),
+            AccountsFormat::Smbpasswd => smbpasswd::sniff(line),
+            AccountsFormat::Hashcat => hashcat::sniff(line),
+            AccountsFormat::Ldif => ldif::sniff(line),
+            AccountsFormat::Ntds | AccountsFormat::Keytab | AccountsFormat::Sam => false,
+        }
+    }
+
+    /// Check if this is a binary format, which cannot be read line by line
+    pub(crate) fn is_binary(self) -> bool {
+        matches!(
+            self,
+            AccountsFormat::Ntds | AccountsFormat::Keytab | AccountsFormat::Sam
+        )
+    }
+
+    /// Detect a binary account dump format from the first bytes of the file
+    pub(crate) fn detect_binary(start: &[u8]) -> Option<AccountsFormat> {
+        if Database::sniff(start) {
+            Some(AccountsFormat::Ntds)
+        } else if keytab::sniff(start) {
+            Some(AccountsFormat::Keytab)
+        } else if Hive::sniff(start) {
+            Some(AccountsFormat::Sam)
+        } else {
+            None
+        }
+    }
+
+    /// Detect the format of an account dump from a sample of its lines
+    ///
+    /// Each line votes for every format it looks like, and the format with the most votes wins;
+    /// lines that look like no format at all (headers, banners, blank lines) are ignored. Since
+    /// `secretsdump.py` lines are pwdump lines with some extra markers that not every line
+    /// carries, any line that is unmistakably `secretsdump.py` output settles the matter.
+    ///
+    /// Returns `None` if no line in the sample looks like an account.
+    pub(crate) fn detect<S: AsRef<str>>(lines: &[S]) -> Option<AccountsFormat> {
+        let mut votes = [0usize; DETECTABLE.len()];
+        for line in lines {
+            for (format, votes) in DETECTABLE.iter().zip(votes.iter_mut()) {
+                if format.sniff(line.as_ref()) {
+                    *votes += 1;
+                }
+            }
+        }
+
+        let mut best: Option<(AccountsFormat, usize)> = None;
+        for (format, votes) in DETECTABLE.into_iter().zip(votes) {
+            if format == AccountsFormat::Secretsdump && votes > 0 {
+                return Some(format);
+            }
+            // Only a strictly greater count replaces the best so far, so ties go to the
+            // preferred format
+            if votes > best.map_or(0, |(_, best_votes)| best_votes) {
+                best = Some((format, votes));
+            }
+        }
+
+        best.map(|(format, _)| format)
+    }
+}
+
+impl fmt::Display for AccountsFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            AccountsFormat::Auto => "auto",
+            AccountsFormat::Mimikatz => "mimikatz",
+            AccountsFormat::Secretsdump => "secretsdump",
+            AccountsFormat::Pwdump => "pwdump",
+            AccountsFormat::Smbpasswd => "smbpasswd",
+            AccountsFormat::Hashcat => "hashcat",
+            AccountsFormat::Ldif => "ldif",
+            AccountsFormat::Ntds => "ntds",
+            AccountsFormat::Keytab => "keytab",
+            AccountsFormat::Sam => "sam",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Read every account from the lines of an account dump
+///
+/// Password history entries, which `secretsdump.py -history` writes as accounts named
+/// `user_history0`, `user_history1`, and so on after each account, are added to that account's
+/// history.
+///
+/// Blank lines are ignored. Other lines that do not describe an account are reported on stderr
+/// with their line numbers: runs of consecutive lines skipped for the same reason (such as a
+/// banner) are reported together, while lines that cannot be parsed are reported one by one.
+///
+/// Returns the accounts along with the lines that could not be parsed, as they were read, or the
+/// first such line's error if `on_error` is `OnError::Stop`.
+///
+/// LDIF entries span several lines, so they are handed to the LDIF reader as a whole.
+pub(crate) fn read<I>(
+    lines: I,
+    format: AccountsFormat,
+    on_error: OnError,
+) -> Result<(Vec<Account>, Vec<String>), ParseError>
+where
+    I: Iterator<Item = io::Result<String>>,
+{
+    if format == AccountsFormat::Ldif {
+        return ldif::read(lines, on_error);
+    }
+
+    let mut accounts = Vec::new();
+    let mut rejected = Vec::new();
+    // The current run of skipped lines, as (first line number, last line number, reason)
+    let mut skipped: Option<(usize, usize, &'static str)> = None;
+
+    for (idx, line) in lines.enumerate() {
+        let line_number = idx + 1;
+        let (line, parsed) = match line {
+            Ok(line) if line.trim().is_empty() => continue,
+            Ok(line) => {
+                let parsed = format.parse_line(&line);
+                (line, parsed)
+            }
+            Err(err) => (
+                String::new(),
+                Err(ParseError::new(None, format!("unable to read line: {err}"))),
+            ),
+        };
+
+        match parsed {
+            // Extend the current run of skipped lines, or start a new one
+            Ok(Line::Skipped(reason)) => match &mut skipped {
+                Some((_, last, run_reason)) if *run_reason == reason => *last = line_number,
+                _ => {
+                    report_skipped(skipped);
+                    skipped = Some((line_number, line_number, reason));
+                }
+            },
+            Ok(Line::Account(account)) => {
+                report_skipped(skipped.take());
+                match accounts.last_mut() {
+                    Some(owner) if is_history_of(&account, owner) => {
+                        owner.history.push(account.password)
+                    }
+                    _ => accounts.push(account),
+                }
+            }
+            Err(mut error) => {
+                report_skipped(skipped.take());
+                error.line = line_number;
+                if on_error == OnError::Stop {
+                    return Err(error);
+                }
+                eprintln!("Skipping invalid {format} line: {error}");
+                rejected.push(line);
+            }
+        }
+    }
+    report_skipped(skipped);
+
+    Ok((accounts, rejected))
+}
+
+/// Check if an account is really a password history entry of the account read before it, which
+/// has the same RID and the same name without the `_historyN` suffix
+fn is_history_of(account: &Account, owner: &Account) -> bool {
+    account.rid == owner.rid
+        && account
+            .name
+            .strip_prefix(owner.name.as_str())
+            .and_then(|suffix| suffix.strip_prefix("_history"))
+            .is_some_and(|index| index.parse::<usize>().is_ok())
+}
+
+/// Report a run of skipped lines on stderr
+fn report_skipped(skipped: Option<(usize, usize, &'static str)>) {
+    match skipped {
+        Some((first, last, reason)) if first == last => {
+            eprintln!("Line {first}: skipped {reason}")
+        }
+        Some((first, last, reason)) => eprintln!("Lines {first}-{last}: skipped {reason}"),
+        None => {}
+    }
+}
+
+/// The LM hash of an empty password, which dumps show for accounts without a stored LM hash
+const EMPTY_LM_HASH: &str = "AAD3B435B51404EEAAD3B435B51404EE";
+
+/// Get the LM hash from a hash field, unless it is a placeholder or the LM hash of an empty
+/// password
+pub(crate) fn lm_hash(field: &str) -> Option<String> {
+    let hash = field.to_ascii_uppercase();
+    (is_hash(&hash) && hash != EMPTY_LM_HASH).then_some(hash)
+}
+
+/// Format a binary hash as uppercase hexadecimal
+pub(crate) fn to_hex(hash: &[u8]) -> String {
+    hash.iter().map(|b| format!("{b:02X}")).collect()
+}
+
+/// Check if the given string looks like a hexadecimal-encoded NTLM or LM hash
+pub(crate) fn is_hash(hash: &str) -> bool {
+    hash.len() == 32 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Decode a hexadecimal-encoded NTLM or LM hash into its 16 bytes
+pub(crate) fn parse_hash(hash: &str) -> Option<[u8; 16]> {
+    if !is_hash(hash) {
+        return None;
+    }
+    let mut bytes = [0; 16];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hash[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NT_HASH: &str = "8846F7EAEE8FB117AD06BDD830B7586C";
+    const EMPTY_LM: &str = "AAD3B435B51404EEAAD3B435B51404EE";
+    const OTHER_HASH: &str = "64F12CDDAA88057E06A81B54E73B949B";
+
+    #[test]
+    fn detects_each_format() {
+        let samples = [
+            (
+                AccountsFormat::Mimikatz,
+                format!("1104\talice\t{NT_HASH}\t512"),
+            ),
+            (
+                AccountsFormat::Secretsdump,
+                format!("CONTOSO\\alice:1104:{EMPTY_LM}:{NT_HASH}:::"),
+            ),
+            (
+                AccountsFormat::Pwdump,
+                format!("alice:1104:{EMPTY_LM}:{NT_HASH}:::"),
+            ),
+            (
+                AccountsFormat::Smbpasswd,
+                format!("alice:1000:{EMPTY_LM}:{NT_HASH}:[U          ]:LCT-5F5E1A2B:"),
+            ),
+            (AccountsFormat::Hashcat, format!("alice:{NT_HASH}")),
+            (
+                AccountsFormat::Ldif,
+                "dn: uid=alice,cn=users,cn=accounts,dc=example,dc=com".to_string(),
+            ),
+        ];
+        for (format, line) in samples {
+            let detected = AccountsFormat::detect(&["# header", "", line.as_str()]);
+            assert!(
+                detected == Some(format),
+                "\"{line}\" is not detected as {format}"
+            );
+        }
+    }
+
+    #[test]
+    fn any_secretsdump_line_settles_detection() {
+        let lines = [
+            format!("Administrator:500:{EMPTY_LM}:{NT_HASH}:::"),
+            format!("Guest:501:{EMPTY_LM}:{NT_HASH}:::"),
+            format!("CONTOSO\\alice:1104:{EMPTY_LM}:{NT_HASH}:::"),
+        ];
+        assert!(AccountsFormat::detect(&lines) == Some(AccountsFormat::Secretsdump));
+        assert!(AccountsFormat::detect(&lines[..2]) == Some(AccountsFormat::Pwdump));
+    }
+
+    #[test]
+    fn detects_nothing_without_accounts() {
+        assert!(AccountsFormat::detect(&["", "# nothing here", "hello world"]).is_none());
+    }
+
+    #[test]
+    fn keeps_lm_hashes_that_are_not_empty() {
+        assert_eq!(
+            lm_hash("e52cac67419a9a224a3b108f3fa6cb6d").as_deref(),
+            Some("E52CAC67419A9A224A3B108F3FA6CB6D")
+        );
+        assert_eq!(lm_hash(EMPTY_LM), None);
+        assert_eq!(lm_hash(&EMPTY_LM.to_ascii_lowercase()), None);
+        assert_eq!(lm_hash("NO PASSWORD*********************"), None);
+        assert_eq!(lm_hash(""), None);
+    }
+
+    #[test]
+    fn formats_hashes_as_upper_case_hex() {
+        assert_eq!(to_hex(&[0x00, 0x0f, 0xa0, 0xff]), "000FA0FF");
+        assert!(is_hash(&to_hex(&[0xab; 16])));
+        assert!(!is_hash(&to_hex(&[0xab; 15])));
+    }
+
+    #[test]
+    fn parses_hashes() {
+        let hash = parse_hash("8846f7eaee8fb117ad06bdd830b7586C").unwrap();
+        assert_eq!(to_hex(&hash), NT_HASH);
+        assert!(parse_hash("8846F7EAEE8FB117AD06BDD830B7586").is_none());
+        assert!(parse_hash("8846F7EAEE8FB117AD06BDD830B7586X").is_none());
+        // Multi-byte characters must not split a byte
+        assert!(parse_hash("8846F7EAEE8FB117AD06BDD830B758é").is_none());
+    }
+
+    /// Read a whole account dump held in a string
+    fn read_str(
+        dump: &str,
+        format: AccountsFormat,
+        on_error: OnError,
+    ) -> Result<(Vec<Account>, Vec<String>), ParseError> {
+        read(
+            dump.lines().map(|line| Ok(line.to_string())),
+            format,
+            on_error,
+        )
+    }
+
+    #[test]
+    fn reads_raw_mimikatz_output() {
+        let dump = format!(
+            "
+  .#####.   mimikatz 2.2.0 (x64) #19041 Sep 19 2022 17:44:08
+ .## ^ ##.  \"A La Vie, A L'Amour\" - (oe.eo)
+  '#####'        > https://pingcastle.com / https://mysmartlogon.com ***/
+
+mimikatz(commandline) # lsadump::dcsync /all /csv
+[DC] 'contoso.com' will be the domain
+[DC] 'DC01.contoso.com' will be the DC server
+[DC] Exporting domain 'contoso.com'
+502\tkrbtgt\t{NT_HASH}\t514
+1104\talice\t{NT_HASH}\t512
+1105\tnohash\t512
+
+mimikatz(commandline) # exit
+Bye!
+"
+        );
+        let (accounts, rejected) =
+            read_str(&dump, AccountsFormat::Mimikatz, OnError::Stop).unwrap();
+        let names: Vec<_> = accounts
+            .iter()
+            .map(|account| account.name.as_str())
+            .collect();
+        assert_eq!(names, ["krbtgt", "alice"]);
+        assert!(rejected.is_empty());
+    }
+
+    #[test]
+    fn folds_history_entries_into_their_account() {
+        let dump = format!(
+            "CONTOSO\\alice:1104:{EMPTY_LM}:{NT_HASH}:::\n\
+             CONTOSO\\alice_history0:1104:{EMPTY_LM}:{OTHER_HASH}:::\n\
+             CONTOSO\\alice_history1:1104:{EMPTY_LM}:{NT_HASH}:::\n\
+             CONTOSO\\bob:1105:{EMPTY_LM}:{OTHER_HASH}:::\n"
+        );
+        let (accounts, _) = read_str(&dump, AccountsFormat::Secretsdump, OnError::Stop).unwrap();
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[0].history, [OTHER_HASH, NT_HASH]);
+        assert!(accounts[1].history.is_empty());
+    }
+
+    #[test]
+    fn history_entries_must_match_the_account_before_them() {
+        let account = |rid, name: &str| Account {
+            rid,
+            name: name.to_string(),
+            password: NT_HASH.to_string(),
+            history: Vec::new(),
+            lm: None,
+            uac: 0,
+            note: None,
+        };
+        let owner = account(1104, "alice");
+        assert!(is_history_of(&account(1104, "alice_history12"), &owner));
+        assert!(!is_history_of(&account(1105, "alice_history0"), &owner));
+        assert!(!is_history_of(&account(1104, "alice_history"), &owner));
+        assert!(!is_history_of(&account(1104, "alice_historyX"), &owner));
+        assert!(!is_history_of(&account(1104, "alice2_history0"), &owner));
+        assert!(!is_history_of(&account(1104, "alice"), &owner));
+    }
+
+    #[test]
+    fn stops_at_first_bad_line() {
+        let dump = format!("1104\talice\t{NT_HASH}\t512\n\n1105\tbob\tnothex\t512\nbroken\n");
+        let err = read_str(&dump, AccountsFormat::Mimikatz, OnError::Stop)
+            .err()
+            .unwrap();
+        // Blank lines still count towards line numbers
+        assert_eq!(err.line, 3);
+        assert_eq!(err.column, Some(3));
+        assert_eq!(
+            err.to_string(),
+            "line 3, column 3: password hash is not 32 hexadecimal characters"
+        );
+    }
+
+    #[test]
+    fn skips_bad_lines_and_returns_them() {
+        let dump = format!(
+            "1104\talice\t{NT_HASH}\t512\n1105\tbob\tnothex\t512\nbroken\n1106\tcarol\t{NT_HASH}\t512\n"
+        );
+        for on_error in [OnError::Skip, OnError::Reject] {
+            let (accounts, rejected) = read_str(&dump, AccountsFormat::Mimikatz, on_error).unwrap();
+            let names: Vec<_> = accounts
+                .iter()
+                .map(|account| account.name.as_str())
+                .collect();
+            assert_eq!(names, ["alice", "carol"]);
+            assert_eq!(rejected, ["1105\tbob\tnothex\t512", "broken"]);
+        }
+    }
+
+    #[test]
+    fn formats_errors_without_column() {
+        let mut err = ParseError::new(None, "expected at least 4 columns, found 1");
+        err.line = 7;
+        assert_eq!(
+            err.to_string(),
+            "line 7: expected at least 4 columns, found 1"
+        );
+    }
+}
@@ -0,0 +1,323 @@
+//! Reader for offline copies of the Active Directory database, `NTDS.dit`
+//!
+//! A consistent copy of the database can be taken without touching a live DC's memory, with
+//! `ntdsutil "ac i ntds" "ifm" "create full C:\ifm" q q`; this creates both `NTDS.dit` and the
+//! `SYSTEM` hive needed to decrypt it. Password hashes are stored encrypted with the Password
+//! Encryption Key (PEK), which is itself encrypted with the boot key held in the `SYSTEM` hive.
+
+use std::io;
+
+use super::{lm_hash, to_hex, Account};
+use crate::consts;
+use crate::crypto;
+use crate::ese::{Column, Database, Row};
+use crate::hive::{self, Hive};
+
+/// Name of the table holding every object in the directory
+const DATATABLE: &str = "datatable";
+
+// Columns of the `datatable` holding the attributes we need
+const SAM_ACCOUNT_NAME: &str = "ATTm590045";
+const SAM_ACCOUNT_TYPE: &str = "ATTj590126";
+const USER_ACCOUNT_CONTROL: &str = "ATTj589832";
+const OBJECT_SID: &str = "ATTr589970";
+const UNICODE_PWD: &str = "ATTk589914";
+const NT_PWD_HISTORY: &str = "ATTk589918";
+const DBCS_PWD: &str = "ATTk589879";
+const PEK_LIST: &str = "ATTk590689";
+
+/// Build an error for a database we cannot decrypt
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Unable to decrypt NTDS.dit: {reason}"),
+    )
+}
+
+/// Decrypt the Password Encryption Keys (PEKs) with the boot key
+///
+/// The list starts with an 8-byte header, whose first 4 bytes give the encryption used, and 16
+/// bytes of key material. Up to Windows Server 2012 R2, the list is encrypted with RC4; from
+/// Windows Server 2016, it is encrypted with AES.
+fn decrypt_peks(boot_key: &[u8; 16], pek_list: &[u8]) -> io::Result<Vec<[u8; 16]>> {
+    if pek_list.len() < 24 {
+        return Err(invalid("PEK list is too short"));
+    }
+    let (header, key_material, encrypted) = (&pek_list[..8], &pek_list[8..24], &pek_list[24..]);
+
+    let mut peks = Vec::new();
+    match header[..4] {
+        [2, 0, 0, 0] => {
+            let mut parts: Vec<&[u8]> = vec![boot_key];
+            parts.extend(std::iter::repeat_n(key_material, 1000));
+            let plain = crypto::rc4(&crypto::md5(&parts), encrypted);
+            // After a 32-byte header, each key is preceded by 4 bytes of its own header
+            for entry in plain.get(32..).unwrap_or_default().chunks_exact(20) {
+                peks.push(entry[4..].try_into().unwrap());
+            }
+        }
+        [3, 0, 0, 0] => {
+            let plain = crypto::aes_cbc_decrypt(boot_key, key_material, encrypted);
+            // After a 32-byte header, each key is preceded by its index; the list ends at the
+            // first out-of-sequence index
+            for (i, entry) in plain
+                .get(32..)
+                .unwrap_or_default()
+                .chunks_exact(20)
+                .enumerate()
+            {
+                if u32::from_le_bytes(entry[..4].try_into().unwrap()) as usize != i {
+                    break;
+                }
+                peks.push(entry[4..].try_into().unwrap());
+            }
+        }
+        _ => return Err(invalid("unknown PEK list encryption")),
+    }
+
+    if peks.is_empty() {
+        return Err(invalid("no PEKs found"));
+    }
+    Ok(peks)
+}
+
+/// Decrypt stored hashes with the PEKs, and then remove the DES layer keyed on the account's RID
+///
+/// Like the PEK list, each value has an 8-byte header and 16 bytes of key material; the fifth byte
+/// of the header picks the PEK that encrypts it. Values starting with `0x13` are encrypted with
+/// AES, and have 4 more bytes giving the length of the decrypted hashes; the rest are encrypted
+/// with RC4. A password hash holds a single hash, and a password history one per entry.
+fn decrypt_hashes(peks: &[[u8; 16]], encrypted: &[u8], rid: u32) -> Option<Vec<[u8; 16]>> {
+    let pek = peks.get(*encrypted.get(4)? as usize)?;
+    let key_material = encrypted.get(8..24)?;
+
+    let plain = if encrypted.starts_with(&[0x13, 0, 0, 0]) {
+        let mut plain = crypto::aes_cbc_decrypt(pek, key_material, encrypted.get(28..)?);
+        // Drop the padding, when the length can be trusted
+        let len = u32::from_le_bytes(encrypted.get(24..28)?.try_into().ok()?) as usize;
+        if len > 0 && len <= plain.len() {
+            plain.truncate(len);
+        }
+        plain
+    } else {
+        crypto::rc4(&crypto::md5(&[pek, key_material]), encrypted.get(24..)?)
+    };
+
+    let hashes: Vec<_> = plain
+        .chunks_exact(16)
+        .map(|hash| crypto::des_rid_decrypt(hash, rid))
+        .collect();
+    if hashes.is_empty() {
+        None
+    } else {
+        Some(hashes)
+    }
+}
+
+/// Read a little-endian 32-bit integer column
+fn u32_value(row: &Row, column: &Column) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        row.get(column)?.get(..4)?.try_into().ok()?,
+    ))
+}
+
+/// Read every account with a password from an `NTDS.dit` file, using the given `SYSTEM` hive
+pub(crate) fn read(ntds_path: &str, system_path: &str) -> io::Result<Vec<Account>> {
+    let boot_key = hive::boot_key(&Hive::open(system_path)?)?;
+    let mut database = Database::open(ntds_path)?;
+
+    let table = database.table(DATATABLE)?;
+    let sam_account_name = table.column(SAM_ACCOUNT_NAME)?;
+    let sam_account_type = table.column(SAM_ACCOUNT_TYPE)?;
+    let user_account_control = table.column(USER_ACCOUNT_CONTROL)?;
+    let object_sid = table.column(OBJECT_SID)?;
+    let unicode_pwd = table.column(UNICODE_PWD)?;
+    let nt_pwd_history = table.column(NT_PWD_HISTORY)?;
+    let dbcs_pwd = table.column(DBCS_PWD)?;
+    let pek_list = table.column(PEK_LIST)?;
+
+    // The PEK list is held by the domain object, which may be anywhere in the table
+    let mut encrypted_peks = None;
+    database.rows(DATATABLE, |row| {
+        if encrypted_peks.is_none() {
+            encrypted_peks = row.get(&pek_list).map(<[u8]>::to_vec);
+        }
+        Ok(())
+    })?;
+    let encrypted_peks = encrypted_peks.ok_or_else(|| invalid("no PEK list found"))?;
+    let peks = decrypt_peks(&boot_key, &encrypted_peks)?;
+
+    let mut accounts = Vec::new();
+    database.rows(DATATABLE, |row| {
+        let is_account = matches!(
+            u32_value(row, &sam_account_type),
+            Some(
+                consts::SAM_NORMAL_USER_ACCOUNT
+                    | consts::SAM_MACHINE_ACCOUNT
+                    | consts::SAM_TRUST_ACCOUNT
+            )
+        );
+        if !is_account {
+            return Ok(());
+        }
+
+        let name: Vec<_> = row
+            .get(&sam_account_name)
+            .unwrap_or_default()
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        let name = String::from_utf16_lossy(&name);
+        // The RID is the last component of the SID, which unlike the rest is stored big-endian
+        let Some(rid) = row
+            .get(&object_sid)
+            .and_then(|sid| sid.get(sid.len().checked_sub(4)?..))
+            .map(|rid| u32::from_be_bytes(rid.try_into().unwrap()))
+        else {
+            eprintln!("Skipping NTDS.dit account {name}: no SID");
+            return Ok(());
+        };
+        // Accounts that have never had a password set have no hash
+        let Some(encrypted) = row.get(&unicode_pwd) else {
+            return Ok(());
+        };
+        let Some(hash) = decrypt_hashes(&peks, encrypted, rid).and_then(|h| h.first().copied())
+        else {
+            eprintln!("Skipping NTDS.dit account {name}: unable to decrypt its password hash");
+            return Ok(());
+        };
+        let history = match row.get(&nt_pwd_history) {
+            Some(encrypted) => decrypt_hashes(&peks, encrypted, rid).unwrap_or_else(|| {
+                eprintln!("Unable to decrypt the password history of NTDS.dit account {name}");
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+        // The LM hash is only stored if LM hashing was enabled when the password was last set
+        let lm = row
+            .get(&dbcs_pwd)
+            .and_then(|encrypted| decrypt_hashes(&peks, encrypted, rid))
+            .and_then(|hashes| lm_hash(&to_hex(hashes.first()?)));
+
+        accounts.push(Account {
+            rid: rid as usize,
+            name,
+            password: to_hex(&hash),
+            // The first history entry is the current hash, which secretsdump.py leaves out too
+            history: history.iter().skip(1).map(|hash| to_hex(hash)).collect(),
+            lm,
+            uac: u32_value(row, &user_account_control).unwrap_or(0),
+            note: None,
+        });
+        Ok(())
+    })?;
+
+    Ok(accounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fixture;
+
+    const PASSWORD: &str = "8846F7EAEE8FB117AD06BDD830B7586C";
+    const PASSWORD1: &str = "64F12CDDAA88057E06A81B54E73B949B";
+    const DIGITS: &str = "32ED87BDB5FDC5E9CBA88547376818D4";
+    const ADMIN: &str = "209C6174DA490CAEB422F3FA5A7AE634";
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn boot_key() -> [u8; 16] {
+        hex("a2dd2e344ce5f888374b87bcc2810852").try_into().unwrap()
+    }
+
+    #[test]
+    fn reads_accounts_with_passwords() {
+        let accounts = read(&fixture("ntds.dit"), &fixture("ntds-system.hive")).unwrap();
+        let summary: Vec<_> = accounts
+            .iter()
+            .map(|account| (account.rid, account.name.as_str(), account.uac))
+            .collect();
+        // The guest account has no password, ghost is deleted, and groups are not accounts
+        assert_eq!(
+            summary,
+            [
+                (500, "Administrator", 0x10200),
+                (502, "krbtgt", 0x202),
+                (1104, "alice", 0x200),
+                (1000, "DC01$", 0x82000),
+            ]
+        );
+    }
+
+    #[test]
+    fn decrypts_aes_hashes_and_history() {
+        let accounts = read(&fixture("ntds.dit"), &fixture("ntds-system.hive")).unwrap();
+        let administrator = &accounts[0];
+        assert_eq!(administrator.password, PASSWORD1);
+        assert_eq!(administrator.history, [ADMIN]);
+        assert_eq!(administrator.lm, None);
+
+        // Encrypted with the second PEK
+        let machine = &accounts[3];
+        assert_eq!(machine.password, ADMIN);
+        assert!(machine.history.is_empty());
+    }
+
+    #[test]
+    fn decrypts_rc4_hashes_and_history() {
+        let accounts = read(&fixture("ntds.dit"), &fixture("ntds-system.hive")).unwrap();
+        let krbtgt = &accounts[1];
+        assert_eq!(krbtgt.password, DIGITS);
+        assert!(krbtgt.history.is_empty());
+
+        let alice = &accounts[2];
+        assert_eq!(alice.password, PASSWORD);
+        assert_eq!(alice.history, [PASSWORD1, DIGITS]);
+        assert_eq!(
+            alice.lm.as_deref(),
+            Some("E52CAC67419A9A224A3B108F3FA6CB6D")
+        );
+    }
+
+    #[test]
+    fn decrypts_rc4_pek_list() {
+        // Encrypted by tests/fixtures/generate.py, as written up to Windows Server 2012 R2
+        let list = hex(
+            "02000000010000001563af56ee9e73de82de59c0d579a3ebdcedabd50ca0e8ef15b0558f8b9d82a8147e\
+             72084caca834b2bb4139728a50e8d1344d2a382be2b64ec7759581be70de425f5917",
+        );
+        let peks = decrypt_peks(&boot_key(), &list).unwrap();
+        assert_eq!(peks.len(), 1);
+        assert_eq!(peks[0].to_vec(), hex("a8e8ea1b669eecc5f85ebaec2407bd96"));
+    }
+
+    #[test]
+    fn refuses_unknown_pek_lists() {
+        assert!(decrypt_peks(&boot_key(), &[2, 0, 0, 0]).is_err());
+        assert!(decrypt_peks(&boot_key(), &[9; 64]).is_err());
+        // An RC4 list with no keys after its header
+        assert!(decrypt_peks(&boot_key(), &[[2, 0, 0, 0].as_slice(), &[0; 52]].concat()).is_err());
+    }
+
+    #[test]
+    fn refuses_hashes_of_unknown_peks() {
+        let mut encrypted = [0x11, 0, 0, 0, 0, 0, 0, 0].to_vec();
+        encrypted.extend([0; 32]);
+        assert!(decrypt_hashes(&[[0; 16]], &encrypted, 500).is_some());
+        encrypted[4] = 1;
+        assert!(decrypt_hashes(&[[0; 16]], &encrypted, 500).is_none());
+        assert!(decrypt_hashes(&[[0; 16]], &encrypted[..24], 500).is_none());
+    }
+
+    #[test]
+    fn refuses_the_wrong_system_hive() {
+        assert!(read(&fixture("ntds-system.hive"), &fixture("ntds-system.hive")).is_err());
+        assert!(read(&fixture("ntds.dit"), &fixture("ntds.dit")).is_err());
+    }
+}
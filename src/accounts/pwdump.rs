@@ -0,0 +1,138 @@
+//! Reader for pwdump-style dumps
+//!
+//! Each account is on a line in the format `user:rid:lmhash:nthash:comment:homedir:`, as written by
+//! pwdump and its descendants (fgdump, Impacket's `secretsdump.py`, and so on). Missing hashes may
+//! be written as `NO PASSWORD*********************`.
+
+use super::{is_hash, lm_hash, Account, Line, ParseError};
+
+/// Check if a hash field holds either a hash or the pwdump "no password" placeholder
+fn is_hash_field(field: &str) -> bool {
+    is_hash(field) || field.starts_with("NO PASSWORD")
+}
+
+/// Parse a pwdump-style line
+///
+/// Accounts without an NT hash are skipped, and LM hashes are kept unless they are empty. pwdump
+/// does not record account flags, so every account is assumed to be active.
+pub(crate) fn parse_line(line: &str) -> Result<Line, ParseError> {
+    let split: Vec<_> = line.trim().split(':').collect();
+    if split.len() < 4 {
+        return Err(ParseError::new(
+            None,
+            format!("expected at least 4 fields, found {}", split.len()),
+        ));
+    }
+
+    let rid = split[1]
+        .parse()
+        .map_err(|_| ParseError::new(Some(2), format!("invalid RID \"{}\"", split[1])))?;
+    let lmhash = split[2];
+    if !is_hash_field(lmhash) {
+        return Err(ParseError::new(
+            Some(3),
+            "LM hash is not 32 hexadecimal characters",
+        ));
+    }
+    let nthash = split[3];
+    if !is_hash_field(nthash) {
+        return Err(ParseError::new(
+            Some(4),
+            "NT hash is not 32 hexadecimal characters",
+        ));
+    }
+    if !is_hash(nthash) {
+        return Ok(Line::Skipped("account without an NT hash"));
+    }
+
+    Ok(Line::Account(Account {
+        rid,
+        name: split[0].to_string(),
+        password: nthash.to_ascii_uppercase(),
+        history: Vec::new(),
+        lm: lm_hash(lmhash),
+        uac: 0,
+        note: None,
+    }))
+}
+
+/// Check if a line looks like a pwdump-style account line
+pub(crate) fn sniff(line: &str) -> bool {
+    let split: Vec<_> = line.trim().split(':').collect();
+    split.len() >= 4
+        && split[1].parse::<usize>().is_ok()
+        && is_hash_field(split[2])
+        && is_hash_field(split[3])
+        // smbpasswd lines share the first 4 fields, but follow them with `[flags]`
+        && !split.get(4).is_some_and(|field| field.starts_with('['))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NT_HASH: &str = "8846f7eaee8fb117ad06bdd830b7586c";
+    const EMPTY_LM: &str = "aad3b435b51404eeaad3b435b51404ee";
+    const LM_HASH: &str = "e52cac67419a9a224a3b108f3fa6cb6d";
+
+    #[test]
+    fn parses_account() {
+        let line = format!("alice:1104:{EMPTY_LM}:{NT_HASH}:::");
+        let Ok(Line::Account(account)) = parse_line(&line) else {
+            panic!("\"{line}\" is not an account");
+        };
+        assert_eq!(account.rid, 1104);
+        assert_eq!(account.name, "alice");
+        assert_eq!(account.password, NT_HASH.to_ascii_uppercase());
+        assert_eq!(account.lm, None);
+        assert_eq!(account.uac, 0);
+    }
+
+    #[test]
+    fn keeps_lm_hash_unless_it_is_empty() {
+        let line = format!("alice:1104:{LM_HASH}:{NT_HASH}:::");
+        let Ok(Line::Account(account)) = parse_line(&line) else {
+            panic!("\"{line}\" is not an account");
+        };
+        assert_eq!(account.lm, Some(LM_HASH.to_ascii_uppercase()));
+
+        let line = format!("alice:1104:NO PASSWORD*********************:{NT_HASH}:::");
+        let Ok(Line::Account(account)) = parse_line(&line) else {
+            panic!("\"{line}\" is not an account");
+        };
+        assert_eq!(account.lm, None);
+    }
+
+    #[test]
+    fn skips_account_without_nt_hash() {
+        let line = "guest:501:NO PASSWORD*********************:NO PASSWORD*********************:::";
+        assert!(matches!(parse_line(line), Ok(Line::Skipped(_))));
+    }
+
+    #[test]
+    fn reports_bad_fields() {
+        let short = parse_line("alice:1104").err().unwrap();
+        assert_eq!(short.column, None);
+        let rid = parse_line(&format!("alice:x:{EMPTY_LM}:{NT_HASH}:::"))
+            .err()
+            .unwrap();
+        assert_eq!(rid.column, Some(2));
+        let nt = parse_line(&format!("alice:1104:{EMPTY_LM}:1234:::"))
+            .err()
+            .unwrap();
+        assert_eq!(nt.column, Some(4));
+        let lm = parse_line(&format!("alice:1104:1234:{NT_HASH}:::"))
+            .err()
+            .unwrap();
+        assert_eq!(lm.column, Some(3));
+    }
+
+    #[test]
+    fn sniffs_pwdump_but_not_smbpasswd() {
+        assert!(sniff(&format!("alice:1104:{EMPTY_LM}:{NT_HASH}:::")));
+        assert!(!sniff(&format!(
+            "alice:1000:{EMPTY_LM}:{NT_HASH}:[U          ]:LCT-5F5E1A2B:"
+        )));
+        assert!(!sniff(&format!("alice:{NT_HASH}")));
+    }
+}
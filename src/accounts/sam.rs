@@ -0,0 +1,379 @@
+//! Reader for offline `SAM` registry hives, which hold the local accounts of a Windows machine
+//!
+//! A copy of the hives can be saved from an elevated prompt with `reg save HKLM\SAM sam.hive` and
+//! `reg save HKLM\SYSTEM system.hive`. Each account's hashes are encrypted with a key derived from
+//! the hashed boot key, which is stored in the `SAM` hive encrypted with the boot key held in the
+//! `SYSTEM` hive.
+
+use std::io;
+
+use super::{lm_hash, to_hex, Account};
+use crate::consts;
+use crate::crypto;
+use crate::hive::{self, Hive, Key};
+
+/// Key holding the SAM's own account domain; its `Users` subkey holds one key per account
+const ACCOUNT_DOMAIN: &str = "SAM\\Domains\\Account";
+
+/// Constants mixed into the RC4 keys of the older, pre-AES encryption scheme
+const QWERTY: &[u8] = b"!@#$%^&*()qwertyUIOPAzxcvbnmQQQQQQQQQQQQ)(*@&%\0";
+const DIGITS: &[u8] = b"0123456789012345678901234567890123456789\0";
+const NTPASSWORD: &[u8] = b"NTPASSWORD\0";
+const LMPASSWORD: &[u8] = b"LMPASSWORD\0";
+
+/// Offset of the account's data after the table of offsets at the start of its `V` value
+const V_DATA: usize = 0xcc;
+
+/// SAM account control bits, and the `userAccountControl` flags they correspond to
+const ACCOUNT_CONTROL: [(u16, u32); 11] = [
+    (0x0001, consts::UAC_ACCOUNT_DISABLE),
+    (0x0002, consts::UAC_HOMEDIR_REQUIRED),
+    (0x0004, consts::UAC_PASSWD_NOTREQD),
+    (0x0008, consts::UAC_TEMP_DUPLICATE_ACCOUNT),
+    (0x0010, consts::UAC_NORMAL_ACCOUNT),
+    (0x0020, consts::UAC_MNS_LOGON_ACCOUNT),
+    (0x0040, consts::UAC_INTERDOMAIN_TRUST_ACCOUNT),
+    (0x0080, consts::UAC_WORKSTATION_TRUST_ACCOUNT),
+    (0x0100, consts::UAC_SERVER_TRUST_ACCOUNT),
+    (0x0200, consts::UAC_DONT_EXPIRE_PASSWD),
+    (0x0400, consts::UAC_LOCKOUT),
+];
+
+/// Build an error for a hive we cannot decrypt
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Unable to decrypt SAM hive: {reason}"),
+    )
+}
+
+/// Read a little-endian 32-bit integer at the given offset
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Decrypt the hashed boot key, held in the account domain's `F` value, with the boot key
+///
+/// The key data starts at offset 0x68 with its revision: revision 1 is encrypted with RC4, and
+/// revision 2, used from Windows 10 1607, with AES.
+fn hashed_boot_key(boot_key: &[u8; 16], f: &[u8]) -> io::Result<[u8; 16]> {
+    let plain = match u32_at(f, 0x68) {
+        Some(1) => {
+            let salt = f.get(0x70..0x80).ok_or_else(|| invalid("F value is too short"))?;
+            let encrypted = f.get(0x80..0xa0).ok_or_else(|| invalid("F value is too short"))?;
+            let plain = crypto::rc4(&crypto::md5(&[salt, QWERTY, boot_key, DIGITS]), encrypted);
+            // Windows follows the key with a checksum, which only matches with the right boot key
+            let (key, checksum) = plain.split_at(16);
+            if crypto::md5(&[key, DIGITS, key, QWERTY]) != checksum {
+                return Err(invalid(
+                    "hashed boot key checksum does not match; \
+                     is the SYSTEM hive from the same machine?",
+                ));
+            }
+            plain
+        }
+        Some(2) => {
+            let len = u32_at(f, 0x74).ok_or_else(|| invalid("F value is too short"))? as usize;
+            let salt = f.get(0x78..0x88).ok_or_else(|| invalid("F value is too short"))?;
+            let encrypted = f
+                .get(0x88..0x88 + len)
+                .ok_or_else(|| invalid("F value is too short"))?;
+            crypto::aes_cbc_decrypt(boot_key, salt, encrypted)
+        }
+        _ => return Err(invalid("unknown hashed boot key revision")),
+    };
+
+    plain
+        .get(..16)
+        .and_then(|key| key.try_into().ok())
+        .ok_or_else(|| invalid("hashed boot key is too short"))
+}
+
+/// Decrypt an account's stored NT or LM hash, and then remove the DES layer keyed on its RID
+///
+/// The hash has a 4-byte header whose second half gives the encryption used. With RC4, the
+/// encrypted hash follows directly, and the key mixes in `constant`, which differs between NT and
+/// LM hashes; with AES, 4 more bytes are followed by a 16-byte salt and then the encrypted hash.
+/// Accounts without a password have only the header (and salt).
+fn decrypt_hash(
+    hashed_boot_key: &[u8; 16],
+    encrypted: &[u8],
+    rid: u32,
+    constant: &[u8],
+) -> Option<[u8; 16]> {
+    let plain = match encrypted.get(2..4)? {
+        [1, 0] => {
+            let key = crypto::md5(&[hashed_boot_key, &rid.to_le_bytes(), constant]);
+            crypto::rc4(&key, encrypted.get(4..20)?)
+        }
+        [2, 0] => {
+            let salt = encrypted.get(8..24)?;
+            let hash = encrypted.get(24..).filter(|hash| !hash.is_empty())?;
+            crypto::aes_cbc_decrypt(hashed_boot_key, salt, hash)
+        }
+        _ => return None,
+    };
+
+    Some(crypto::des_rid_decrypt(plain.get(..16)?, rid))
+}
+
+/// Read an account's name and stored NT and LM hashes from its `V` value
+///
+/// The value starts with a table of (offset, length, unknown) entries; the name is the second
+/// entry, the LM hash the thirteenth, and the NT hash the fourteenth. Offsets are relative to the
+/// end of the table.
+fn parse_v(v: &[u8]) -> Option<(String, &[u8], &[u8])> {
+    let field = |entry: usize| {
+        let offset = u32_at(v, entry * 12)? as usize + V_DATA;
+        let len = u32_at(v, entry * 12 + 4)? as usize;
+        v.get(offset..offset + len)
+    };
+
+    let name: Vec<_> = field(1)?
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    Some((String::from_utf16_lossy(&name), field(14)?, field(13)?))
+}
+
+/// Translate an account's control bits, at offset 0x38 of its `F` value, into
+/// `userAccountControl` flags
+fn account_control(f: &[u8]) -> u32 {
+    let Some(bits) = f.get(0x38..0x3a) else {
+        return 0;
+    };
+    let bits = u16::from_le_bytes([bits[0], bits[1]]);
+    ACCOUNT_CONTROL
+        .iter()
+        .filter(|(bit, _)| bits & bit != 0)
+        .fold(0, |uac, (_, flag)| uac | flag)
+}
+
+/// Read a single account from its key under `Users`, which is named after its RID in hexadecimal
+fn read_account(
+    key: &Key,
+    hashed_boot_key: &[u8; 16],
+    machine: &str,
+) -> io::Result<Option<Account>> {
+    let Ok(rid) = u32::from_str_radix(&key.name()?, 16) else {
+        // The `Names` key maps names to RIDs, and holds no hashes
+        return Ok(None);
+    };
+    let Some((name, encrypted, encrypted_lm)) = parse_v(key.value("V")?) else {
+        eprintln!("Skipping SAM account {rid}: malformed V value");
+        return Ok(None);
+    };
+    // Accounts that have never had a password set have no hash
+    let Some(hash) = decrypt_hash(hashed_boot_key, encrypted, rid, NTPASSWORD) else {
+        return Ok(None);
+    };
+    let lm = decrypt_hash(hashed_boot_key, encrypted_lm, rid, LMPASSWORD)
+        .and_then(|lm| lm_hash(&to_hex(&lm)));
+
+    Ok(Some(Account {
+        rid: rid as usize,
+        name: format!("{machine}\\{name}"),
+        password: to_hex(&hash),
+        history: Vec::new(),
+        lm,
+        uac: account_control(key.value("F")?),
+        note: None,
+    }))
+}
+
+/// Read every local account with a password from a `SAM` hive, using the given `SYSTEM` hive
+///
+/// Account names are prefixed with the machine's name, as `MACHINE\user`.
+pub(crate) fn read(sam_path: &str, system_path: &str) -> io::Result<Vec<Account>> {
+    let system = Hive::open(system_path)?;
+    let boot_key = hive::boot_key(&system)?;
+    let machine = hive::computer_name(&system)?;
+    let sam = Hive::open(sam_path)?;
+
+    let domain = sam.key(ACCOUNT_DOMAIN)?;
+    let hashed_boot_key = hashed_boot_key(&boot_key, domain.value("F")?)?;
+
+    let mut accounts = Vec::new();
+    for key in domain.subkey("Users")?.subkeys()? {
+        if let Some(account) = read_account(&key, &hashed_boot_key, &machine)? {
+            accounts.push(account);
+        }
+    }
+
+    Ok(accounts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::fixture;
+
+    const PASSWORD: &str = "8846F7EAEE8FB117AD06BDD830B7586C";
+    const PASSWORD1: &str = "64F12CDDAA88057E06A81B54E73B949B";
+    const DIGITS_HASH: &str = "32ED87BDB5FDC5E9CBA88547376818D4";
+    const ADMIN: &str = "209C6174DA490CAEB422F3FA5A7AE634";
+    const PASSWORD_LM: &str = "E52CAC67419A9A224A3B108F3FA6CB6D";
+
+    fn boot_key() -> [u8; 16] {
+        hive::boot_key(&Hive::open(&fixture("sam-system.hive")).unwrap()).unwrap()
+    }
+
+    /// The account domain's `F` value from a fixture hive
+    fn domain_f(name: &str) -> Vec<u8> {
+        let sam = Hive::open(&fixture(name)).unwrap();
+        let f = sam
+            .key(ACCOUNT_DOMAIN)
+            .unwrap()
+            .value("F")
+            .unwrap()
+            .to_vec();
+        f
+    }
+
+    fn summary(accounts: &[Account]) -> Vec<(usize, &str, &str, Option<&str>)> {
+        accounts
+            .iter()
+            .map(|account| {
+                (
+                    account.rid,
+                    account.name.as_str(),
+                    account.password.as_str(),
+                    account.lm.as_deref(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn boot_key_is_permuted_from_the_system_hive() {
+        assert_eq!(to_hex(&boot_key()), "43ACD58D4F5EB4138FB4E14DEEA30804");
+    }
+
+    #[test]
+    fn decrypts_the_aes_hashed_boot_key_at_0x68() {
+        let f = domain_f("sam.hive");
+        assert_eq!(u32_at(&f, 0x68), Some(2));
+        let key = hashed_boot_key(&boot_key(), &f).unwrap();
+        assert_eq!(to_hex(&key), "4E8E2726E511D216DD1B526436C3DE9A");
+    }
+
+    #[test]
+    fn decrypts_the_rc4_hashed_boot_key_at_0x68() {
+        let f = domain_f("sam-rc4.hive");
+        assert_eq!(u32_at(&f, 0x68), Some(1));
+        let key = hashed_boot_key(&boot_key(), &f).unwrap();
+        assert_eq!(to_hex(&key), "4E8E2726E511D216DD1B526436C3DE9A");
+    }
+
+    #[test]
+    fn refuses_the_boot_key_of_another_machine() {
+        let f = domain_f("sam-rc4.hive");
+        let mut other = boot_key();
+        other[0] ^= 1;
+        let err = hashed_boot_key(&other, &f).unwrap_err();
+        assert!(err.to_string().contains("checksum"), "{err}");
+    }
+
+    #[test]
+    fn refuses_unknown_hashed_boot_keys() {
+        let mut f = domain_f("sam.hive");
+        f[0x68] = 3;
+        assert!(hashed_boot_key(&boot_key(), &f).is_err());
+        assert!(hashed_boot_key(&boot_key(), &domain_f("sam.hive")[..0x80]).is_err());
+        assert!(hashed_boot_key(&boot_key(), &[0; 0x40]).is_err());
+    }
+
+    #[test]
+    fn reads_aes_encrypted_accounts() {
+        let accounts = read(&fixture("sam.hive"), &fixture("sam-system.hive")).unwrap();
+        // The guest account has no password
+        assert_eq!(
+            summary(&accounts),
+            [
+                (500, "WS01\\Administrator", PASSWORD1, None),
+                (504, "WS01\\WDAGUtilityAccount", ADMIN, None),
+                (1001, "WS01\\alice", PASSWORD, Some(PASSWORD_LM)),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_rc4_encrypted_accounts() {
+        let accounts = read(&fixture("sam-rc4.hive"), &fixture("sam-system.hive")).unwrap();
+        assert_eq!(
+            summary(&accounts),
+            [
+                (500, "WS01\\Administrator", DIGITS_HASH, None),
+                (1000, "WS01\\alice", PASSWORD, Some(PASSWORD_LM)),
+            ]
+        );
+    }
+
+    #[test]
+    fn maps_account_control_bits_to_user_account_control() {
+        let accounts = read(&fixture("sam.hive"), &fixture("sam-system.hive")).unwrap();
+        assert_eq!(
+            accounts[0].uac,
+            consts::UAC_ACCOUNT_DISABLE
+                | consts::UAC_NORMAL_ACCOUNT
+                | consts::UAC_DONT_EXPIRE_PASSWD
+        );
+        assert_eq!(accounts[2].uac, consts::UAC_NORMAL_ACCOUNT);
+
+        let mut f = [0; 0x50];
+        f[0x38..0x3a].copy_from_slice(&0x0684u16.to_le_bytes());
+        assert_eq!(
+            account_control(&f),
+            consts::UAC_PASSWD_NOTREQD
+                | consts::UAC_WORKSTATION_TRUST_ACCOUNT
+                | consts::UAC_DONT_EXPIRE_PASSWD
+                | consts::UAC_LOCKOUT
+        );
+        // Bits without an equivalent are ignored
+        f[0x38..0x3a].copy_from_slice(&0x0800u16.to_le_bytes());
+        assert_eq!(account_control(&f), 0);
+        assert_eq!(account_control(&f[..0x38]), 0);
+    }
+
+    #[test]
+    fn parses_v_entries_after_the_table_at_0xcc() {
+        let name: Vec<u8> = "bob".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let mut v = vec![0; V_DATA];
+        let mut set = |entry: usize, offset: u32, len: u32| {
+            v[entry * 12..entry * 12 + 4].copy_from_slice(&offset.to_le_bytes());
+            v[entry * 12 + 4..entry * 12 + 8].copy_from_slice(&len.to_le_bytes());
+        };
+        set(1, 0, 6);
+        set(13, 8, 4);
+        set(14, 12, 20);
+        v.extend(&name);
+        v.extend([0, 0]);
+        v.extend([0, 0, 1, 0]);
+        v.extend([0xaa; 20]);
+
+        let (parsed_name, nt, lm) = parse_v(&v).unwrap();
+        assert_eq!(parsed_name, "bob");
+        assert_eq!(nt, [0xaa; 20]);
+        assert_eq!(lm, [0, 0, 1, 0]);
+
+        // An entry running past the end of the value
+        assert!(parse_v(&v[..v.len() - 1]).is_none());
+        assert!(parse_v(&v[..V_DATA - 1]).is_none());
+    }
+
+    #[test]
+    fn accounts_without_a_password_have_no_hash() {
+        let key = [0; 16];
+        assert!(decrypt_hash(&key, &[0, 0, 1, 0], 501, NTPASSWORD).is_none());
+        assert!(decrypt_hash(
+            &key,
+            &[[0, 0, 2, 0].as_slice(), &[0; 20]].concat(),
+            501,
+            NTPASSWORD
+        )
+        .is_none());
+        assert!(decrypt_hash(&key, &[0, 0, 3, 0, 0, 0], 501, NTPASSWORD).is_none());
+    }
+}
@@ -0,0 +1,189 @@
+//! Reader for Impacket's `secretsdump.py` NTDS output
+//!
+//! Each account is on a line in the format `DOMAIN\user:rid:lmhash:nthash:::`, optionally followed
+//! by parenthesised details such as `(pwdLastSet=...)` and `(status=Enabled)` when the dump was
+//! made with the `-pwd-last-set` or `-user-status` switches. Apart from these details, this is the
+//! pwdump format.
+
+use super::{pwdump, Line, ParseError};
+use crate::consts;
+
+/// Openings of the parenthesised details `secretsdump.py` may append to an account line
+const DETAILS: [&str; 2] = ["(pwdLastSet=", "(status="];
+
+/// Split a line into its pwdump fields and any trailing parenthesised details
+///
+/// Only the details groups that close the line are split off, so an account name that itself
+/// holds " (" is left whole.
+fn split_details(line: &str) -> (&str, &str) {
+    let mut end = line.trim_end().len();
+    while let Some(idx) = line[..end].rfind(" (") {
+        let group = &line[idx + 1..end];
+        if !group.ends_with(')') || !DETAILS.iter().any(|opening| group.starts_with(opening)) {
+            break;
+        }
+        end = idx;
+    }
+    line.split_at(end)
+}
+
+/// Classify a line of `secretsdump.py` output that does not hold NTLM hashes
+fn noise(line: &str) -> Option<&'static str> {
+    let split: Vec<_> = line.trim().split(':').collect();
+    if line.starts_with("Impacket v") {
+        Some("secretsdump.py banner")
+    } else if line.starts_with("[*]") || line.starts_with("[+]") || line.starts_with("[-]") {
+        Some("secretsdump.py status message")
+    } else if split.len() >= 3 && split[1] == "CLEARTEXT" {
+        Some("cleartext password")
+    } else if split.len() >= 3
+        && (split[1].contains("-cts-hmac-")
+            || split[1].starts_with("des-cbc-")
+            || split[1] == "rc4_hmac"
+            || split[1].starts_with("0x"))
+    {
+        Some("Kerberos key")
+    } else {
+        None
+    }
+}
+
+/// Parse a line of `secretsdump.py` output
+///
+/// Lines that are not NTLM hash lines, such as the `[*]` status messages or the Kerberos keys and
+/// cleartext passwords sections, are skipped. The account is marked as disabled (via the
+/// `ACCOUNT_DISABLE` flag) only if the line carries `(status=Disabled)`; otherwise, the account is
+/// assumed to be active, since `secretsdump.py` only reports account status when asked to.
+pub(crate) fn parse_line(line: &str) -> Result<Line, ParseError> {
+    if let Some(reason) = noise(line) {
+        return Ok(Line::Skipped(reason));
+    }
+
+    let (fields, details) = split_details(line);
+    let mut parsed = pwdump::parse_line(fields)?;
+
+    if let Line::Account(account) = &mut parsed {
+        if details.contains("(status=Disabled)") {
+            account.uac |= consts::UAC_ACCOUNT_DISABLE;
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// Check if a line looks like `secretsdump.py` output rather than plain pwdump output
+///
+/// Only lines with a domain-qualified account name or `secretsdump.py`'s parenthesised details
+/// are distinctive; built-in accounts such as `Administrator` are written without a domain.
+pub(crate) fn sniff(line: &str) -> bool {
+    let (fields, details) = split_details(line);
+    pwdump::sniff(fields)
+        && (fields
+            .split(':')
+            .next()
+            .is_some_and(|name| name.contains('\\'))
+            || details.contains("(status=")
+            || details.contains("(pwdLastSet="))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::Account;
+
+    const NT_HASH: &str = "8846f7eaee8fb117ad06bdd830b7586c";
+    const LM_HASH: &str = "e52cac67419a9a224a3b108f3fa6cb6d";
+    const EMPTY_LM: &str = "aad3b435b51404eeaad3b435b51404ee";
+
+    fn account(line: &str) -> Account {
+        match parse_line(line) {
+            Ok(Line::Account(account)) => account,
+            Ok(Line::Skipped(reason)) => panic!("\"{line}\" was skipped as {reason}"),
+            Err(err) => panic!("\"{line}\" failed to parse: {err}"),
+        }
+    }
+
+    #[test]
+    fn parses_domain_account() {
+        let account = account(&format!("CONTOSO\\alice:1104:{EMPTY_LM}:{NT_HASH}:::"));
+        assert_eq!(account.rid, 1104);
+        assert_eq!(account.name, "CONTOSO\\alice");
+        assert_eq!(account.password, NT_HASH.to_ascii_uppercase());
+        assert_eq!(account.lm, None);
+        assert_eq!(account.uac & consts::UAC_ACCOUNT_DISABLE, 0);
+    }
+
+    #[test]
+    fn keeps_lm_hash() {
+        let account = account(&format!("Administrator:500:{LM_HASH}:{NT_HASH}:::"));
+        assert_eq!(account.lm, Some(LM_HASH.to_ascii_uppercase()));
+    }
+
+    #[test]
+    fn reads_status_details() {
+        let disabled = account(&format!(
+            "CONTOSO\\bob:1105:{EMPTY_LM}:{NT_HASH}::: (pwdLastSet=2024-01-01 10:00) (status=Disabled)"
+        ));
+        assert_ne!(disabled.uac & consts::UAC_ACCOUNT_DISABLE, 0);
+
+        let enabled = account(&format!(
+            "CONTOSO\\carol:1106:{EMPTY_LM}:{NT_HASH}::: (status=Enabled)"
+        ));
+        assert_eq!(enabled.uac & consts::UAC_ACCOUNT_DISABLE, 0);
+    }
+
+    #[test]
+    fn keeps_parentheses_in_account_names() {
+        let account = account(&format!(
+            "CONTOSO\\svc (legacy):1107:{EMPTY_LM}:{NT_HASH}::: (pwdLastSet=never) (status=Disabled)"
+        ));
+        assert_eq!(account.name, "CONTOSO\\svc (legacy)");
+        assert_eq!(account.rid, 1107);
+        assert_ne!(account.uac & consts::UAC_ACCOUNT_DISABLE, 0);
+
+        let line = format!("CONTOSO\\svc (legacy):1107:{EMPTY_LM}:{NT_HASH}:::");
+        assert_eq!(split_details(&line), (line.as_str(), ""));
+        assert!(sniff(&line));
+    }
+
+    #[test]
+    fn skips_noise() {
+        for line in [
+            "Impacket v0.11.0 - Copyright 2023 Fortra",
+            "[*] Dumping Domain Credentials (domain\\uid:rid:lmhash:nthash)",
+            "[*] Kerberos keys grabbed",
+            "CONTOSO\\alice:aes256-cts-hmac-sha1-96:0011223344556677",
+            "CONTOSO\\alice:des-cbc-md5:0011223344556677",
+            "CONTOSO\\alice:0x17:0011223344556677",
+            "CONTOSO\\alice:CLEARTEXT:hunter2",
+        ] {
+            assert!(
+                matches!(parse_line(line), Ok(Line::Skipped(_))),
+                "\"{line}\" was not skipped"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_bad_hash() {
+        let err = parse_line("CONTOSO\\alice:1104:nothex:nothex:::")
+            .err()
+            .unwrap();
+        assert_eq!(err.column, Some(3));
+    }
+
+    #[test]
+    fn sniffs_distinctive_lines_only() {
+        assert!(sniff(&format!(
+            "CONTOSO\\alice:1104:{EMPTY_LM}:{NT_HASH}:::"
+        )));
+        assert!(sniff(&format!(
+            "Administrator:500:{EMPTY_LM}:{NT_HASH}::: (status=Enabled)"
+        )));
+        // A built-in account without details is plain pwdump
+        assert!(!sniff(&format!(
+            "Administrator:500:{EMPTY_LM}:{NT_HASH}:::"
+        )));
+        assert!(!sniff("[*] Cleaning up..."));
+    }
+}
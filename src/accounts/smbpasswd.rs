@@ -0,0 +1,222 @@
+//! Reader for Samba's `smbpasswd` format
+//!
+//! This is the format of the `smbpasswd` file and of `pdbedit -L -w` output, with lines in the
+//! format `user:uid:LMHASH:NTHASH:[UX         ]:LCT-XXXXXXXX:`. Missing hashes are written as 32
+//! `X` characters, or as `NO PASSWORD` followed by `X` characters.
+
+use super::{is_hash, lm_hash, Account, Line, ParseError};
+use crate::consts;
+
+/// Samba account flags, and the `userAccountControl` flags they correspond to
+///
+/// The letters are those written by Samba's `pdb_encode_acct_ctrl`.
+const ACCOUNT_FLAGS: [(char, u32); 11] = [
+    ('U', consts::UAC_NORMAL_ACCOUNT),
+    ('D', consts::UAC_ACCOUNT_DISABLE),
+    ('N', consts::UAC_PASSWD_NOTREQD),
+    ('H', consts::UAC_HOMEDIR_REQUIRED),
+    ('T', consts::UAC_TEMP_DUPLICATE_ACCOUNT),
+    ('M', consts::UAC_MNS_LOGON_ACCOUNT),
+    ('W', consts::UAC_WORKSTATION_TRUST_ACCOUNT),
+    ('S', consts::UAC_SERVER_TRUST_ACCOUNT),
+    ('I', consts::UAC_INTERDOMAIN_TRUST_ACCOUNT),
+    ('L', consts::UAC_LOCKOUT),
+    ('X', consts::UAC_DONT_EXPIRE_PASSWD),
+];
+
+/// Translate Samba account flags into `userAccountControl` flags
+///
+/// Letters with no `userAccountControl` equivalent are ignored.
+pub(crate) fn account_flags(flags: &str) -> u32 {
+    flags
+        .chars()
+        .filter_map(|flag| ACCOUNT_FLAGS.iter().find(|(letter, _)| *letter == flag))
+        .fold(0, |uac, (_, bit)| uac | bit)
+}
+
+/// Check if a hash field holds either a hash or one of the smbpasswd placeholders
+fn is_hash_field(field: &str) -> bool {
+    is_hash(field)
+        || (field.len() == 32
+            && field
+                .trim_start_matches("NO PASSWORD")
+                .bytes()
+                .all(|b| b == b'X'))
+}
+
+/// Parse a line of `smbpasswd` output
+///
+/// Accounts without an NT hash are skipped, as are comments. The Unix UID is used as the account's
+/// ID, and the account flags (such as `D` for disabled, or `W` for a workstation trust account)
+/// are translated into their `userAccountControl` equivalents.
+pub(crate) fn parse_line(line: &str) -> Result<Line, ParseError> {
+    if line.trim_start().starts_with('#') {
+        return Ok(Line::Skipped("comment"));
+    }
+
+    let split: Vec<_> = line.trim().split(':').collect();
+    if split.len() < 5 {
+        return Err(ParseError::new(
+            None,
+            format!("expected at least 5 fields, found {}", split.len()),
+        ));
+    }
+
+    let rid = split[1]
+        .parse()
+        .map_err(|_| ParseError::new(Some(2), format!("invalid UID \"{}\"", split[1])))?;
+    let lmhash = split[2];
+    if !is_hash_field(lmhash) {
+        return Err(ParseError::new(
+            Some(3),
+            "LM hash is not 32 hexadecimal characters",
+        ));
+    }
+    let nthash = split[3];
+    if !is_hash_field(nthash) {
+        return Err(ParseError::new(
+            Some(4),
+            "NT hash is not 32 hexadecimal characters",
+        ));
+    }
+    let Some(flags) = split[4]
+        .strip_prefix('[')
+        .and_then(|flags| flags.strip_suffix(']'))
+    else {
+        return Err(ParseError::new(
+            Some(5),
+            "account flags are not enclosed in square brackets",
+        ));
+    };
+    if !is_hash(nthash) {
+        return Ok(Line::Skipped("account without an NT hash"));
+    }
+
+    let uac = account_flags(flags);
+
+    Ok(Line::Account(Account {
+        rid,
+        name: split[0].to_string(),
+        password: nthash.to_ascii_uppercase(),
+        history: Vec::new(),
+        lm: lm_hash(lmhash),
+        uac,
+        note: None,
+    }))
+}
+
+/// Check if a line looks like an `smbpasswd` account line
+pub(crate) fn sniff(line: &str) -> bool {
+    let split: Vec<_> = line.trim().split(':').collect();
+    split.len() >= 5
+        && split[1].parse::<usize>().is_ok()
+        && is_hash_field(split[2])
+        && is_hash_field(split[3])
+        && split[4].starts_with('[')
+        && split[4].ends_with(']')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NT_HASH: &str = "8846F7EAEE8FB117AD06BDD830B7586C";
+    const NO_HASH: &str = "XXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX";
+    const LM_HASH: &str = "E52CAC67419A9A224A3B108F3FA6CB6D";
+
+    #[test]
+    fn parses_account() {
+        let line = format!("alice:1000:{NO_HASH}:{NT_HASH}:[U          ]:LCT-5F5E1A2B:");
+        let Ok(Line::Account(account)) = parse_line(&line) else {
+            panic!("\"{line}\" is not an account");
+        };
+        assert_eq!(account.rid, 1000);
+        assert_eq!(account.name, "alice");
+        assert_eq!(account.password, NT_HASH);
+        assert_eq!(account.lm, None);
+        assert_eq!(account.uac, consts::UAC_NORMAL_ACCOUNT);
+    }
+
+    #[test]
+    fn maps_account_flags_to_user_account_control() {
+        assert_eq!(account_flags("[U          ]"), consts::UAC_NORMAL_ACCOUNT);
+        assert_eq!(
+            account_flags("[UDX        ]"),
+            consts::UAC_NORMAL_ACCOUNT
+                | consts::UAC_ACCOUNT_DISABLE
+                | consts::UAC_DONT_EXPIRE_PASSWD
+        );
+        assert_eq!(
+            account_flags("[WN]"),
+            consts::UAC_WORKSTATION_TRUST_ACCOUNT | consts::UAC_PASSWD_NOTREQD
+        );
+        assert_eq!(
+            account_flags("[SIL]"),
+            consts::UAC_SERVER_TRUST_ACCOUNT
+                | consts::UAC_INTERDOMAIN_TRUST_ACCOUNT
+                | consts::UAC_LOCKOUT
+        );
+        assert_eq!(
+            account_flags("[HTM]"),
+            consts::UAC_HOMEDIR_REQUIRED
+                | consts::UAC_TEMP_DUPLICATE_ACCOUNT
+                | consts::UAC_MNS_LOGON_ACCOUNT
+        );
+        // Letters without an equivalent are ignored
+        assert_eq!(account_flags("[           ]"), 0);
+        assert_eq!(account_flags("[UQ]"), consts::UAC_NORMAL_ACCOUNT);
+    }
+
+    #[test]
+    fn disabled_accounts_are_flagged() {
+        let line = format!("bob:1001:{NO_HASH}:{NT_HASH}:[DU         ]:LCT-5F5E1A2B:");
+        let Ok(Line::Account(account)) = parse_line(&line) else {
+            panic!("\"{line}\" is not an account");
+        };
+        assert_eq!(
+            account.uac,
+            consts::UAC_ACCOUNT_DISABLE | consts::UAC_NORMAL_ACCOUNT
+        );
+    }
+
+    #[test]
+    fn skips_comments_and_missing_hashes() {
+        assert!(matches!(
+            parse_line("# smbpasswd file"),
+            Ok(Line::Skipped(_))
+        ));
+        let line = format!(
+            "nobody:65534:NO PASSWORDXXXXXXXXXXXXXXXXXXXXX:{NO_HASH}:[NU         ]:LCT-00000000:"
+        );
+        assert!(matches!(parse_line(&line), Ok(Line::Skipped(_))));
+    }
+
+    #[test]
+    fn keeps_lm_hash() {
+        let line = format!("alice:1000:{LM_HASH}:{NT_HASH}:[U          ]:LCT-5F5E1A2B:");
+        let Ok(Line::Account(account)) = parse_line(&line) else {
+            panic!("\"{line}\" is not an account");
+        };
+        assert_eq!(account.lm.as_deref(), Some(LM_HASH));
+
+        let line = format!(
+            "alice:1000:{}:{NT_HASH}:[U          ]:LCT-5F5E1A2B:",
+            &LM_HASH[..31]
+        );
+        assert_eq!(parse_line(&line).err().unwrap().column, Some(3));
+    }
+
+    #[test]
+    fn rejects_flags_without_brackets() {
+        let line = format!("alice:1000:{NO_HASH}:{NT_HASH}:U:LCT-5F5E1A2B:");
+        assert_eq!(parse_line(&line).err().unwrap().column, Some(5));
+    }
+
+    #[test]
+    fn sniffs_smbpasswd_lines() {
+        assert!(sniff(&format!(
+            "alice:1000:{NO_HASH}:{NT_HASH}:[U          ]:LCT-5F5E1A2B:"
+        )));
+        assert!(!sniff(&format!("alice:1104:{NO_HASH}:{NT_HASH}:::")));
+    }
+}
@@ -0,0 +1,298 @@
+//! This module checks accounts against a list of banned base words, in the manner of Azure AD
+//! Password Protection
+//!
+//! Words such as a company's name, mascot or city are rarely in breach corpora in the forms users
+//! pick them in, like `C0ntoso2024!`. Each base word is expanded with common mangling rules into
+//! candidate passwords, and each candidate is NT hashed in memory, so that it can be matched
+//! against the account hashes. Only the base word is ever reported, never the candidate.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::accounts::parse_hash;
+use crate::crypto;
+
+/// Leetspeak substitutions, each applied in every combination to the letters it covers
+const LEET: &[&[(char, char)]] = &[
+    &[('a', '@'), ('e', '3'), ('i', '1'), ('o', '0'), ('s', '$'), ('t', '7')],
+    &[('a', '4'), ('e', '3'), ('i', '!'), ('o', '0'), ('s', '5'), ('l', '1')],
+];
+
+/// Number of letters at the start of a word whose leetspeak substitutions are combined in every
+/// way; any letters after them are only substituted along with all the others
+///
+/// Each letter doubles the number of combinations, so this keeps long words to 64 of them.
+const LEET_LETTERS: usize = 6;
+
+/// Seasons, appended on their own or followed by a year
+const SEASONS: &[&str] = &["Spring", "Summer", "Fall", "Autumn", "Winter"];
+
+/// Trailing symbols, appended after everything else
+const SYMBOLS: &[&str] = &["", "!", "@", "#", "$", "?", ".", "*", "1", "123", "!!"];
+
+/// Number of years before this one that are appended to words
+const PAST_YEARS: i64 = 30;
+
+/// Read base words from a file, one per line
+pub(crate) fn read(path: &str) -> io::Result<Vec<String>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+/// The current year, near enough for picking the years to append
+fn current_year() -> i64 {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    1970 + secs as i64 / 31_556_952
+}
+
+/// The word in lower case, upper case, capitalized and as given
+fn cases(word: &str) -> Vec<String> {
+    let lower = word.to_lowercase();
+    let mut capitalized = String::with_capacity(word.len());
+    let mut chars = lower.chars();
+    if let Some(first) = chars.next() {
+        capitalized.extend(first.to_uppercase());
+        capitalized.push_str(chars.as_str());
+    }
+
+    let mut cases = vec![word.to_string(), lower, word.to_uppercase(), capitalized];
+    cases.sort_unstable();
+    cases.dedup();
+    cases
+}
+
+/// The word with every combination of leetspeak substitutions, including none at all
+///
+/// The substitutions of each entry of [`LEET`] are combined with each other, but not with those of
+/// the other entries. Only the first [`LEET_LETTERS`] letters that can be substituted are combined
+/// in every way; the word with every letter substituted is always included.
+fn leet(word: &str) -> Vec<String> {
+    let mut variants = vec![word.to_string()];
+    for substitutions in LEET {
+        let substitute = |c: char| {
+            substitutions
+                .iter()
+                .find(|(from, _)| *from == c.to_ascii_lowercase())
+                .map(|&(_, to)| to)
+        };
+        variants.push(word.chars().map(|c| substitute(c).unwrap_or(c)).collect());
+
+        // Byte range of each letter that can be substituted, and what it is substituted with
+        let letters: Vec<_> = word
+            .char_indices()
+            .filter_map(|(i, c)| substitute(c).map(|to| (i..i + c.len_utf8(), to)))
+            .take(LEET_LETTERS)
+            .collect();
+        for combination in 1..1u32 << letters.len() {
+            let mut variant = String::with_capacity(word.len());
+            let mut rest = 0;
+            for (bit, (range, to)) in letters.iter().enumerate() {
+                if combination & 1 << bit != 0 {
+                    variant.push_str(&word[rest..range.start]);
+                    variant.push(*to);
+                    rest = range.end;
+                }
+            }
+            variant.push_str(&word[rest..]);
+            variants.push(variant);
+        }
+    }
+    variants.sort_unstable();
+    variants.dedup();
+    variants
+}
+
+/// Everything appended to a word before the trailing symbol: nothing, a year, a season, or a
+/// season and a year
+fn suffixes() -> Vec<String> {
+    let this_year = current_year();
+    let years: Vec<String> = (this_year - PAST_YEARS..=this_year + 1)
+        .flat_map(|year| [format!("{year}"), format!("{:02}", year % 100)])
+        .collect();
+
+    let mut suffixes = vec![String::new()];
+    suffixes.extend(years.iter().cloned());
+    for season in SEASONS {
+        suffixes.push(season.to_string());
+        suffixes.extend(years.iter().map(|year| format!("{season}{year}")));
+    }
+    suffixes
+}
+
+/// Account hashes that are candidate passwords made from a list of base words
+///
+/// Only the matches are kept, however many candidates the words expand into.
+pub(crate) struct Banned {
+    /// Index of the base word each matching account hash was made from
+    found: HashMap<[u8; 16], usize>,
+    /// Number of candidate passwords tried
+    candidates: usize,
+}
+
+impl Banned {
+    /// Expand each base word into candidate passwords, and hash them to find those among the given
+    /// account hashes
+    pub(crate) fn new(words: &[String], hashes: &[&str]) -> Banned {
+        let accounts: HashSet<[u8; 16]> =
+            hashes.iter().filter_map(|hash| parse_hash(hash)).collect();
+        let suffixes = suffixes();
+        let mut found = HashMap::new();
+        let mut candidates = 0;
+        for (word_idx, word) in words.iter().enumerate() {
+            for case in cases(word) {
+                for variant in leet(&case) {
+                    for suffix in &suffixes {
+                        for symbol in SYMBOLS {
+                            let candidate = format!("{variant}{suffix}{symbol}");
+                            candidates += 1;
+                            let hash = crypto::nt_hash(&candidate);
+                            if accounts.contains(&hash) {
+                                // A candidate made from more than one base word is reported under
+                                // the first of them
+                                found.entry(hash).or_insert(word_idx);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Banned { found, candidates }
+    }
+
+    /// Number of candidate passwords tried
+    pub(crate) fn len(&self) -> usize {
+        self.candidates
+    }
+
+    /// Find the base word whose candidates include the password with the given hash
+    pub(crate) fn find(&self, hash: &str) -> Option<usize> {
+        self.found.get(&parse_hash(hash)?).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::to_hex;
+
+    /// NT hash of "Password1"
+    const PASSWORD1: &str = "64F12CDDAA88057E06A81B54E73B949B";
+
+    /// Uppercase hexadecimal NT hash of a password
+    fn hash(password: &str) -> String {
+        to_hex(&crypto::nt_hash(password))
+    }
+
+    #[test]
+    fn reads_words_skipping_blank_lines() {
+        let dir = crate::testing::TempDir::new();
+        let path = dir.write("banned.txt", "Contoso\r\n\n  Redmond \n");
+        assert_eq!(read(&path).unwrap(), ["Contoso", "Redmond"]);
+    }
+
+    #[test]
+    fn expands_cases() {
+        assert_eq!(
+            cases("conTOSO"),
+            ["CONTOSO", "Contoso", "conTOSO", "contoso"]
+        );
+        assert_eq!(cases("contoso"), ["CONTOSO", "Contoso", "contoso"]);
+        assert_eq!(cases("éclair"), ["ÉCLAIR", "Éclair", "éclair"]);
+        assert_eq!(cases(""), [""]);
+    }
+
+    #[test]
+    fn combines_leetspeak_substitutions() {
+        let variants = leet("pass");
+        for variant in [
+            "pass", "p@ss", "pa$s", "pas$", "p@$s", "p@s$", "pa$$", "p@$$", "p4s5",
+        ] {
+            assert!(variants.iter().any(|v| v == variant), "{variant} missing");
+        }
+        // The substitutions of the two lists are not mixed
+        assert!(!variants.iter().any(|v| v == "p@55"));
+        // Two lists of 8 combinations, which both start with the word itself
+        assert_eq!(variants.len(), 15);
+
+        // Upper case letters are substituted too
+        assert!(leet("PASSWORD").iter().any(|v| v == "P@SSW0RD"));
+        assert_eq!(leet("xyz"), ["xyz"]);
+    }
+
+    #[test]
+    fn caps_the_leetspeak_combinations_of_long_words() {
+        let word = "aaaaaaaaaa";
+        let variants = leet(word);
+        // The first letters in every combination, for each of the two lists, and every letter at
+        // once
+        assert_eq!(variants.len(), 2 * (1 << LEET_LETTERS) + 1);
+        assert!(variants.iter().any(|v| v == "@@@@@@@@@@"));
+        assert!(variants.iter().any(|v| v == "@a@aaaaaaa"));
+        assert!(!variants.iter().any(|v| v == "aaaaaaaaa@"));
+    }
+
+    #[test]
+    fn appends_years_and_seasons() {
+        let suffixes = suffixes();
+        let year = current_year();
+        for suffix in [
+            String::new(),
+            format!("{year}"),
+            format!("{:02}", (year - 1) % 100),
+            format!("{}", year + 1),
+            format!("{}", year - PAST_YEARS),
+            "Winter".to_string(),
+            format!("Summer{:02}", year % 100),
+        ] {
+            assert!(suffixes.contains(&suffix), "{suffix:?} missing");
+        }
+        assert!(!suffixes.contains(&format!("{}", year - PAST_YEARS - 1)));
+    }
+
+    #[test]
+    fn finds_account_hashes_made_from_banned_words() {
+        let words = ["Drum".to_string(), "password".to_string()];
+        let year = current_year();
+        let drum = hash(&format!("drum{year}!"));
+        let season = hash(&format!("DRUMSummer{:02}?", year % 100));
+        let leet = hash("P@ssw0rd");
+        let unrelated = hash("correct horse battery staple");
+        let mut hashes = vec![
+            drum.as_str(),
+            season.as_str(),
+            leet.as_str(),
+            PASSWORD1,
+            unrelated.as_str(),
+        ];
+        hashes.sort_unstable();
+
+        let banned = Banned::new(&words, &hashes);
+        assert_eq!(banned.find(&drum), Some(0));
+        assert_eq!(banned.find(&season), Some(0));
+        assert_eq!(banned.find(&leet), Some(1));
+        assert_eq!(banned.find(PASSWORD1), Some(1));
+        assert_eq!(banned.find(&unrelated), None);
+        // Hashes that were not among the accounts are not kept
+        assert_eq!(banned.find(&hash("Drum")), None);
+        assert_eq!(banned.find("not a hash"), None);
+        assert!(banned.len() > 1000);
+    }
+
+    #[test]
+    fn reports_a_candidate_under_its_first_word() {
+        let words = ["Drum".to_string(), "drum".to_string()];
+        let drum = hash("Drum1");
+        let banned = Banned::new(&words, &[drum.as_str()]);
+        assert_eq!(banned.find(&drum), Some(0));
+    }
+}
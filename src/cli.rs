@@ -1,41 +1,333 @@
 //! This module defines our CLI arguments and parser
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+
+use crate::accounts::{AccountsFormat, OnError};
+use crate::search::{SearchStrategy, Source};
 
 /// Check Active Directory accounts for passwords known to have been breached
 /// 
 /// This tool is designed to check Active Directory accounts that have been dumped by Mimikatz
 /// against the NTLM hashes in the HaveIBeenPwned breached passwords file. It is suggested that
-/// the accounts be gathered by running the command `lsadump::dcsync /all /csv` in Mimikatz;
-/// ensure that you remove any lines from the output file that are not accounts, then run this
-/// tool against it.
+/// the accounts be gathered by running the command `lsadump::dcsync /all /csv` in Mimikatz, then
+/// run this tool against its output; Mimikatz's banner and status lines will be skipped.
 #[derive(Parser)]
 #[command(author, version, about, long_about)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub(crate) struct Args {
+    #[command(subcommand)]
+    pub(crate) command: Option<Command>,
+
     /// "Pwned" password hashes file
     /// 
     /// This file must contain lines in the format "<hash>:<count>", where <hash> must be
     /// a hashed password in the same format as in the <ACCOUNTS> file.
-    pub(crate) passwords: String,
+    ///
+    /// This may also be a directory holding one file per 5-character hash prefix, named
+    /// "<PREFIX>.txt" and containing "<SUFFIX>:<count>" lines, as written by the
+    /// PwnedPasswordsDownloader. "-" reads the file from standard input.
+    ///
+    /// The file may be compressed with gzip, zstd or xz, in which case it is read once from start
+    /// to finish rather than searched.
+    ///
+    /// If a binary index built from this file by the `index` subcommand sits next to it, as
+    /// "<PASSWORDS>.bin", the index is searched instead. A binary index may also be given here
+    /// directly.
+    ///
+    /// This may also be the base URL of a Pwned Passwords range API, such as an internal mirror of
+    /// HaveIBeenPwned's, starting with "http://" or "https://"; see `--range-cache`.
+    ///
+    /// The file may be given a name to report it under in the "Source" column of the output, as
+    /// "NAME=PATH"; by default, it is named after the file, less its extension. See `--corpus` to
+    /// check against more files at once.
+    #[arg(required = true, value_parser = Source::parse)]
+    pub(crate) passwords: Option<Source>,
 
     /// Account dump file
     /// 
-    /// Each line in this file must be whitespace-separated columns, in this order: "Relative ID",
-    /// "Account Name", "Account Password", "User Account Control flags"; this is the same format
-    /// as Mimikatz's `dcsync` command with the `/csv` option. The account password must be hashed
-    /// in the same format as in the <PASSWORDS> file. This file is assumed to not include a header
-    /// row.
+    /// By default, each line in this file must be whitespace-separated columns, in this order:
+    /// "Relative ID", "Account Name", "Account Password", "User Account Control flags"; this is the
+    /// same format as Mimikatz's `dcsync` command with the `/csv` option. The account password must
+    /// be hashed in the same format as in the <PASSWORDS> file. This file is assumed to not include
+    /// a header row. See `--accounts-format` for other supported formats.
     /// 
     /// Accounts for which the "ACCOUNT_DISABLE" flag is set in the "User Account Control flags"
     /// will be skipped.
-    pub(crate) accounts: String,
+    #[arg(required = true)]
+    pub(crate) accounts: Option<String>,
 
     /// Output CSV file
     /// 
-    /// Each row of the output will describe an account whose password was found in the
-    /// <PASSWORDS> file, with the following columns: "RID" ("Relative ID"), "Name",
-    /// "userAccountControl", and "Pwned" containing the number of times the password has been
-    /// seen in breaches.
+    /// Each row of the output will describe a finding about an account, such as its password
+    /// being found in the <PASSWORDS> file, with the following columns: "RID" ("Relative ID"),
+    /// "Name", "userAccountControl", "Pwned" containing the number of times the password has
+    /// been seen in breaches, "Finding", "Source" listing each <PASSWORDS> file the password was
+    /// found in with its count there, and "Note".
     #[arg(default_value_t = String::from("./pwned.csv"))]
     pub(crate) outfile: String,
+
+    /// Format of the <ACCOUNTS> file
+    ///
+    /// By default, the format is detected from the first lines of the file, and the detected
+    /// format is reported. "mimikatz" is the output of Mimikatz's `dcsync` command with the `/csv`
+    /// option, as described above. "secretsdump" is the NTDS output of Impacket's
+    /// `secretsdump.py`, with lines in the format "DOMAIN\user:rid:lmhash:nthash:::"; accounts are
+    /// only treated as disabled if the dump was made with `-user-status` and shows
+    /// "(status=Disabled)". "pwdump" is the same, without the domain or status. "smbpasswd" is
+    /// Samba's `smbpasswd` file or `pdbedit -L -w` output. "hashcat" is "user:hash" lines.
+    /// "ldif" is an LDIF export of a FreeIPA or OpenLDAP directory holding `ipaNTHash` or
+    /// `sambaNTPassword` attributes; entries without either are skipped.
+    /// "ntds" is an offline copy of the Active Directory database, NTDS.dit, such as one created
+    /// by `ntdsutil`'s "ifm" command; it needs the matching SYSTEM hive (see `--system`).
+    /// "keytab" is a Kerberos keytab, whose RC4-HMAC keys are the principals' NT hashes. "sam" is
+    /// a SAM registry hive holding a machine's local accounts; like "ntds", it needs the matching
+    /// SYSTEM hive.
+    #[arg(long, value_enum, default_value_t = AccountsFormat::Auto)]
+    pub(crate) accounts_format: AccountsFormat,
+
+    /// SYSTEM registry hive
+    ///
+    /// The SYSTEM hive holds the boot key needed to decrypt the password hashes in an NTDS.dit or
+    /// SAM hive <ACCOUNTS> file; it must come from the same machine as the <ACCOUNTS> file.
+    #[arg(long)]
+    pub(crate) system: Option<String>,
+
+    /// What to do with lines in the <ACCOUNTS> file that cannot be parsed
+    ///
+    /// Each such line is reported with its line number, column and the reason it could not be
+    /// parsed. "stop" ends the run at the first such line; "skip" carries on without it; "reject"
+    /// carries on without it, and also writes it to the rejects file (see `--rejects-file`).
+    #[arg(long, value_enum, default_value_t = OnError::Skip)]
+    pub(crate) on_error: OnError,
+
+    /// Another "pwned" password hashes file to check the accounts against, as [NAME=]PATH
+    ///
+    /// This may be given any number of times, to check against other lists alongside
+    /// <PASSWORDS>, such as hashes of passwords cracked in past audits. Each file may be in any
+    /// of the forms <PASSWORDS> may be, and is named the same way. Every file a hash is found in
+    /// is listed in the "Source" column of the output, with the hash's count in that file.
+    #[arg(long = "corpus", value_name = "[NAME=]PATH", value_parser = Source::parse)]
+    pub(crate) corpora: Vec<Source>,
+
+    /// Manifest of more "pwned" password hashes files to check the accounts against
+    ///
+    /// Each line of the manifest holds a file as [NAME=]PATH, as for `--corpus`; blank lines and
+    /// lines starting with "#" are skipped. Relative paths are taken relative to the manifest.
+    #[arg(long)]
+    pub(crate) corpus_manifest: Option<String>,
+
+    /// Banned words file
+    ///
+    /// Each line of this file is a base word that passwords should not be made from, such as the
+    /// organization's name, mascot or city. Every word is expanded into candidate passwords: in
+    /// lower case, upper case, capitalized and as given; with leetspeak substitutions, such as "0"
+    /// for "o"; followed by a year, a season or both; and followed by a symbol such as "!". The
+    /// candidates are NT hashed in memory and matched against the accounts, and matches are
+    /// reported as "banned word" findings naming the base word, never the password itself.
+    #[arg(long)]
+    pub(crate) banned_words: Option<String>,
+
+    /// How to search each "pwned" password hashes file
+    ///
+    /// "auto" uses the file's binary index or prefix offset sidecar if it has one. Otherwise, it
+    /// binary searches a memory map of the file for a handful of hashes, jump searches the file
+    /// for more, and reads it once from start to finish with a merge-join once there are enough
+    /// hashes to look up for the file's size, or if the file cannot be seeked into, like a
+    /// compressed file or a pipe. "binary-search", "jump" and "merge-join" search the file itself
+    /// that way, ignoring any binary index or sidecar. "unsorted" reads the file once from start
+    /// to finish, looking each line up in a set of the hashes, so the file may be in any order,
+    /// such as the one ordered by prevalence. The strategy used is reported at the end of the run.
+    ///
+    /// A directory of prefix files or a range API URL is always searched by prefix, so only
+    /// "auto" is accepted for it.
+    #[arg(long, value_enum, default_value_t = SearchStrategy::Auto)]
+    pub(crate) strategy: SearchStrategy,
+
+    /// Number of threads to search each "pwned" password hashes file with
+    ///
+    /// The hashes to look up are split into ranges of hash prefixes, and each range is searched
+    /// on its own thread with its own handle on the file; the output is the same as with a single
+    /// thread. A file that can only be read from start to finish, like a compressed file, is
+    /// always searched with a single thread.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    pub(crate) threads: u16,
+
+    /// Directory to cache range API responses in
+    ///
+    /// When a <PASSWORDS> file is the base URL of a range API, "<URL>/range/<PREFIX>?mode=ntlm" is
+    /// requested for each 5-character prefix of the hashes to look up, and the hashes are matched
+    /// against the response locally. With this option, each response is kept in this directory
+    /// as "<PREFIX>.txt", and later runs read it from there rather than ask again; delete the
+    /// files to ask again.
+    #[arg(long)]
+    pub(crate) range_cache: Option<String>,
+
+    /// Largest number of range API requests in flight at once
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u16).range(1..))]
+    pub(crate) range_concurrency: u16,
+
+    /// Number of times a failed range API request is retried
+    ///
+    /// Connection errors, rate limiting and server errors are retried after a growing delay, or
+    /// as long as the server asks for, but never more than a minute or so; and at most 10 times.
+    #[arg(long, default_value_t = 3)]
+    pub(crate) range_retries: u32,
+
+    /// Rejects file
+    ///
+    /// With `--on-error reject`, lines in the <ACCOUNTS> file that cannot be parsed are written
+    /// to this file as they were read, so they can be corrected and checked separately.
+    #[arg(long, default_value_t = String::from("./rejects.txt"))]
+    pub(crate) rejects_file: String,
+}
+
+/// Subcommands other than checking accounts
+#[derive(Subcommand)]
+pub(crate) enum Command {
+    /// Convert a sorted "pwned" password hashes file into a compact binary index
+    ///
+    /// The index holds each hash as 16 raw bytes followed by its count, so it is about half the
+    /// size of the text file and can be searched without parsing any text. By default, the index
+    /// is written next to the text file, where it is picked up automatically.
+    ///
+    /// With `--offsets`, a small prefix offset sidecar is built instead, leaving the text file as
+    /// the corpus.
+    Index(IndexArgs),
+
+    /// Look up hashes in a sorted "pwned" password hashes file
+    ///
+    /// Each hash is found with a binary search of the file, without any accounts file, and printed
+    /// with its count as "<hash>:<count>"; a count of 0 means it has not been seen in breaches.
+    Lookup(LookupArgs),
+
+    /// Serve a sorted "pwned" password hashes file as a local copy of the Pwned Passwords range API
+    ///
+    /// `GET /range/<PREFIX>?mode=ntlm` answers with each hash in the file starting with the given
+    /// 5-character prefix, as "<SUFFIX>:<count>" lines, like the HaveIBeenPwned API; with an
+    /// "Add-Padding: true" header, the response is padded with random suffixes with a count of 0.
+    /// Tools that speak the range API can then check passwords without reaching the internet.
+    ///
+    /// Unpadded responses carry an ETag, and are answered with "304 Not Modified" if it matches
+    /// the request's "If-None-Match" header, so that `fetch` can keep a copy up to date.
+    Serve(ServeArgs),
+
+    /// Download or refresh a local copy of a Pwned Passwords range API
+    ///
+    /// Each of the 16^5 5-character hash prefixes is requested from
+    /// "<BASE_URL>/range/<PREFIX>?mode=ntlm" and kept in <DIR> as "<PREFIX>.txt", the layout the
+    /// PwnedPasswordsDownloader writes, which can be given as a "pwned" password hashes directory.
+    /// Each response's ETag is kept too, so that running this again only downloads the prefixes
+    /// that have changed since. If a run is interrupted, running it again carries on where it
+    /// stopped.
+    Fetch(FetchArgs),
+
+    /// Check that a "pwned" password hashes file is fit to be searched
+    ///
+    /// The whole file is read, and every line is checked to be "<hash>:<count>", with a
+    /// 32-character upper case hexadecimal hash and a whole number count, and every hash to come
+    /// after the one before it, with no duplicates. A file in any other order, such as the one
+    /// ordered by prevalence rather than by hash, gives wrong answers. The first problems are
+    /// reported with their byte offsets and line numbers, and the exit status is 1 if there are
+    /// any.
+    ///
+    /// Every search does a much cheaper check of a few lines spread across the file, and refuses
+    /// a file that fails it.
+    Verify(VerifyArgs),
+}
+
+/// Arguments of the `index` subcommand
+#[derive(clap::Args)]
+pub(crate) struct IndexArgs {
+    /// Sorted "pwned" password hashes file, with lines in the format "<hash>:<count>"
+    ///
+    /// A binary index may be built from a gzip, zstd or xz compressed file, but a prefix offset
+    /// sidecar may not.
+    pub(crate) passwords: String,
+
+    /// Binary index file to write [default: <PASSWORDS>.bin]
+    pub(crate) output: Option<String>,
+
+    /// Build a prefix offset sidecar rather than a binary index
+    ///
+    /// The sidecar, "<PASSWORDS>.offsets", records where each 5-character hash prefix starts in
+    /// the text file, so that searches can seek straight to it; it is only about 8 MB. It is
+    /// picked up automatically, and rebuilt automatically whenever the size or modification time
+    /// of the text file no longer matches.
+    #[arg(long, conflicts_with = "output")]
+    pub(crate) offsets: bool,
+}
+
+/// Arguments of the `lookup` subcommand
+#[derive(clap::Args)]
+pub(crate) struct LookupArgs {
+    /// Sorted, uncompressed "pwned" password hashes file, with lines in the format
+    /// "<hash>:<count>"
+    pub(crate) passwords: String,
+
+    /// Hashes to look up, hashed in the same format as in the <PASSWORDS> file
+    #[arg(required = true)]
+    pub(crate) hashes: Vec<String>,
+}
+
+/// Arguments of the `serve` subcommand
+#[derive(clap::Args)]
+pub(crate) struct ServeArgs {
+    /// Sorted, uncompressed "pwned" password hashes file, with lines in the format
+    /// "<hash>:<count>"
+    pub(crate) passwords: String,
+
+    /// Address and port to listen on
+    #[arg(long, default_value_t = String::from("127.0.0.1:8080"))]
+    pub(crate) listen: String,
+
+    /// Number of threads answering requests
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+    pub(crate) threads: u16,
+
+    /// Number of recently requested prefixes whose responses are kept in memory
+    #[arg(long, default_value_t = 4096)]
+    pub(crate) cache_size: usize,
+}
+
+/// Arguments of the `fetch` subcommand
+#[derive(clap::Args)]
+pub(crate) struct FetchArgs {
+    /// Directory to keep the per-prefix files in
+    pub(crate) dir: String,
+
+    /// Base URL of the range API, such as an internal mirror or a test server
+    #[arg(long, default_value_t = String::from("https://api.pwnedpasswords.com"))]
+    pub(crate) base_url: String,
+
+    /// Sorted "pwned" password hashes file to write from the per-prefix files
+    ///
+    /// Once every prefix is fetched, the per-prefix files are joined into a single file with lines
+    /// in the format "<hash>:<count>", in ascending order of hash.
+    #[arg(long)]
+    pub(crate) output: Option<String>,
+
+    /// Largest number of requests in flight at once
+    #[arg(long, default_value_t = 16, value_parser = clap::value_parser!(u16).range(1..))]
+    pub(crate) concurrency: u16,
+
+    /// Number of times a failed request is retried
+    ///
+    /// Connection errors, rate limiting and server errors are retried after a growing delay, or
+    /// as long as the server asks for, but never more than a minute or so; and at most 10 times.
+    #[arg(long, default_value_t = 3)]
+    pub(crate) retries: u32,
+}
+
+/// Arguments of the `verify` subcommand
+#[derive(clap::Args)]
+pub(crate) struct VerifyArgs {
+    /// "Pwned" password hashes file, with lines in the format "<hash>:<count>"
+    ///
+    /// The file may be compressed with gzip, zstd or xz, in which case byte offsets are of its
+    /// decompressed contents. "-" reads the file from standard input.
+    pub(crate) passwords: String,
+
+    /// Largest number of problems to report
+    #[arg(long, default_value_t = 10)]
+    pub(crate) max_problems: usize,
 }
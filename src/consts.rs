@@ -7,6 +7,7 @@ pub(crate) const UAC_LOCKOUT: u32 = 16;
 pub(crate) const UAC_PASSWD_NOTREQD: u32 = 32;
 pub(crate) const UAC_PASSWD_CANT_CHANGE: u32 = 64;
 pub(crate) const UAC_ENCRYPTED_TEXT_PASSWORD_ALLOWED: u32 = 128;
+pub(crate) const UAC_TEMP_DUPLICATE_ACCOUNT: u32 = 256;
 pub(crate) const UAC_NORMAL_ACCOUNT: u32 = 512;
 pub(crate) const UAC_INTERDOMAIN_TRUST_ACCOUNT: u32 = 2048;
 pub(crate) const UAC_WORKSTATION_TRUST_ACCOUNT: u32 = 4096;
@@ -22,3 +23,9 @@ pub(crate) const UAC_PASSWORD_EXPIRED: u32 = 8388608;
 pub(crate) const UAC_TRUSTED_TO_AUTHENTICATE_FOR_DELEGATION: u32 = 16777216;
 pub(crate) const UAC_NO_AUTH_DATA_REQUIRED: u32 = 33554432;
 pub(crate) const UAC_PARTIAL_SECRETS_ACCOUNT: u32 = 67108864;
+
+// `sAMAccountType` values for accounts that can hold a password, from
+// https://learn.microsoft.com/en-us/windows/win32/adschema/a-samaccounttype
+pub(crate) const SAM_NORMAL_USER_ACCOUNT: u32 = 0x30000000;
+pub(crate) const SAM_MACHINE_ACCOUNT: u32 = 0x30000001;
+pub(crate) const SAM_TRUST_ACCOUNT: u32 = 0x30000002;
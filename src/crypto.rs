@@ -0,0 +1,205 @@
+//! This module holds the cryptographic primitives needed to decrypt password hashes stored by
+//! Windows
+//!
+//! Windows wraps its stored hashes in layers of RC4, AES and DES; RC4 is simple enough to
+//! implement here, while the block ciphers, MD4 and MD5 come from the RustCrypto crates.
+
+use aes::cipher::{generic_array::GenericArray, BlockDecrypt, KeyInit};
+use aes::Aes128;
+use des::Des;
+use md4::Md4;
+use md5::{Digest, Md5};
+
+/// Compute the MD5 digest of the concatenation of the given parts
+pub(crate) fn md5(parts: &[&[u8]]) -> [u8; 16] {
+    let mut hasher = Md5::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// Compute the NT hash of a password: the MD4 digest of its UTF-16LE encoding
+pub(crate) fn nt_hash(password: &str) -> [u8; 16] {
+    let mut hasher = Md4::new();
+    for unit in password.encode_utf16() {
+        hasher.update(unit.to_le_bytes());
+    }
+    hasher.finalize().into()
+}
+
+/// Encrypt or decrypt data with RC4
+pub(crate) fn rc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut state: [u8; 256] = std::array::from_fn(|i| i as u8);
+    let mut j: u8 = 0;
+    for i in 0..256 {
+        j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+        state.swap(i, j as usize);
+    }
+
+    let (mut i, mut j) = (0u8, 0u8);
+    data.iter()
+        .map(|byte| {
+            i = i.wrapping_add(1);
+            j = j.wrapping_add(state[i as usize]);
+            state.swap(i as usize, j as usize);
+            let k = state[state[i as usize].wrapping_add(state[j as usize]) as usize];
+            byte ^ k
+        })
+        .collect()
+}
+
+/// Decrypt data with AES-128 in CBC mode
+///
+/// A trailing partial block is padded with zeroes before decryption, as Windows does not always
+/// store a whole number of blocks.
+pub(crate) fn aes_cbc_decrypt(key: &[u8; 16], iv: &[u8], data: &[u8]) -> Vec<u8> {
+    let cipher = Aes128::new(GenericArray::from_slice(key));
+    let mut previous = [0; 16];
+    previous.copy_from_slice(&iv[..16]);
+
+    let mut plain = Vec::with_capacity(data.len() + 16);
+    for chunk in data.chunks(16) {
+        let mut block = [0; 16];
+        block[..chunk.len()].copy_from_slice(chunk);
+        let mut decrypted = GenericArray::clone_from_slice(&block);
+        cipher.decrypt_block(&mut decrypted);
+        plain.extend(decrypted.iter().zip(previous.iter()).map(|(a, b)| a ^ b));
+        previous = block;
+    }
+    plain
+}
+
+/// Expand a 7-byte key into an 8-byte DES key, by spreading it over the top 7 bits of each byte
+///
+/// https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-samr/ebdb15df-8d0d-4347-9d62-082e6eccac40
+fn expand_des_key(key: &[u8; 7]) -> [u8; 8] {
+    [
+        key[0] >> 1,
+        ((key[0] & 0x01) << 6) | (key[1] >> 2),
+        ((key[1] & 0x03) << 5) | (key[2] >> 3),
+        ((key[2] & 0x07) << 4) | (key[3] >> 4),
+        ((key[3] & 0x0f) << 3) | (key[4] >> 5),
+        ((key[4] & 0x1f) << 2) | (key[5] >> 6),
+        ((key[5] & 0x3f) << 1) | (key[6] >> 7),
+        key[6] & 0x7f,
+    ]
+    .map(|b| b << 1)
+}
+
+/// Remove the final DES layer of a stored hash, which is keyed on the account's RID
+///
+/// https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-samr/b1b0094f-2546-431f-b06d-582158a9f2bb
+pub(crate) fn des_rid_decrypt(data: &[u8], rid: u32) -> [u8; 16] {
+    let i = rid.to_le_bytes();
+    let key1 = expand_des_key(&[i[0], i[1], i[2], i[3], i[0], i[1], i[2]]);
+    let key2 = expand_des_key(&[i[3], i[0], i[1], i[2], i[3], i[0], i[1]]);
+
+    let mut hash = [0; 16];
+    for (half, key) in [key1, key2].iter().enumerate() {
+        let cipher = Des::new(GenericArray::from_slice(key));
+        let mut block = GenericArray::clone_from_slice(&data[half * 8..half * 8 + 8]);
+        cipher.decrypt_block(&mut block);
+        hash[half * 8..half * 8 + 8].copy_from_slice(&block);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use des::cipher::BlockEncrypt;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn md5_digests_the_concatenated_parts() {
+        // RFC 1321
+        assert_eq!(
+            md5(&[b"abc"]).to_vec(),
+            hex("900150983cd24fb0d6963f7d28e17f72")
+        );
+        assert_eq!(md5(&[b"a", b"", b"bc"]), md5(&[b"abc"]));
+    }
+
+    #[test]
+    fn nt_hash_matches_known_answers() {
+        assert_eq!(
+            nt_hash("password").to_vec(),
+            hex("8846f7eaee8fb117ad06bdd830b7586c")
+        );
+        assert_eq!(
+            nt_hash("").to_vec(),
+            hex("31d6cfe0d16ae931b73c59d7e0c089c0")
+        );
+        // Hashed as UTF-16, not as bytes
+        assert_ne!(nt_hash("pässword"), nt_hash("pÃ¤ssword"));
+    }
+
+    #[test]
+    fn rc4_matches_known_answers() {
+        assert_eq!(rc4(b"Key", b"Plaintext"), hex("bbf316e8d940af0ad3"));
+        assert_eq!(rc4(b"Wiki", b"pedia"), hex("1021bf0420"));
+        assert_eq!(rc4(b"Key", &rc4(b"Key", b"Plaintext")), b"Plaintext");
+    }
+
+    #[test]
+    fn aes_cbc_decrypt_matches_known_answer() {
+        // NIST SP 800-38A, F.2.2
+        let key: [u8; 16] = hex("2b7e151628aed2a6abf7158809cf4f3c").try_into().unwrap();
+        let iv = hex("000102030405060708090a0b0c0d0e0f");
+        let encrypted = hex("7649abac8119b246cee98e9b12e9197d5086cb9b507219ee95db113a917678b2");
+        assert_eq!(
+            aes_cbc_decrypt(&key, &iv, &encrypted),
+            hex("6bc1bee22e409f96e93d7e117393172aae2d8a571e03ac9c9eb76fac45af8e51")
+        );
+    }
+
+    #[test]
+    fn aes_cbc_decrypt_pads_a_partial_block() {
+        let key = [7; 16];
+        let iv = [0; 16];
+        let encrypted = hex("7649abac8119b246cee98e9b12e9197d5086cb9b");
+        let padded = hex("7649abac8119b246cee98e9b12e9197d5086cb9b000000000000000000000000");
+        assert_eq!(
+            aes_cbc_decrypt(&key, &iv, &encrypted),
+            aes_cbc_decrypt(&key, &iv, &padded)
+        );
+    }
+
+    #[test]
+    fn expand_des_key_matches_published_lm_hashes() {
+        // The LM hash is made with the same key expansion as the RID layer: each 7-character half
+        // of the password, expanded into a DES key, encrypts "KGS!@#$%"
+        let lm_half = |half: &[u8; 7]| {
+            let cipher = Des::new(GenericArray::from_slice(&expand_des_key(half)));
+            let mut block = GenericArray::clone_from_slice(b"KGS!@#$%");
+            cipher.encrypt_block(&mut block);
+            block.to_vec()
+        };
+        assert_eq!(
+            [lm_half(b"PASSWOR"), lm_half(b"D\0\0\0\0\0\0")].concat(),
+            hex("e52cac67419a9a224a3b108f3fa6cb6d")
+        );
+        assert_eq!(lm_half(&[0; 7]), hex("aad3b435b51404ee"));
+    }
+
+    #[test]
+    fn des_rid_decrypt_removes_the_rid_layer() {
+        // Encrypted by tests/fixtures/generate.py
+        let password = hex("8846f7eaee8fb117ad06bdd830b7586c");
+        assert_eq!(
+            des_rid_decrypt(&hex("d07f6bde60b7d91c116918f75dc2778b"), 500).to_vec(),
+            password
+        );
+        assert_eq!(
+            des_rid_decrypt(&hex("4b960c0591b61c3f623acf4a50a84875"), 1104).to_vec(),
+            password
+        );
+    }
+}
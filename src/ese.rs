@@ -0,0 +1,546 @@
+//! This module is a minimal, read-only reader for Extensible Storage Engine (ESE) databases
+//!
+//! It supports just enough of the format to read `NTDS.dit`: the catalog, and the rows of a
+//! table walked in key order, with fixed, variable and tagged columns. Long values stored outside
+//! of their row, compressed values and multi-valued columns are not supported; such values are
+//! reported as missing. The format is described at
+//! https://github.com/libyal/libesedb/blob/main/documentation/Extensible%20Storage%20Engine%20(ESE)%20Database%20File%20(EDB)%20format.asciidoc
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Signature found at offset 4 of the database header
+const SIGNATURE: [u8; 4] = [0xef, 0xcd, 0xab, 0x89];
+
+/// Page number of the root of the catalog, `MSysObjects`
+const CATALOG_PAGE: u32 = 4;
+
+/// Page flag set on leaf pages of a B+ tree
+const PAGE_LEAF: u32 = 0x02;
+
+/// Tag flag set on deleted entries that have not yet been cleaned up
+const TAG_DEFUNCT: u8 = 0x02;
+/// Tag flag set on entries whose key shares a common prefix with the page's key
+const TAG_COMMON: u8 = 0x04;
+
+/// Catalog entry types we care about
+const CATALOG_TABLE: u16 = 1;
+const CATALOG_COLUMN: u16 = 2;
+
+/// Tagged value flags for values we cannot read in place
+const TAGGED_COMPRESSED: u8 = 0x02;
+const TAGGED_STORED: u8 = 0x04;
+const TAGGED_MULTI_VALUE: u8 = 0x08;
+
+/// Build an error for a malformed database
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Malformed ESE database: {reason}"),
+    )
+}
+
+fn u16_at(data: &[u8], offset: usize) -> io::Result<u16> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| invalid("offset out of bounds"))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> io::Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| invalid("offset out of bounds"))
+}
+
+/// An open ESE database
+pub(crate) struct Database {
+    file: File,
+    page_size: usize,
+    /// Number of pages in the file, after the database header and its shadow copy
+    page_count: u64,
+    /// Whether the database uses the page layout introduced for pages larger than 8 KiB
+    large_pages: bool,
+    tables: Vec<Table>,
+}
+
+/// A table, as described by the catalog
+pub(crate) struct Table {
+    pub(crate) name: String,
+    /// Object ID, shared by the catalog entries of the table's columns
+    object_id: u32,
+    /// Root page of the table's B+ tree
+    root_page: u32,
+    columns: Vec<Column>,
+}
+
+/// A column of a table, as described by the catalog
+#[derive(Clone)]
+pub(crate) struct Column {
+    pub(crate) name: String,
+    id: u32,
+    /// Size of the column's data, for fixed columns
+    size: u32,
+    /// Offset of the column's data within a row, for fixed columns
+    offset: usize,
+}
+
+/// A single row of a table
+pub(crate) struct Row {
+    data: Vec<u8>,
+    large_pages: bool,
+}
+
+/// A page of the database, with its header decoded
+struct Page {
+    data: Vec<u8>,
+    header_len: usize,
+    next: u32,
+    tag_count: usize,
+    flags: u32,
+    large_pages: bool,
+}
+
+impl Page {
+    /// Get the flags and data of the tag (or entry) with the given index
+    fn tag(&self, index: usize) -> io::Result<(u8, Vec<u8>)> {
+        let tag_offset = self.data.len() - 4 * (index + 1);
+        let size = u16_at(&self.data, tag_offset)?;
+        let offset = u16_at(&self.data, tag_offset + 2)?;
+
+        if self.large_pages {
+            // The flags are held in the top bits of the entry's first 2 bytes
+            let start = self.header_len + (offset & 0x7fff) as usize;
+            let mut data = self
+                .data
+                .get(start..start + (size & 0x7fff) as usize)
+                .ok_or_else(|| invalid("tag out of bounds"))?
+                .to_vec();
+            let flags = data.get(1).map_or(0, |b| b >> 5);
+            if let Some(b) = data.get_mut(1) {
+                *b &= 0x1f;
+            }
+            Ok((flags, data))
+        } else {
+            let start = self.header_len + (offset & 0x1fff) as usize;
+            let data = self
+                .data
+                .get(start..start + (size & 0x1fff) as usize)
+                .ok_or_else(|| invalid("tag out of bounds"))?
+                .to_vec();
+            Ok(((offset >> 13) as u8, data))
+        }
+    }
+
+    /// Strip the key from a B+ tree entry, leaving the entry's data
+    fn entry_data(flags: u8, entry: &[u8]) -> io::Result<&[u8]> {
+        let mut offset = 0;
+        if flags & TAG_COMMON != 0 {
+            offset += 2;
+        }
+        let key_len = u16_at(entry, offset)? as usize;
+        entry
+            .get(offset + 2 + key_len..)
+            .ok_or_else(|| invalid("entry key out of bounds"))
+    }
+}
+
+impl Database {
+    /// Open a database, and read its catalog
+    pub(crate) fn open(path: &str) -> io::Result<Database> {
+        let mut file = File::open(path)?;
+        let mut header = [0; 240];
+        file.read_exact(&mut header)?;
+        if header[4..8] != SIGNATURE {
+            return Err(invalid("missing signature"));
+        }
+        let version = u32_at(&header, 8)?;
+        let revision = u32_at(&header, 232)?;
+        let page_size = u32_at(&header, 236)? as usize;
+        if !page_size.is_power_of_two() || !(2048..=32768).contains(&page_size) {
+            return Err(invalid("unsupported page size"));
+        }
+
+        let page_count = (file.metadata()?.len() / page_size as u64).saturating_sub(2);
+        let mut database = Database {
+            file,
+            page_size,
+            page_count,
+            large_pages: version == 0x620 && revision >= 0x11 && page_size > 8192,
+            tables: Vec::new(),
+        };
+        database.read_catalog()?;
+        Ok(database)
+    }
+
+    /// Check if the given bytes look like the start of an ESE database
+    pub(crate) fn sniff(start: &[u8]) -> bool {
+        start.get(4..8) == Some(&SIGNATURE)
+    }
+
+    /// Read the page with the given number
+    fn page(&mut self, number: u32) -> io::Result<Page> {
+        // Pages are numbered from 1
+        if number == 0 || number as u64 > self.page_count {
+            return Err(invalid(&format!("page {number} is out of range")));
+        }
+        // The database header and its shadow copy precede the first page
+        let offset = (number as u64 + 1) * self.page_size as u64;
+        self.file.seek(SeekFrom::Start(offset))?;
+        let mut data = vec![0; self.page_size];
+        self.file.read_exact(&mut data)?;
+
+        let header_len = if self.large_pages { 80 } else { 40 };
+        let tag_count = u16_at(&data, 34)? as usize;
+        if header_len + tag_count * 4 > data.len() {
+            return Err(invalid("too many tags in page"));
+        }
+
+        Ok(Page {
+            header_len,
+            next: u32_at(&data, 20)?,
+            tag_count,
+            flags: u32_at(&data, 36)?,
+            large_pages: self.large_pages,
+            data,
+        })
+    }
+
+    /// Read every row of the B+ tree rooted at the given page, in key order
+    ///
+    /// The tree is descended along its leftmost branch to the first leaf, and then the leaves are
+    /// walked in order through their sibling links. A corrupt database may link back to a page
+    /// already read, which is refused rather than followed forever.
+    fn walk<F>(&mut self, root: u32, mut visit: F) -> io::Result<()>
+    where
+        F: FnMut(&[u8]) -> io::Result<()>,
+    {
+        let mut visited = HashSet::new();
+        let mut read = |database: &mut Database, number: u32| {
+            if !visited.insert(number) {
+                return Err(invalid(&format!("page {number} is linked to more than once")));
+            }
+            database.page(number)
+        };
+
+        let mut page = read(self, root)?;
+        while page.flags & PAGE_LEAF == 0 {
+            if page.tag_count < 2 {
+                return Ok(());
+            }
+            let (flags, entry) = page.tag(1)?;
+            let child = u32_at(Page::entry_data(flags, &entry)?, 0)?;
+            page = read(self, child)?;
+        }
+
+        loop {
+            // Tag 0 holds the page's common key, rather than an entry
+            for index in 1..page.tag_count {
+                let (flags, entry) = page.tag(index)?;
+                if flags & TAG_DEFUNCT == 0 {
+                    visit(Page::entry_data(flags, &entry)?)?;
+                }
+            }
+            if page.next == 0 {
+                return Ok(());
+            }
+            page = read(self, page.next)?;
+        }
+    }
+
+    /// Read the catalog, to learn the tables and columns in the database
+    fn read_catalog(&mut self) -> io::Result<()> {
+        let large_pages = self.large_pages;
+        let mut tables = Vec::new();
+        let mut columns = Vec::new();
+
+        self.walk(CATALOG_PAGE, |data| {
+            let row = Row {
+                data: data.to_vec(),
+                large_pages,
+            };
+            // The catalog's own fixed columns: owning object ID, entry type and entry ID, followed
+            // by type-specific columns; the entry's name is its first variable column
+            let object_id = u32_at(data, 4)?;
+            let kind = u16_at(data, 8)?;
+            let id = u32_at(data, 10)?;
+            let name = row
+                .variable(128)
+                .map(|name| String::from_utf8_lossy(name).into_owned())
+                .unwrap_or_default();
+            match kind {
+                CATALOG_TABLE => tables.push(Table {
+                    name,
+                    object_id,
+                    root_page: u32_at(data, 14)?,
+                    columns: Vec::new(),
+                }),
+                CATALOG_COLUMN => columns.push((
+                    object_id,
+                    Column {
+                        name,
+                        id,
+                        size: u32_at(data, 18)?,
+                        offset: 0,
+                    },
+                )),
+                _ => {}
+            }
+            Ok(())
+        })?;
+
+        for (object_id, column) in columns {
+            if let Some(table) = tables.iter_mut().find(|table| table.object_id == object_id) {
+                table.columns.push(column);
+            }
+        }
+        for table in &mut tables {
+            // Fixed columns are stored in ID order, after the 4-byte row header
+            table.columns.sort_by_key(|column| column.id);
+            let mut offset = 4;
+            for column in table.columns.iter_mut().filter(|column| column.id <= 127) {
+                column.offset = offset;
+                offset += column.size as usize;
+            }
+        }
+
+        self.tables = tables;
+        Ok(())
+    }
+
+    /// Get the table with the given name
+    pub(crate) fn table(&self, name: &str) -> io::Result<&Table> {
+        self.tables
+            .iter()
+            .find(|table| table.name == name)
+            .ok_or_else(|| invalid(&format!("no table named {name}")))
+    }
+
+    /// Visit every row of the named table, in key order
+    pub(crate) fn rows<F>(&mut self, table: &str, mut visit: F) -> io::Result<()>
+    where
+        F: FnMut(&Row) -> io::Result<()>,
+    {
+        let root = self.table(table)?.root_page;
+        let large_pages = self.large_pages;
+        self.walk(root, |data| {
+            visit(&Row {
+                data: data.to_vec(),
+                large_pages,
+            })
+        })
+    }
+}
+
+impl Table {
+    /// Get the column with the given name
+    pub(crate) fn column(&self, name: &str) -> io::Result<Column> {
+        self.columns
+            .iter()
+            .find(|column| column.name == name)
+            .cloned()
+            .ok_or_else(|| invalid(&format!("table {} has no column {name}", self.name)))
+    }
+}
+
+impl Row {
+    /// Get the value of a column in this row, if it has one we can read
+    pub(crate) fn get(&self, column: &Column) -> Option<&[u8]> {
+        match column.id {
+            0..=127 => self.fixed(column),
+            128..=255 => self.variable(column.id),
+            _ => self.tagged(column.id),
+        }
+    }
+
+    /// Number of variable columns present in this row
+    fn variable_count(&self) -> usize {
+        match self.data.get(1) {
+            Some(&last) if last > 127 => last as usize - 127,
+            _ => 0,
+        }
+    }
+
+    /// Offset of the array of variable column sizes
+    fn variable_offset(&self) -> Option<usize> {
+        u16_at(&self.data, 2).ok().map(usize::from)
+    }
+
+    fn fixed(&self, column: &Column) -> Option<&[u8]> {
+        // Only the fixed columns up to the last one set are present
+        if column.id > *self.data.first()? as u32 {
+            return None;
+        }
+        self.data
+            .get(column.offset..column.offset + column.size as usize)
+    }
+
+    fn variable(&self, id: u32) -> Option<&[u8]> {
+        let index = id as usize - 128;
+        let count = self.variable_count();
+        if index >= count {
+            return None;
+        }
+        let sizes = self.variable_offset()?;
+        // Each entry holds the end of the column's data, with the top bit set if it is empty
+        let end = u16_at(&self.data, sizes + index * 2).ok()?;
+        if end & 0x8000 != 0 {
+            return None;
+        }
+        let start = match index {
+            0 => 0,
+            _ => u16_at(&self.data, sizes + (index - 1) * 2).ok()? & 0x7fff,
+        };
+        let base = sizes + count * 2;
+        self.data
+            .get(base + start as usize..base + (end & 0x7fff) as usize)
+    }
+
+    fn tagged(&self, id: u32) -> Option<&[u8]> {
+        // Tagged columns follow the variable columns' data
+        let sizes = self.variable_offset()?;
+        let count = self.variable_count();
+        let variable_len = match count {
+            0 => 0,
+            _ => (u16_at(&self.data, sizes + (count - 1) * 2).ok()? & 0x7fff) as usize,
+        };
+        let base = sizes + count * 2 + variable_len;
+        let area = self.data.get(base..)?;
+        if area.len() < 4 {
+            return None;
+        }
+
+        // The tagged area starts with an array of (column ID, offset) pairs; the first offset
+        // tells us how long the array is
+        let entries = (u16_at(area, 2).ok()? & 0x3fff) as usize / 4;
+        for entry in 0..entries {
+            if u16_at(area, entry * 4).ok()? as u32 != id {
+                continue;
+            }
+            let raw_offset = u16_at(area, entry * 4 + 2).ok()?;
+            let start = (raw_offset & 0x3fff) as usize;
+            let end = if entry + 1 < entries {
+                (u16_at(area, entry * 4 + 6).ok()? & 0x3fff) as usize
+            } else {
+                area.len()
+            };
+            let value = area.get(start..end)?;
+
+            // Values may be prefixed with a flags byte
+            if self.large_pages || raw_offset & 0x4000 != 0 {
+                let (&flags, value) = value.split_first()?;
+                if flags & (TAGGED_COMPRESSED | TAGGED_STORED | TAGGED_MULTI_VALUE) != 0 {
+                    return None;
+                }
+                return Some(value);
+            }
+            return Some(value);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{fixture, TempDir};
+
+    const PAGE_SIZE: usize = 8192;
+
+    /// Copy the fixture database with a page's link to its next sibling changed
+    fn with_next_link(dir: &TempDir, page: usize, next: u32) -> String {
+        let mut data = std::fs::read(fixture("ntds.dit")).unwrap();
+        let offset = (page + 1) * PAGE_SIZE + 20;
+        data[offset..offset + 4].copy_from_slice(&next.to_le_bytes());
+        dir.write("ntds.dit", data)
+    }
+
+    fn utf16(s: &str) -> Vec<u8> {
+        s.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    #[test]
+    fn reads_the_catalog() {
+        let database = Database::open(&fixture("ntds.dit")).unwrap();
+        let table = database.table("datatable").unwrap();
+        assert_eq!(table.columns.len(), 10);
+        assert_eq!(table.column("DNT_col").unwrap().offset, 4);
+        assert!(table.column("ATTm0").is_err());
+        assert!(database.table("sd_table").is_err());
+    }
+
+    #[test]
+    fn walks_rows_in_key_order_across_leaves() {
+        let mut database = Database::open(&fixture("ntds.dit")).unwrap();
+        let table = database.table("datatable").unwrap();
+        let dnt = table.column("DNT_col").unwrap();
+        let rdn = table.column("ATTm589825").unwrap();
+        let name = table.column("ATTm590045").unwrap();
+        let sid = table.column("ATTr589970").unwrap();
+
+        let mut rows = Vec::new();
+        database
+            .rows("datatable", |row| {
+                rows.push((
+                    u32::from_le_bytes(row.get(&dnt).unwrap().try_into().unwrap()),
+                    row.get(&rdn).map(<[u8]>::to_vec),
+                    row.get(&name).map(<[u8]>::to_vec),
+                    row.get(&sid).map(<[u8]>::len),
+                ));
+                Ok(())
+            })
+            .unwrap();
+
+        // Row 7 is deleted, and rows 8 to 10 are in the second leaf
+        let dnts: Vec<_> = rows.iter().map(|row| row.0).collect();
+        assert_eq!(dnts, [3, 4, 5, 6, 8, 9, 10]);
+        assert_eq!(rows[0].1, Some(utf16("example")));
+        assert_eq!(rows[0].2, None);
+        assert_eq!(rows[5].1, Some(utf16("alice")));
+        assert_eq!(rows[5].2, Some(utf16("alice")));
+        assert_eq!(rows[5].3, Some(28));
+    }
+
+    #[test]
+    fn refuses_a_cycle_of_pages() {
+        let dir = TempDir::new();
+        let path = with_next_link(&dir, 7, 6);
+        let mut database = Database::open(&path).unwrap();
+        let err = database.rows("datatable", |_| Ok(())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err
+            .to_string()
+            .contains("page 6 is linked to more than once"));
+    }
+
+    #[test]
+    fn refuses_a_link_past_the_end_of_the_file() {
+        let dir = TempDir::new();
+        let path = with_next_link(&dir, 6, 99);
+        let mut database = Database::open(&path).unwrap();
+        let err = database.rows("datatable", |_| Ok(())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("page 99 is out of range"));
+    }
+
+    #[test]
+    fn refuses_a_catalog_linking_to_itself() {
+        let dir = TempDir::new();
+        let path = with_next_link(&dir, CATALOG_PAGE as usize, CATALOG_PAGE);
+        assert!(Database::open(&path).is_err());
+    }
+
+    #[test]
+    fn refuses_files_that_are_not_databases() {
+        let dir = TempDir::new();
+        let mut data = std::fs::read(fixture("ntds.dit")).unwrap();
+        data[236..240].copy_from_slice(&1000u32.to_le_bytes());
+        let path = dir.write("ntds.dit", &data);
+        assert!(Database::open(&path).is_err());
+
+        let path = dir.write("empty.dit", vec![0; PAGE_SIZE]);
+        assert!(Database::open(&path).is_err());
+        assert!(Database::sniff(&data[..8]));
+        assert!(!Database::sniff(b"regf"));
+    }
+}
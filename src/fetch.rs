@@ -0,0 +1,376 @@
+//! This module downloads a local copy of a Pwned Passwords corpus from a range API
+//!
+//! Every one of the 16^5 5-character prefixes is requested from
+//! `{base_url}/range/{prefix}?mode=ntlm` and kept as `<PREFIX>.txt` in a directory, in the same
+//! layout the PwnedPasswordsDownloader writes. Each response's ETag is recorded, so that a later
+//! run asks for each prefix with `If-None-Match` and only downloads the ones that changed. The
+//! prefixes finished by a run are journaled as it goes, so that a run that is interrupted can carry
+//! on where it stopped.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::search::range_api::{agent, call, run_concurrently, write_whole};
+
+/// Number of 5-character hash prefixes
+const PREFIXES: usize = 1 << 20;
+
+/// Number of prefixes between progress reports
+const REPORT_EVERY: usize = 1 << 16;
+
+/// Journal of each prefix's ETag, as `<PREFIX> <ETag>` lines; the last line for a prefix wins
+const ETAGS_FILE: &str = ".etags";
+
+/// Journal of the prefixes finished by an unfinished run, one per line
+const PROGRESS_FILE: &str = ".progress";
+
+/// Options for fetching a corpus from a range API
+#[derive(Clone, Debug)]
+pub(crate) struct FetchOptions {
+    /// Largest number of requests in flight at once
+    pub(crate) concurrency: usize,
+    /// Number of times a failed request is retried
+    pub(crate) retries: u32,
+}
+
+/// What a fetch did
+#[derive(Debug, Default)]
+pub(crate) struct FetchSummary {
+    /// Prefixes finished by an interrupted run, and not requested again
+    pub(crate) resumed: usize,
+    /// Prefixes downloaded
+    pub(crate) downloaded: usize,
+    /// Prefixes the server said had not changed
+    pub(crate) unchanged: usize,
+}
+
+/// Format a prefix number as its 5 hexadecimal characters
+fn prefix(i: usize) -> String {
+    format!("{i:05X}")
+}
+
+/// Read a journal of `<PREFIX>[ <value>]` lines, keeping the last value for each prefix
+fn read_journal(path: &Path) -> io::Result<HashMap<usize, String>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut entries = HashMap::new();
+    for line in contents.lines() {
+        let (prefix, value) = line.split_once(' ').unwrap_or((line, ""));
+        // A line cut short by an interruption is ignored, and its prefix fetched again
+        match usize::from_str_radix(prefix, 16) {
+            Ok(i) if prefix.len() == 5 => {
+                entries.insert(i, value.to_string());
+            }
+            _ => continue,
+        }
+    }
+    Ok(entries)
+}
+
+/// Download every prefix from a range API into a directory
+///
+/// Prefixes already in the directory are only downloaded again if their ETag has changed. If a
+/// previous run was interrupted, the prefixes it finished are skipped.
+pub(crate) fn fetch(base_url: &str, dir: &str, options: &FetchOptions) -> io::Result<FetchSummary> {
+    fetch_prefixes(base_url, dir, options, PREFIXES)
+}
+
+/// Download the first `prefixes` prefixes from a range API into a directory
+fn fetch_prefixes(
+    base_url: &str,
+    dir: &str,
+    options: &FetchOptions,
+    prefixes: usize,
+) -> io::Result<FetchSummary> {
+    let base_url = base_url.trim_end_matches('/');
+    let dir = Path::new(dir);
+    fs::create_dir_all(dir)?;
+
+    let etags_path = dir.join(ETAGS_FILE);
+    let progress_path = dir.join(PROGRESS_FILE);
+    let mut etags = read_journal(&etags_path)?;
+    let finished = read_journal(&progress_path)?;
+    let mut summary = FetchSummary {
+        resumed: finished.len(),
+        ..FetchSummary::default()
+    };
+    if summary.resumed > 0 {
+        println!(
+            "Resuming an interrupted fetch, with {} of {prefixes} prefixes already fetched",
+            summary.resumed
+        );
+    }
+
+    let append = |path: &Path| OpenOptions::new().create(true).append(true).open(path);
+    let journals = Mutex::new((append(&etags_path)?, append(&progress_path)?));
+
+    let agent = agent();
+
+    // Fetch one prefix, returning whether it was downloaded rather than unchanged
+    let fetch_prefix = |i: usize| -> io::Result<bool> {
+        let prefix = prefix(i);
+        let path = dir.join(format!("{prefix}.txt"));
+        let mut request = agent.get(&format!("{base_url}/range/{prefix}?mode=ntlm"));
+        let known_etag = etags
+            .get(&i)
+            .filter(|etag| !etag.is_empty() && path.exists());
+        if let Some(etag) = known_etag {
+            request = request.set("If-None-Match", etag);
+        }
+        let response = call(&request, options.retries)
+            .map_err(|err| io::Error::other(format!("Range {prefix}: {err}")))?;
+
+        if response.status() == 304 {
+            // Without a copy of the prefix, there is nothing to keep
+            if known_etag.is_none() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Range {prefix}: not modified, but it was never downloaded"),
+                ));
+            }
+            journals
+                .lock()
+                .unwrap()
+                .1
+                .write_all(format!("{prefix}\n").as_bytes())?;
+            return Ok(false);
+        }
+        let etag = response.header("ETag").unwrap_or_default().to_string();
+        write_whole(&path, &response.into_string()?)?;
+
+        let mut journals = journals.lock().unwrap();
+        journals
+            .0
+            .write_all(format!("{prefix} {etag}\n").as_bytes())?;
+        journals.1.write_all(format!("{prefix}\n").as_bytes())?;
+        Ok(true)
+    };
+
+    // Request the prefixes from a few threads at once, skipping those finished by an interrupted
+    // run
+    let done = AtomicUsize::new(summary.resumed);
+    let fetched = run_concurrently(prefixes, options.concurrency, |i| {
+        if finished.contains_key(&i) {
+            return Ok(None);
+        }
+        let downloaded = fetch_prefix(i)?;
+        let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+        if done.is_multiple_of(REPORT_EVERY) {
+            println!("Fetched {done} of {prefixes} prefixes");
+        }
+        Ok(Some(downloaded))
+    })?;
+    summary.downloaded = fetched.iter().filter(|&&prefix| prefix == Some(true)).count();
+    summary.unchanged = fetched.iter().filter(|&&prefix| prefix == Some(false)).count();
+    drop(journals);
+
+    // Every prefix is fetched, so compact the ETag journal and start afresh next time
+    etags.extend(read_journal(&etags_path)?);
+    let partial_path = dir.join(format!("{ETAGS_FILE}.partial"));
+    let mut writer = BufWriter::new(File::create(&partial_path)?);
+    for i in 0..prefixes {
+        match etags.get(&i) {
+            Some(etag) if !etag.is_empty() => writeln!(writer, "{} {etag}", prefix(i))?,
+            _ => {}
+        }
+    }
+    writer.into_inner()?.sync_all()?;
+    fs::rename(&partial_path, &etags_path)?;
+    fs::remove_file(&progress_path)?;
+
+    Ok(summary)
+}
+
+/// Join a directory of per-prefix files into a single sorted `<hash>:<count>` file
+///
+/// Returns the number of hashes written. The file is written under a temporary name and only
+/// renamed into place once complete, so that an interrupted join never leaves a partial corpus.
+pub(crate) fn join(dir: &str, output: &str) -> io::Result<usize> {
+    join_prefixes(dir, output, PREFIXES)
+}
+
+/// Join the first `prefixes` per-prefix files of a directory into a single sorted file
+fn join_prefixes(dir: &str, output: &str, prefixes: usize) -> io::Result<usize> {
+    let dir = Path::new(dir);
+    let partial_path = format!("{output}.partial");
+    let mut writer = BufWriter::new(File::create(&partial_path)?);
+
+    let mut hashes = 0;
+    for i in 0..prefixes {
+        let prefix = prefix(i);
+        let contents = fs::read_to_string(dir.join(format!("{prefix}.txt")))
+            .map_err(|err| io::Error::new(err.kind(), format!("Prefix {prefix}: {err}")))?;
+        let mut lines = Vec::new();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let count = line
+                .split_once(':')
+                .and_then(|(_, count)| count.parse::<usize>().ok());
+            match count {
+                // Padding lines have a count of 0
+                Some(0) => {}
+                Some(_) => lines.push(line),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Prefix {prefix}: malformed line \"{line}\""),
+                    ))
+                }
+            }
+        }
+        lines.sort_unstable();
+        for line in lines {
+            writeln!(writer, "{prefix}{line}")?;
+            hashes += 1;
+        }
+    }
+
+    writer.into_inner()?.sync_all()?;
+    fs::rename(&partial_path, output)?;
+    Ok(hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    use crate::testing::{Served, TempDir};
+
+    /// Number of prefixes fetched by the tests
+    const TEST_PREFIXES: usize = 16;
+
+    const OPTIONS: FetchOptions = FetchOptions {
+        concurrency: 4,
+        retries: 0,
+    };
+
+    /// A sorted corpus with a couple of hashes in every test prefix but the last, and a third in
+    /// each prefix listed
+    fn corpus(extra: &[usize]) -> String {
+        let mut lines = String::new();
+        for i in 0..TEST_PREFIXES - 1 {
+            lines.push_str(&format!(
+                "{}1111111111111111111111111111:{}\n",
+                prefix(i),
+                i + 1
+            ));
+            if extra.contains(&i) {
+                lines.push_str(&format!("{}5555555555555555555555555555:7\n", prefix(i)));
+            }
+            lines.push_str(&format!("{}AAAAAAAAAAAAAAAAAAAAAAAAAAA:2\n", prefix(i)));
+        }
+        lines
+    }
+
+    #[test]
+    fn fetches_refreshes_and_joins_a_corpus() {
+        let dir = TempDir::new();
+        let copy = dir.join("copy");
+        let served = Served::start(&dir.write("pwned.txt", corpus(&[])));
+
+        let summary = fetch_prefixes(&served.base_url(), &copy, &OPTIONS, TEST_PREFIXES).unwrap();
+        assert_eq!(
+            (summary.resumed, summary.downloaded, summary.unchanged),
+            (0, TEST_PREFIXES, 0)
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("copy/00003.txt")).unwrap(),
+            "1111111111111111111111111111:4\r\nAAAAAAAAAAAAAAAAAAAAAAAAAAA:2"
+        );
+        assert_eq!(fs::read_to_string(dir.join("copy/0000F.txt")).unwrap(), "");
+        assert_eq!(
+            read_journal(&Path::new(&copy).join(ETAGS_FILE))
+                .unwrap()
+                .len(),
+            16
+        );
+        assert!(fs::metadata(dir.join("copy/.progress")).is_err());
+        let output = dir.join("joined.txt");
+        assert_eq!(join_prefixes(&copy, &output, TEST_PREFIXES).unwrap(), 30);
+        assert_eq!(fs::read_to_string(&output).unwrap(), corpus(&[]));
+
+        // Only the prefixes that changed are downloaded again, along with any that went missing
+        drop(served);
+        let served = Served::start(&dir.write("pwned.txt", corpus(&[2, 9])));
+        fs::remove_file(dir.join("copy/00004.txt")).unwrap();
+        let summary = fetch_prefixes(&served.base_url(), &copy, &OPTIONS, TEST_PREFIXES).unwrap();
+        assert_eq!(
+            (summary.resumed, summary.downloaded, summary.unchanged),
+            (0, 3, TEST_PREFIXES - 3)
+        );
+        assert_eq!(join_prefixes(&copy, &output, TEST_PREFIXES).unwrap(), 32);
+        assert_eq!(fs::read_to_string(&output).unwrap(), corpus(&[2, 9]));
+    }
+
+    #[test]
+    fn resumes_an_interrupted_fetch() {
+        let dir = TempDir::new();
+        let copy = dir.join("copy");
+        let served = Served::start(&dir.write("pwned.txt", corpus(&[])));
+        fs::create_dir(&copy).unwrap();
+        // The last line was cut short by the interruption
+        dir.write("copy/.progress", "00000\n00001\n0000");
+
+        let summary = fetch_prefixes(&served.base_url(), &copy, &OPTIONS, TEST_PREFIXES).unwrap();
+        assert_eq!(
+            (summary.resumed, summary.downloaded, summary.unchanged),
+            (2, TEST_PREFIXES - 2, 0)
+        );
+        assert!(fs::metadata(dir.join("copy/00000.txt")).is_err());
+        assert!(fs::metadata(dir.join("copy/00002.txt")).is_ok());
+        assert!(fs::metadata(dir.join("copy/.progress")).is_err());
+    }
+
+    #[test]
+    fn refuses_not_modified_for_a_prefix_never_downloaded() {
+        let dir = TempDir::new();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tiny_http::Server::from_listener(listener, None).unwrap();
+        let answer = std::thread::spawn(move || {
+            let request = server.recv().unwrap();
+            let asked = request
+                .headers()
+                .iter()
+                .any(|header| header.field.equiv("If-None-Match"));
+            request.respond(tiny_http::Response::empty(304)).unwrap();
+            asked
+        });
+
+        let options = FetchOptions {
+            concurrency: 1,
+            retries: 0,
+        };
+        let err = fetch_prefixes(&base_url, &dir.join("copy"), &options, 1).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(!answer.join().unwrap());
+        assert!(fs::metadata(dir.join("copy/00000.txt")).is_err());
+    }
+
+    #[test]
+    fn refuses_to_join_malformed_or_missing_prefixes() {
+        let dir = TempDir::new();
+        dir.write("00000.txt", "1111111111111111111111111111:1\r\nbroken\r\n");
+        let output = dir.join("joined.txt");
+        let err = join_prefixes(dir.path(), &output, 1).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        dir.write("00000.txt", "1111111111111111111111111111:1\r\n");
+        let err = join_prefixes(dir.path(), &output, 2).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().starts_with("Prefix 00001:"), "{err}");
+        assert!(fs::metadata(&output).is_err());
+    }
+}
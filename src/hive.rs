@@ -0,0 +1,383 @@
+//! This module is a minimal reader for Windows registry hive files
+//!
+//! Only what is needed to pull secrets out of offline `SYSTEM` and `SAM` hives is supported:
+//! walking keys by name, and reading their values and class names. The format is described at
+//! https://github.com/msuhanov/regf/blob/master/Windows%20registry%20file%20format%20specification.md
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+
+/// Offset of the first hive bin; all cell offsets are relative to this
+const HBIN_START: usize = 0x1000;
+
+/// Key node flag indicating the key name is stored as ASCII rather than UTF-16
+const KEY_COMP_NAME: u16 = 0x20;
+
+/// Value flag indicating the value name is stored as ASCII rather than UTF-16
+const VALUE_COMP_NAME: u16 = 0x01;
+
+/// Build an error for a malformed hive
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Malformed registry hive: {reason}"),
+    )
+}
+
+/// Decode a key or value name, which is either ASCII (really Latin-1) or UTF-16LE
+fn decode_name(name: &[u8], ascii: bool) -> String {
+    if ascii {
+        name.iter().map(|&b| b as char).collect()
+    } else {
+        let units: Vec<_> = name
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    }
+}
+
+/// A registry hive file, read entirely into memory
+pub(crate) struct Hive {
+    data: Vec<u8>,
+}
+
+/// A key within a registry hive
+pub(crate) struct Key<'a> {
+    hive: &'a Hive,
+    /// Offset of the key node's cell data within the hive
+    offset: usize,
+}
+
+impl Hive {
+    /// Check if a file looks like a registry hive from its first bytes
+    pub(crate) fn sniff(start: &[u8]) -> bool {
+        start.starts_with(b"regf")
+    }
+
+    /// Read a hive file
+    pub(crate) fn open(path: &str) -> io::Result<Hive> {
+        let data = fs::read(path)?;
+        if data.len() < HBIN_START || &data[0..4] != b"regf" {
+            return Err(invalid("missing \"regf\" signature"));
+        }
+
+        Ok(Hive { data })
+    }
+
+    /// Get a slice of the hive, failing if it runs past the end
+    fn slice(&self, offset: usize, len: usize) -> io::Result<&[u8]> {
+        self.data
+            .get(offset..offset + len)
+            .ok_or_else(|| invalid("offset out of bounds"))
+    }
+
+    fn u16_at(&self, offset: usize) -> io::Result<u16> {
+        let bytes = self.slice(offset, 2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32_at(&self, offset: usize) -> io::Result<u32> {
+        let bytes = self.slice(offset, 4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Find the data of the cell at the given offset, as an absolute offset into the hive
+    fn cell(&self, offset: u32) -> io::Result<usize> {
+        // Skip the cell's size to get to its data
+        let offset = HBIN_START + offset as usize + 4;
+        if offset >= self.data.len() {
+            return Err(invalid("cell offset out of bounds"));
+        }
+        Ok(offset)
+    }
+
+    /// Get the root key of the hive
+    pub(crate) fn root(&self) -> io::Result<Key<'_>> {
+        let offset = self.cell(self.u32_at(0x24)?)?;
+        Key::new(self, offset)
+    }
+
+    /// Get the key at the given backslash-separated path, relative to the root key
+    pub(crate) fn key(&self, path: &str) -> io::Result<Key<'_>> {
+        let mut key = self.root()?;
+        for name in path.split('\\').filter(|name| !name.is_empty()) {
+            key = key.subkey(name)?;
+        }
+        Ok(key)
+    }
+}
+
+impl<'a> Key<'a> {
+    /// Wrap the key node at the given offset, checking its signature
+    fn new(hive: &'a Hive, offset: usize) -> io::Result<Key<'a>> {
+        if hive.slice(offset, 2)? != b"nk" {
+            return Err(invalid("expected a key node"));
+        }
+        Ok(Key { hive, offset })
+    }
+
+    /// The key's name
+    pub(crate) fn name(&self) -> io::Result<String> {
+        let flags = self.hive.u16_at(self.offset + 2)?;
+        let len = self.hive.u16_at(self.offset + 72)? as usize;
+        let name = self.hive.slice(self.offset + 76, len)?;
+        Ok(decode_name(name, flags & KEY_COMP_NAME != 0))
+    }
+
+    /// The key's class name, as raw bytes
+    pub(crate) fn class(&self) -> io::Result<&'a [u8]> {
+        let offset = self.hive.u32_at(self.offset + 48)?;
+        let len = self.hive.u16_at(self.offset + 74)? as usize;
+        if offset == u32::MAX {
+            return Ok(&[]);
+        }
+        self.hive.slice(self.hive.cell(offset)?, len)
+    }
+
+    /// All subkeys of this key
+    pub(crate) fn subkeys(&self) -> io::Result<Vec<Key<'a>>> {
+        let count = self.hive.u32_at(self.offset + 20)?;
+        let mut subkeys = Vec::with_capacity(count as usize);
+        if count > 0 {
+            let list = self.hive.u32_at(self.offset + 28)?;
+            self.collect_subkeys(list, &mut subkeys, &mut HashSet::new())?;
+        }
+        Ok(subkeys)
+    }
+
+    /// Collect the keys referenced by a subkey list, following index roots into their sublists
+    ///
+    /// The offsets of the lists already followed are kept in `visited`, so that an index root
+    /// linking back to itself or to another list is refused rather than followed forever.
+    fn collect_subkeys(
+        &self,
+        list: u32,
+        subkeys: &mut Vec<Key<'a>>,
+        visited: &mut HashSet<u32>,
+    ) -> io::Result<()> {
+        if !visited.insert(list) {
+            return Err(invalid("subkey list is linked to more than once"));
+        }
+        let offset = self.hive.cell(list)?;
+        let count = self.hive.u16_at(offset + 2)? as usize;
+        match self.hive.slice(offset, 2)? {
+            // Fast leaf and hash leaf: (key offset, name hint or hash) pairs
+            b"lf" | b"lh" => {
+                for i in 0..count {
+                    let key = self.hive.u32_at(offset + 4 + i * 8)?;
+                    subkeys.push(Key::new(self.hive, self.hive.cell(key)?)?);
+                }
+            }
+            // Index leaf: key offsets
+            b"li" => {
+                for i in 0..count {
+                    let key = self.hive.u32_at(offset + 4 + i * 4)?;
+                    subkeys.push(Key::new(self.hive, self.hive.cell(key)?)?);
+                }
+            }
+            // Index root: offsets of further subkey lists
+            b"ri" => {
+                for i in 0..count {
+                    let sublist = self.hive.u32_at(offset + 4 + i * 4)?;
+                    self.collect_subkeys(sublist, subkeys, visited)?;
+                }
+            }
+            _ => return Err(invalid("unknown subkey list type")),
+        }
+        Ok(())
+    }
+
+    /// Get the subkey with the given name, which is matched case-insensitively
+    pub(crate) fn subkey(&self, name: &str) -> io::Result<Key<'a>> {
+        for subkey in self.subkeys()? {
+            if subkey.name()?.eq_ignore_ascii_case(name) {
+                return Ok(subkey);
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Registry key {} has no subkey {name}", self.name()?),
+        ))
+    }
+
+    /// Get the data of the value with the given name, which is matched case-insensitively
+    pub(crate) fn value(&self, name: &str) -> io::Result<&'a [u8]> {
+        let count = self.hive.u32_at(self.offset + 36)? as usize;
+        let list = if count > 0 {
+            self.hive.cell(self.hive.u32_at(self.offset + 40)?)?
+        } else {
+            0
+        };
+
+        for i in 0..count {
+            let offset = self.hive.cell(self.hive.u32_at(list + i * 4)?)?;
+            if self.hive.slice(offset, 2)? != b"vk" {
+                return Err(invalid("expected a value"));
+            }
+            let name_len = self.hive.u16_at(offset + 2)? as usize;
+            let flags = self.hive.u16_at(offset + 16)?;
+            let value_name = decode_name(
+                self.hive.slice(offset + 20, name_len)?,
+                flags & VALUE_COMP_NAME != 0,
+            );
+            if !value_name.eq_ignore_ascii_case(name) {
+                continue;
+            }
+
+            let size = self.hive.u32_at(offset + 4)?;
+            // Data of 4 bytes or less is stored in place of its offset
+            if size & 0x8000_0000 != 0 {
+                let len = (size & 0x7fff_ffff).min(4) as usize;
+                return self.hive.slice(offset + 8, len);
+            }
+            let data = self.hive.cell(self.hive.u32_at(offset + 8)?)?;
+            if self.hive.slice(data, 2)? == b"db" && size > 16344 {
+                return Err(invalid("big data values are not supported"));
+            }
+            return self.hive.slice(data, size as usize);
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Registry key {} has no value {name}", self.name()?),
+        ))
+    }
+}
+
+/// Find the name of the control set a `SYSTEM` hive was last booted with, such as `ControlSet001`
+fn current_control_set(system: &Hive) -> io::Result<String> {
+    let current = system.key("Select")?.value("Current")?;
+    let current = u32::from_le_bytes(
+        current
+            .try_into()
+            .map_err(|_| invalid("Select\\Current is not a DWORD"))?,
+    );
+    Ok(format!("ControlSet{current:03}"))
+}
+
+/// Read the machine's NetBIOS name from a `SYSTEM` hive
+pub(crate) fn computer_name(system: &Hive) -> io::Result<String> {
+    let key = system.key(&format!(
+        "{}\\Control\\ComputerName\\ComputerName",
+        current_control_set(system)?
+    ))?;
+    // The name is a NUL-terminated UTF-16 string
+    let name = decode_name(key.value("ComputerName")?, false);
+    Ok(name.trim_end_matches('\0').to_string())
+}
+
+/// Extract the boot key (or "syskey") from a `SYSTEM` hive
+///
+/// The boot key is scattered across the class names of 4 keys under the current control set's
+/// `Control\Lsa` key, and then shuffled; it is the root of the encryption of the secrets stored in
+/// both `NTDS.dit` and `SAM` hives.
+pub(crate) fn boot_key(system: &Hive) -> io::Result<[u8; 16]> {
+    let lsa = system.key(&format!("{}\\Control\\Lsa", current_control_set(system)?))?;
+
+    // Each class name holds 8 hexadecimal digits, encoded as UTF-16
+    let mut digits = String::new();
+    for name in ["JD", "Skew1", "GBG", "Data"] {
+        let class = lsa.subkey(name)?.class()?;
+        digits.push_str(&decode_name(class.get(..16).unwrap_or(class), false));
+    }
+    let scrambled = (0..16)
+        .map(|i| {
+            digits
+                .get(i * 2..i * 2 + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| invalid("boot key class names are not hexadecimal"))?;
+
+    const PERMUTATION: [usize; 16] = [8, 5, 4, 2, 11, 9, 13, 3, 0, 6, 1, 12, 14, 10, 15, 7];
+    let mut key = [0; 16];
+    for (byte, &from) in key.iter_mut().zip(PERMUTATION.iter()) {
+        *byte = scrambled[from];
+    }
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{fixture, TempDir};
+
+    fn system() -> Hive {
+        Hive::open(&fixture("ntds-system.hive")).unwrap()
+    }
+
+    #[test]
+    fn boot_key_is_unscrambled_from_the_lsa_class_names() {
+        let key = boot_key(&system()).unwrap();
+        assert_eq!(
+            crate::accounts::to_hex(&key),
+            "A2DD2E344CE5F888374B87BCC2810852"
+        );
+    }
+
+    #[test]
+    fn reads_computer_name() {
+        assert_eq!(computer_name(&system()).unwrap(), "DC01");
+    }
+
+    #[test]
+    fn keys_and_values_are_matched_case_insensitively() {
+        let system = system();
+        let key = system.key("select").unwrap();
+        assert_eq!(key.name().unwrap(), "Select");
+        assert_eq!(key.value("CURRENT").unwrap(), 1u32.to_le_bytes());
+    }
+
+    #[test]
+    fn reads_class_names() {
+        let system = system();
+        let lsa = system.key("ControlSet001\\Control\\Lsa").unwrap();
+        assert_eq!(lsa.subkeys().unwrap().len(), 4);
+        let class = lsa.subkey("JD").unwrap().class().unwrap();
+        assert_eq!(decode_name(class, false).len(), 8);
+        assert!(system.key("Select").unwrap().class().unwrap().is_empty());
+    }
+
+    #[test]
+    fn missing_keys_and_values_are_not_found() {
+        let system = system();
+        let err = system.key("ControlSet002").err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let err = system.key("Select").unwrap().value("Missing").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn refuses_subkey_lists_that_link_back_to_themselves() {
+        // A root key node whose subkey list is an index root pointing at itself
+        let mut data = vec![0; HBIN_START + 0x200];
+        data[0x24..0x28].copy_from_slice(&0u32.to_le_bytes());
+        let key = HBIN_START + 4;
+        data[key..key + 2].copy_from_slice(b"nk");
+        data[key + 20..key + 24].copy_from_slice(&1u32.to_le_bytes());
+        data[key + 28..key + 32].copy_from_slice(&0x100u32.to_le_bytes());
+        let list = HBIN_START + 0x104;
+        data[list..list + 2].copy_from_slice(b"ri");
+        data[list + 2..list + 4].copy_from_slice(&1u16.to_le_bytes());
+        data[list + 4..list + 8].copy_from_slice(&0x100u32.to_le_bytes());
+
+        let hive = Hive { data };
+        let err = hive.root().unwrap().subkeys().err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(
+            err.to_string().contains("linked to more than once"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn refuses_files_that_are_not_hives() {
+        let dir = TempDir::new();
+        let path = dir.write("not-a-hive", vec![0; HBIN_START]);
+        assert!(Hive::open(&path).is_err());
+        assert!(Hive::sniff(b"regf\x01\0\0\0"));
+        assert!(!Hive::sniff(b"\xef\xcd\xab\x89"));
+    }
+}
@@ -1,218 +1,599 @@
+use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, Write};
+use std::time::Instant;
 
 use clap::Parser;
 use encoding_rs_io::DecodeReaderBytes;
 
+use accounts::{Account, AccountsFormat, OnError};
+
+mod accounts;
+mod banned;
 mod consts;
 mod cli;
+mod crypto;
+mod ese;
+mod fetch;
+mod hive;
+mod search;
+mod serve;
+#[cfg(test)]
+mod testing;
 
-/// Simple data structure matching Mimikatz's `dcsync` output with the `/csv` switch
-struct Account {
-    /// Relative ID
-    rid: usize,
-    /// Account name
-    name: String,
-    /// Account password
-    password: String,
-    /// Account `userAccountControl` flags
-    ///
-    /// https://learn.microsoft.com/en-us/troubleshoot/windows-server/identity/useraccountcontrol-manipulate-account-properties
-    uac: u32,
+/// Something found about an account, reported as a row of the output file
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Finding {
+    /// The account's current password hash is in the pwned passwords file
+    Pwned,
+    /// The password history hash at the given index is in the pwned passwords file
+    PwnedHistory(usize),
+    /// The account's current password is made from the banned word at the given index
+    Banned(usize),
+    /// The password history hash at the given index is made from the banned word at the second
+    /// index
+    BannedHistory(usize, usize),
+    /// The account's current password hash is also at the given index of its password history
+    PasswordCycling(usize),
+    /// The account has an LM hash stored, which is trivial to crack
+    LmStored,
 }
 
-/// Use a jump search to progressively search through the file for sorted hashes
-///
-/// The search file is expected to contain lines in the format `<hash>:<count>`, where `<count>`
-/// is the number of times it has been seen in breaches; this is precisely the format for the
-/// data files provided by [HaveIBeenPwned]
-///
-/// For each hash given to us, we jump forward in the sorted file until we hit a larger hash.
-/// Then, we jump back to our previous position, and do a simple linear search through that
-/// segment until we either find the hash we're looking for, or once again pass it by.
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Finding::Pwned => f.write_str("pwned"),
+            Finding::PwnedHistory(index) => write!(f, "pwned history {index}"),
+            Finding::Banned(_) => f.write_str("banned word"),
+            Finding::BannedHistory(index, _) => write!(f, "banned word history {index}"),
+            Finding::PasswordCycling(index) => write!(f, "password cycling (history {index})"),
+            Finding::LmStored => f.write_str("LM stored"),
+        }
+    }
+}
+
+/// List every hash to search for, with the index of its account and, for password history
+/// hashes, its history index
 ///
-/// By leaving the reader's cursor in the position where we last read it, we can pre-sort the
-/// hashes we're looking for and then start the search for the next one where we left off,
-/// allowing us to very quickly search a very large file for a large number of hashes. This
-/// makes this jump search much faster than doing a binary search for each hash, despite a
-/// binary search being much faster for a single search.
+/// History hashes that are the same as the current hash are left out, as the current hash covers
+/// them.
+fn searches(accounts: &[Account]) -> Vec<(&str, usize, Option<usize>)> {
+    let mut searches = Vec::new();
+    for (idx, account) in accounts.iter().enumerate() {
+        searches.push((account.password.as_str(), idx, None));
+        for (index, hash) in account.history.iter().enumerate() {
+            if *hash != account.password {
+                searches.push((hash.as_str(), idx, Some(index)));
+            }
+        }
+    }
+    searches
+}
+
+/// Find the history index at which an account's current hash appears again, meaning it has reused
+/// a password
+fn reused_password(account: &Account) -> Option<usize> {
+    account
+        .history
+        .iter()
+        .enumerate()
+        .find(|(_, hash)| **hash == account.password)
+        .map(|(index, _)| index)
+}
+
+/// Pwned count of the hash at the given index, as the largest count of any corpus
 ///
-/// Based on the algorithm described at https://www.geeksforgeeks.org/jump-search/
+/// The corpora may overlap, so their counts are not added up.
+fn pwned_count(counts: &[Vec<usize>], hit: usize) -> usize {
+    counts
+        .iter()
+        .map(|corpus_counts| corpus_counts[hit])
+        .max()
+        .unwrap_or_default()
+}
+
+/// Each corpus the hash at the given index is in, with its count there, for the `Source` column
+fn found_in(sources: &[search::Source], counts: &[Vec<usize>], hit: usize) -> String {
+    sources
+        .iter()
+        .zip(counts)
+        .filter(|(_, corpus_counts)| corpus_counts[hit] > 0)
+        .map(|(source, corpus_counts)| format!("{}:{}", source.name, corpus_counts[hit]))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Read every account from the accounts file, in the format chosen with `--accounts-format` or
+/// detected from the start of the file
 ///
-/// [HaveIBeenPwned]: https://haveibeenpwned.com/Passwords
-fn jump_search<R: BufRead + Seek>(reader: &mut R, hash: &str) -> (String, usize) {
-    // Stash our starting point for this search
-    let mut segment_start = reader.stream_position().unwrap();
-    // Determine the total number of bytes in our reader
-    let n = reader.seek(SeekFrom::End(0)).unwrap();
-    // Set our step size to be equal to the square root of the total bytes, to a minimum of 1 byte
-    let step = ((n as f32).sqrt() as i64).max(1);
-    // And return to our starting point
-    reader.seek(SeekFrom::Start(segment_start)).unwrap();
-
-    // String buffer to read lines into
-    let mut line = String::new();
-
-    // Jump search phase: Jump `step` bytes forward in the file, and see if we've passed our target
-    loop {
-        // Empty our buffer
-        line.clear();
-        // Read the next line into our buffer
-        if reader.read_line(&mut line).unwrap() == 0 {
-            // Reached the end of the file without finding our target
-            return ("".to_string(), 0);
-        }
-
-        // Split the line and check if we've found our target
-        let split_line: Vec<_> = line.trim().split(':').collect();
-
-        match split_line[0].cmp(hash) {
-            std::cmp::Ordering::Equal => {
-            // Found our target! Return it directly
-                return (split_line[0].to_owned(), split_line[1].parse().unwrap())
-            }
-            // Still "below" our target, keep going
-            std::cmp::Ordering::Less => {}
-            // Overshot our target, break out of this loop and begin the linear search phase
-            std::cmp::Ordering::Greater => break,
-        }
-
-        // Store our current position as the start of the next segment
-        segment_start = reader.stream_position().unwrap();
-        // Jump forward `step` bytes
-        reader.seek(SeekFrom::Current(step)).unwrap();
-        // We probably landed in the middle of a line, so read to the end of the line
-        reader.read_line(&mut line).unwrap();
-    }
-
-    // Found the segment where our hash may be, start looking for it linearly
-    // Start by backing up to the start of the segment
-    reader.seek(SeekFrom::Start(segment_start)).unwrap();
-    // Loop infinitely; we'll necessarily stop by the end of the segment because
-    // we only drop to this part of the function if we have overshot our target
-    loop {
-        // Empty our buffer
-        line.clear();
-        // Read the next line into our buffer
-        if reader.read_line(&mut line).unwrap() == 0 {
-            // Reached the end of the file without finding our target
-            return ("".to_string(), 0);
-        }
-
-        // Split the line and check if we've found our target
-        let split_line: Vec<_> = line.trim().split(':').collect();
-
-        match split_line[0].cmp(hash) {
-            std::cmp::Ordering::Equal => {
-            // Found our target! Return it directly
-                return (split_line[0].to_owned(), split_line[1].parse().unwrap())
-            }
-            // Still "below" our target, keep going
-            std::cmp::Ordering::Less => {}
-            std::cmp::Ordering::Greater => {
-            // Overshot our target, means it's not here; return the last hash we did see
-                return (split_line[0].to_owned(), split_line[1].parse().unwrap())
-            }
+/// Returns the accounts, along with the lines of a text accounts file that could not be parsed.
+fn read_accounts(args: &cli::Args) -> (Vec<Account>, Vec<String>) {
+    // Without a subcommand, clap makes sure the accounts file is given
+    let accounts_path = args.accounts.as_deref().unwrap_or_default();
+    let mut accounts_file = File::open(accounts_path).expect("Unable to open hashes file");
+
+    // Binary formats can be recognised from their first bytes
+    let mut start = Vec::new();
+    (&mut accounts_file)
+        .take(8)
+        .read_to_end(&mut start)
+        .expect("Unable to read hashes file");
+    accounts_file.rewind().expect("Unable to read hashes file");
+    let binary_format = match args.accounts_format {
+        AccountsFormat::Auto => AccountsFormat::detect_binary(&start)
+            .inspect(|format| println!("Detected accounts file format: {format}")),
+        format if format.is_binary() => Some(format),
+        _ => None,
+    };
+    match binary_format {
+        Some(AccountsFormat::Ntds) => {
+            let system = args
+                .system
+                .as_deref()
+                .expect("An NTDS.dit accounts file needs the SYSTEM hive given with --system");
+            let accounts = accounts::ntds::read(accounts_path, system)
+                .unwrap_or_else(|err| panic!("Failed to read NTDS.dit: {err}"));
+            return (accounts, Vec::new());
+        }
+        Some(AccountsFormat::Sam) => {
+            let system = args
+                .system
+                .as_deref()
+                .expect("A SAM hive accounts file needs the SYSTEM hive given with --system");
+            let accounts = accounts::sam::read(accounts_path, system)
+                .unwrap_or_else(|err| panic!("Failed to read SAM hive: {err}"));
+            return (accounts, Vec::new());
+        }
+        Some(AccountsFormat::Keytab) => {
+            let accounts = accounts::keytab::read(accounts_path)
+                .unwrap_or_else(|err| panic!("Failed to read keytab: {err}"));
+            return (accounts, Vec::new());
+        }
+        _ => {}
+    }
+
+    // Text formats are detected from their first lines
+    let mut accounts_lines = BufReader::new(DecodeReaderBytes::new(accounts_file)).lines();
+    let sample: Vec<_> = accounts_lines
+        .by_ref()
+        .take(accounts::SNIFF_LINES)
+        .filter_map(|line| line.ok())
+        .collect();
+    let accounts_format = match args.accounts_format {
+        AccountsFormat::Auto => {
+            let format = AccountsFormat::detect(&sample)
+                .expect("Unable to detect the accounts file format; specify it with --accounts-format");
+            println!("Detected accounts file format: {format}");
+            format
+        }
+        format => format,
+    };
+
+    let lines = sample.into_iter().map(Ok).chain(accounts_lines);
+    let (accounts, rejected) = accounts::read(lines, accounts_format, args.on_error)
+        .unwrap_or_else(|err| panic!("Failed to parse accounts file: {err}"));
+    if args.on_error == OnError::Reject {
+        let rejects_file = File::create(&args.rejects_file).expect("Unable to create rejects file");
+        let mut rejects_writer = BufWriter::new(rejects_file);
+        for line in &rejected {
+            writeln!(&mut rejects_writer, "{line}").expect("Failed to write to rejects file");
         }
+        rejects_writer.flush().expect("Failed to finish writing rejects file");
+    }
+
+    (accounts, rejected)
+}
+
+/// Print how long a command took since it started
+fn report_elapsed(start: Instant) {
+    let elapsed = start.elapsed();
+    let minutes = elapsed.as_secs() / 60;
+    let seconds = elapsed.as_secs_f32() - (minutes * 60) as f32;
+    println!("Finished in {minutes} minutes {seconds:.2} seconds");
+}
+
+/// Build a binary index or prefix offset sidecar of a sorted pwned passwords file
+fn index(args: &cli::IndexArgs) {
+    let start = Instant::now();
+
+    let message = if args.offsets {
+        search::offsets::build(&args.passwords)
+            .unwrap_or_else(|err| panic!("Failed to build prefix offset sidecar: {err}"));
+        format!(
+            "Wrote prefix offsets into {}",
+            search::offsets::sidecar_path(&args.passwords)
+        )
+    } else {
+        let output = args
+            .output
+            .clone()
+            .unwrap_or_else(|| search::index_path(&args.passwords));
+        let records = search::binary::build(&args.passwords, &output)
+            .unwrap_or_else(|err| panic!("Failed to build binary index: {err}"));
+        format!("Indexed {records} hashes into {output}")
+    };
+
+    report_elapsed(start);
+    println!("{message}");
+}
+
+fn lookup(args: &cli::LookupArgs) {
+    search::verify::spot_check(&args.passwords)
+        .unwrap_or_else(|err| panic!("Refusing pwned passwords file: {err}"));
+    let passwords =
+        search::MmapSearch::open(&args.passwords).expect("Unable to open pwned passwords file");
+
+    for hash in &args.hashes {
+        let hash = hash.to_ascii_uppercase();
+        let count = passwords
+            .lookup(&hash)
+            .unwrap_or_else(|err| panic!("Failed to search pwned passwords: {err}"));
+        println!("{hash}:{count}");
+    }
+}
+
+/// Download or refresh a local copy of a range API, and join it into a single file if asked to
+fn fetch(args: &cli::FetchArgs) {
+    let start = Instant::now();
+
+    let options = fetch::FetchOptions {
+        concurrency: args.concurrency.into(),
+        retries: args.retries,
+    };
+    let summary = fetch::fetch(&args.base_url, &args.dir, &options)
+        .unwrap_or_else(|err| panic!("Failed to fetch pwned passwords: {err}"));
+    let joined = args.output.as_ref().map(|output| {
+        let hashes = fetch::join(&args.dir, output)
+            .unwrap_or_else(|err| panic!("Failed to write pwned passwords file: {err}"));
+        format!("Wrote {hashes} hashes into {output}")
+    });
+
+    report_elapsed(start);
+    println!(
+        "Downloaded {} prefixes; {} unchanged; {} already fetched before an interruption",
+        summary.downloaded, summary.unchanged, summary.resumed
+    );
+    if let Some(joined) = joined {
+        println!("{joined}");
+    }
+}
+
+/// Check every line of a pwned passwords file, exiting with status 1 if any are wrong
+fn verify(args: &cli::VerifyArgs) {
+    let reader = if args.passwords == "-" {
+        search::compressed::decompress(BufReader::new(io::stdin()))
+    } else {
+        search::compressed::reader(&args.passwords)
+    }
+    .expect("Unable to open pwned passwords file");
+    let report = search::verify::verify(reader, args.max_problems)
+        .unwrap_or_else(|err| panic!("Failed to read pwned passwords file: {err}"));
+
+    for violation in &report.violations {
+        println!(
+            "Byte {}, line {}: {}",
+            violation.offset, violation.line_number, violation.message
+        );
+    }
+    if report.total_violations > report.violations.len() {
+        println!(
+            "... and {} more problems",
+            report.total_violations - report.violations.len()
+        );
+    }
+    println!(
+        "Checked {} hashes; {} problems found",
+        report.hashes, report.total_violations
+    );
+    if report.total_violations > 0 {
+        std::process::exit(1);
     }
 }
 
 fn main() {
-    let start = std::time::Instant::now();
+    let start = Instant::now();
 
     let args = cli::Args::parse();
+    match &args.command {
+        Some(cli::Command::Index(index_args)) => return index(index_args),
+        Some(cli::Command::Lookup(lookup_args)) => return lookup(lookup_args),
+        Some(cli::Command::Fetch(fetch_args)) => return fetch(fetch_args),
+        Some(cli::Command::Verify(verify_args)) => return verify(verify_args),
+        Some(cli::Command::Serve(serve_args)) => {
+            return serve::run(
+                &serve_args.passwords,
+                &serve_args.listen,
+                serve_args.threads.into(),
+                serve_args.cache_size,
+            )
+            .expect("Failed to serve pwned passwords")
+        }
+        None => {}
+    }
 
-    // The hashes file is expected to be whitespace-delineated and contain (at least) 4 columns:
-    // RID  AccountName    HashedPassword  userAccountControl
-    // This matches the output of Mimikatz's `dcsync` with the `/csv` option
-    let accounts_file = File::open(args.accounts).expect("Unable to open hashes file");
-    let accounts_reader = BufReader::new(DecodeReaderBytes::new(accounts_file));
+    // Pwned passwords files, containing lines in the format `<hash>:<count>`, or directories of
+    // per-prefix files
+    // Without a subcommand, clap makes sure the passwords file is given
+    let mut sources: Vec<search::Source> = args.passwords.iter().cloned().collect();
+    sources.extend(args.corpora.iter().cloned());
+    if let Some(manifest) = &args.corpus_manifest {
+        sources.extend(
+            search::read_manifest(manifest)
+                .unwrap_or_else(|err| panic!("Unable to read corpus manifest: {err}")),
+        );
+    }
+    for (i, source) in sources.iter().enumerate() {
+        if sources[..i].iter().any(|other| other.name == source.name) {
+            panic!(
+                "More than one pwned passwords file is named \"{}\"; name them with NAME=PATH",
+                source.name
+            );
+        }
+    }
+    // Base words that passwords should not be made from
+    let banned_words = args
+        .banned_words
+        .as_ref()
+        .map(|path| banned::read(path).expect("Unable to read banned words file"));
 
-    // Pwned passwords file, containing lines in the format `<hash>:<count>`
-    let passwords_file = File::open(args.passwords)
-        .expect("Unable to open pwned passwords file");
-    let mut passwords_reader = BufReader::new(passwords_file);
+    let range = search::RangeOptions {
+        cache_dir: args.range_cache.clone(),
+        concurrency: args.range_concurrency.into(),
+        retries: args.range_retries,
+    };
+    let mut corpora: Vec<_> = sources
+        .iter()
+        .map(|source| {
+            search::open(&source.path, args.strategy, &range).unwrap_or_else(|err| {
+                panic!("Unable to open pwned passwords file {}: {err}", source.path)
+            })
+        })
+        .collect();
 
     // Output CSV file
-    let outfile = File::create(args.outfile).expect("Unable to created output file");
+    let outfile = File::create(&args.outfile).expect("Unable to created output file");
     let mut writer = BufWriter::new(outfile);
 
-    // Read our hashes file and create `Account`s from each of the active accounts in the file
-    let mut total_accounts = 0;
-    let mut accounts: Vec<_> = accounts_reader
-        .lines()
-        .filter_map(|line| {
-            let line = line.ok()?;
-            let split: Vec<_> = line.split_whitespace().collect();
-
-            total_accounts += 1;
-
-            let uac = split[3]
-                .parse()
-                .expect(&format!("Failed to parse userAccountControl: {line}"));
-            // Ensure account is active, then build an `Account` object from it
-            if uac & consts::UAC_ACCOUNT_DISABLE == 0 {
-                Some(Account {
-                    rid: split[0].parse().expect("Failed to parse RID: {line}"),
-                    name: split[1].to_string(),
-                    password: split[2].to_ascii_uppercase(),
-                    uac,
-                })
-            } else {
-                None
-            }
-        })
-        .collect();
-    // Sort the accounts by their password hashes so we can more efficiently search for them
+    // Read our hashes file and keep only the active accounts in it
+    let (mut accounts, rejected) = read_accounts(&args);
+    let total_accounts = accounts.len();
+    accounts.retain(|account| account.uac & consts::UAC_ACCOUNT_DISABLE == 0);
+    // Sort the accounts by their password hashes so the output is in the same order as before
     accounts.sort_unstable_by(|a, b| a.password.cmp(&b.password));
     let active_accounts = accounts.len();
 
-    // Write column headers to our output file
-    writeln!(&mut writer, "RID\tName\tuserAccountControl\tPwned").expect("Failed to write to file");
+    let searches = searches(&accounts);
+    // Search for each distinct hash once, in sorted order so we can more efficiently search for
+    // them
+    let mut hashes: Vec<&str> = searches.iter().map(|&(hash, _, _)| hash).collect();
+    hashes.sort_unstable();
+    hashes.dedup();
+    // Pwned count of each hash in each corpus, and the number of threads each was searched on
+    let mut counts = Vec::with_capacity(corpora.len());
+    let mut threads = Vec::with_capacity(corpora.len());
+    for (source, corpus) in sources.iter().zip(&mut corpora) {
+        let (corpus_counts, corpus_threads) =
+            search::parallel::search(corpus.as_mut(), &hashes, args.threads.into())
+                .unwrap_or_else(|err| panic!("Failed to search {}: {err}", source.path));
+        counts.push(corpus_counts);
+        threads.push(corpus_threads);
+    }
+    // Account hashes of passwords made from banned words, and the words themselves
+    let banned = banned_words.map(|words| (banned::Banned::new(&words, &hashes), words));
+    let pwned = |i: usize| pwned_count(&counts, i);
+    let source_column = |i: usize| found_in(&sources, &counts, i);
 
+    // Findings as (account index, finding, index of the hash the finding is about, if it was
+    // found in any corpus)
+    let mut findings = Vec::new();
+    // Index of each account's current password hash, if it was found, for findings about the
+    // account as a whole
+    let mut current_hit = vec![None; accounts.len()];
     let mut pwned_accounts = 0;
+    let mut pwned_history = 0;
 
-    let mut last_hash = String::new();
-    let mut last_pwned = 0;
+    for &(hash, idx, history_index) in &searches {
+        let Ok(hit) = hashes.binary_search(&hash) else {
+            continue;
+        };
+        if pwned(hit) == 0 {
+            continue;
+        }
 
-    accounts
-        .iter()
-        .filter_map(|account| {
-            // If this account's password is a duplicate of the previously seen hash, no need to search - it's pwned
-            if account.password == last_hash {
+        match history_index {
+            None => {
                 pwned_accounts += 1;
-                return Some((account, last_pwned));
+                current_hit[idx] = Some(hit);
+                findings.push((idx, Finding::Pwned, Some(hit)));
+            }
+            Some(index) => {
+                pwned_history += 1;
+                findings.push((idx, Finding::PwnedHistory(index), Some(hit)));
             }
+        }
+    }
 
-            // Search for this account's password hash
-            // Note the returned hash may not be the same one we're looking for, indicating it was not found
-            (last_hash, last_pwned) = jump_search(&mut passwords_reader, account.password.as_str());
+    // Hashes made from banned words; only the word is reported, never the password made from it
+    let mut banned_accounts = 0;
+    if let Some((banned, _)) = &banned {
+        for &(hash, idx, history_index) in &searches {
+            let Some(word) = banned.find(hash) else {
+                continue;
+            };
+            let hit = hashes.binary_search(&hash).ok().filter(|&i| pwned(i) > 0);
 
-            // Check if the returned hash is actually the one we're looking for
-            if account.password == last_hash {
-                pwned_accounts += 1;
-                Some((account, last_pwned))
-            } else {
-                None
+            match history_index {
+                None => {
+                    banned_accounts += 1;
+                    findings.push((idx, Finding::Banned(word), hit));
+                }
+                Some(index) => findings.push((idx, Finding::BannedHistory(index, word), hit)),
             }
-        })
-        .for_each(|(account, pwned)| {
-            // Write each user we found into our output CSV
-            writeln!(
-                &mut writer,
-                "{}\t{}\t{}\t{}",
-                account.rid, account.name, account.uac, pwned
-            )
-            .expect("Failed to write to file");
-        });
+        }
+    }
+
+    // Accounts whose current hash is also in their history have reused a password
+    let mut cycling_accounts = 0;
+    for (idx, account) in accounts.iter().enumerate() {
+        if let Some(index) = reused_password(account) {
+            cycling_accounts += 1;
+            findings.push((idx, Finding::PasswordCycling(index), current_hit[idx]));
+        }
+    }
+
+    // Stored LM hashes are reported whether or not the NT hash is pwned
+    let mut lm_accounts = 0;
+    for (idx, account) in accounts.iter().enumerate() {
+        if account.lm.is_some() {
+            lm_accounts += 1;
+            findings.push((idx, Finding::LmStored, current_hit[idx]));
+        }
+    }
+    findings.sort_unstable_by_key(|&(idx, finding, _)| (idx, finding));
+
+    // Write column headers to our output file
+    writeln!(&mut writer, "RID\tName\tuserAccountControl\tPwned\tFinding\tSource\tNote")
+        .expect("Failed to write to file");
+
+    for (idx, finding, hit) in findings {
+        let account = &accounts[idx];
+        // Findings about banned words name the word in place of the pwned passwords files
+        let source = match (finding, &banned) {
+            (Finding::Banned(word) | Finding::BannedHistory(_, word), Some((_, words))) => {
+                format!("banned:{}", words[word])
+            }
+            _ => hit.map(source_column).unwrap_or_default(),
+        };
+        // Write each finding into our output CSV
+        writeln!(
+            &mut writer,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            account.rid,
+            account.name,
+            account.uac,
+            hit.map_or(0, pwned),
+            finding,
+            source,
+            account.note.as_deref().unwrap_or_default()
+        )
+        .expect("Failed to write to file");
+    }
 
     // Flush the buffered writer to disk
     writer.flush().expect("Failed to finish writing");
 
     // Output timing and statistics
-    let elapsed = start.elapsed();
-    let minutes = elapsed.as_secs() / 60;
-    let seconds = elapsed.as_secs_f32() - (minutes * 60) as f32;
-    println!("Finished in {minutes} minutes {seconds:.2} seconds");
+    report_elapsed(start);
+    for (i, source) in sources.iter().enumerate() {
+        let strategy = corpora[i].strategy();
+        let found = counts[i].iter().filter(|&&count| count > 0).count();
+        if threads[i] > 1 {
+            println!(
+                "Searched {} with {strategy} on {} threads; {found} hashes found",
+                source.name, threads[i]
+            );
+        } else {
+            println!("Searched {} with {strategy}; {found} hashes found", source.name);
+        }
+    }
     println!("{total_accounts} accounts; {active_accounts} active accounts; {pwned_accounts} pwned accounts");
+    println!("{pwned_history} pwned password history hashes; {cycling_accounts} accounts reusing passwords");
+    if let Some((banned, words)) = &banned {
+        println!(
+            "{} banned password candidates from {} banned words; {banned_accounts} accounts using a banned word",
+            banned.len(),
+            words.len()
+        );
+    }
+    println!("{lm_accounts} accounts with a stored LM hash");
+    println!("{} rejected lines", rejected.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWORD: &str = "8846F7EAEE8FB117AD06BDD830B7586C";
+    const PASSWORD1: &str = "64F12CDDAA88057E06A81B54E73B949B";
+    const ADMIN: &str = "209C6174DA490CAEB422F3FA5A7AE634";
+
+    fn account(password: &str, history: &[&str]) -> Account {
+        Account {
+            rid: 1104,
+            name: "alice".to_string(),
+            password: password.to_string(),
+            history: history.iter().map(|hash| hash.to_string()).collect(),
+            lm: None,
+            uac: consts::UAC_NORMAL_ACCOUNT,
+            note: None,
+        }
+    }
+
+    #[test]
+    fn searches_history_hashes_that_differ_from_the_current_hash() {
+        let accounts = [
+            account(PASSWORD, &[PASSWORD1, PASSWORD, ADMIN]),
+            account(ADMIN, &[]),
+        ];
+        assert_eq!(
+            searches(&accounts),
+            [
+                (PASSWORD, 0, None),
+                (PASSWORD1, 0, Some(0)),
+                (ADMIN, 0, Some(2)),
+                (ADMIN, 1, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_reused_passwords_anywhere_in_the_history() {
+        assert_eq!(
+            reused_password(&account(PASSWORD, &[PASSWORD1, ADMIN, PASSWORD])),
+            Some(2)
+        );
+        // The history holds previous passwords only, so even its first entry is a reuse
+        assert_eq!(
+            reused_password(&account(PASSWORD, &[PASSWORD, PASSWORD1])),
+            Some(0)
+        );
+        assert_eq!(
+            reused_password(&account(PASSWORD, &[PASSWORD1, ADMIN])),
+            None
+        );
+        assert_eq!(reused_password(&account(PASSWORD, &[])), None);
+    }
+
+    #[test]
+    fn finds_cycling_when_secretsdump_history0_is_the_current_hash() {
+        // secretsdump.py leaves the current hash out of the history, so history0 is a reuse
+        let empty_lm = "aad3b435b51404eeaad3b435b51404ee";
+        let dump = format!(
+            "CONTOSO\\alice:1104:{empty_lm}:{PASSWORD}:::\n\
+             CONTOSO\\alice_history0:1104:{empty_lm}:{PASSWORD}:::\n\
+             CONTOSO\\alice_history1:1104:{empty_lm}:{PASSWORD1}:::\n"
+        );
+        let lines = dump.lines().map(|line| Ok(line.to_string()));
+        let (accounts, _) =
+            accounts::read(lines, AccountsFormat::Secretsdump, OnError::Stop).unwrap();
+        let finding = reused_password(&accounts[0]).map(Finding::PasswordCycling);
+        assert!(matches!(finding, Some(Finding::PasswordCycling(0))));
+        assert_eq!(finding.unwrap().to_string(), "password cycling (history 0)");
+    }
+
+    #[test]
+    fn combines_counts_across_corpora() {
+        let sources = [
+            search::Source::parse("hibp=pwned.txt").unwrap(),
+            search::Source::parse("cracked=cracked.txt").unwrap(),
+        ];
+        let counts = [vec![3, 0, 0], vec![5, 2, 0]];
+        // The largest count, since the corpora may hold the same breaches
+        assert_eq!(pwned_count(&counts, 0), 5);
+        assert_eq!(pwned_count(&counts, 1), 2);
+        assert_eq!(pwned_count(&counts, 2), 0);
+        assert_eq!(found_in(&sources, &counts, 0), "hibp:3; cracked:5");
+        assert_eq!(found_in(&sources, &counts, 1), "cracked:2");
+        assert_eq!(found_in(&sources, &counts, 2), "");
+    }
 }
@@ -0,0 +1,362 @@
+//! Compact binary index of a pwned passwords corpus, and an interpolation search over it
+//!
+//! The index is a fixed header followed by one fixed-width record per hash, in the same ascending
+//! order as the text file it was built from:
+//!
+//! | Offset | Size | Contents                                  |
+//! |--------|------|-------------------------------------------|
+//! | 0      | 8    | Magic, `ADPWNIDX`                         |
+//! | 8      | 4    | Format version, little-endian             |
+//! | 12     | 4    | Record size in bytes, little-endian       |
+//! | 16     | 8    | Number of records, little-endian          |
+//! | 24     | 20   | First record: 16-byte hash, 4-byte count  |
+//!
+//! Each record holds the raw 16 bytes of the hash and its pwned count as a little-endian 32-bit
+//! integer. Since the hashes are uniformly distributed, the position of a hash in the index can
+//! be estimated from its value, and an interpolation search finds it in a handful of reads.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter, Read, Seek, SeekFrom, Write};
+
+use super::{compressed, parse_line, Search};
+use crate::accounts::parse_hash;
+
+/// Magic bytes at the start of every index
+pub(crate) const MAGIC: &[u8; 8] = b"ADPWNIDX";
+
+/// Version of the index format written by this build
+const VERSION: u32 = 1;
+
+/// Size of the header, in bytes
+const HEADER_LEN: u64 = 24;
+
+/// Size of each record, in bytes
+const RECORD_LEN: usize = 20;
+
+/// Build an error for a malformed index
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Malformed binary index: {reason}"),
+    )
+}
+
+/// Estimate where a hash falls between the two hashes bounding a range, from their first 8 bytes
+fn interpolate(low: &[u8; 16], high: &[u8; 16], target: &[u8; 16]) -> f64 {
+    let key = |hash: &[u8; 16]| u64::from_be_bytes(hash[..8].try_into().unwrap()) as f64;
+    let (low, high, target) = (key(low), key(high), key(target));
+    if high <= low {
+        0.5
+    } else {
+        ((target - low) / (high - low)).clamp(0.0, 1.0)
+    }
+}
+
+/// Convert a sorted `<hash>:<count>` text file, which may be compressed, into a binary index
+///
+/// Returns the number of records written. Fails if a line is malformed, or if the hashes are not
+/// in strictly ascending order, since the index could not be searched. The index is written to a
+/// temporary file first, so that a failed build never leaves a partial index to be picked up.
+pub(crate) fn build(text_path: &str, index_path: &str) -> io::Result<u64> {
+    let partial_path = format!("{index_path}.partial");
+    let result = write_index(text_path, &partial_path);
+    match result {
+        Ok(_) => fs::rename(&partial_path, index_path)?,
+        Err(_) => {
+            let _ = fs::remove_file(&partial_path);
+        }
+    }
+    result
+}
+
+/// Write the binary index of a sorted `<hash>:<count>` text file
+fn write_index(text_path: &str, index_path: &str) -> io::Result<u64> {
+    let reader = compressed::reader(text_path)?;
+    let mut writer = BufWriter::new(File::create(index_path)?);
+
+    // Write a header with no records for now, and fill in the count at the end
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&(RECORD_LEN as u32).to_le_bytes())?;
+    writer.write_all(&0u64.to_le_bytes())?;
+
+    let mut records = 0u64;
+    let mut previous: Option<[u8; 16]> = None;
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (hash, count) = parse_line(&line)?;
+        let hash = parse_hash(hash).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Line {}: \"{hash}\" is not a 32-character hash", idx + 1),
+            )
+        })?;
+        if previous.is_some_and(|previous| previous >= hash) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Line {}: hashes are not in ascending order", idx + 1),
+            ));
+        }
+        let count = u32::try_from(count).unwrap_or(u32::MAX);
+
+        writer.write_all(&hash)?;
+        writer.write_all(&count.to_le_bytes())?;
+        previous = Some(hash);
+        records += 1;
+    }
+
+    let mut file = writer.into_inner().map_err(|err| err.into_error())?;
+    file.seek(SeekFrom::Start(16))?;
+    file.write_all(&records.to_le_bytes())?;
+    file.sync_all()?;
+
+    Ok(records)
+}
+
+/// Check if a file looks like a binary index from its first bytes
+pub(crate) fn sniff(start: &[u8]) -> bool {
+    start.starts_with(MAGIC)
+}
+
+/// A binary index, searched with an interpolation search
+pub(crate) struct BinaryIndex {
+    path: String,
+    file: File,
+    records: u64,
+}
+
+impl BinaryIndex {
+    /// Open a binary index, checking its header
+    pub(crate) fn open(path: &str) -> io::Result<BinaryIndex> {
+        let mut file = File::open(path)?;
+        let mut header = [0; HEADER_LEN as usize];
+        file.read_exact(&mut header)
+            .map_err(|_| invalid("header is truncated"))?;
+        if !sniff(&header) {
+            return Err(invalid("missing magic bytes"));
+        }
+        let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        if version != VERSION {
+            return Err(invalid(&format!(
+                "unsupported version {version}; rebuild it with the `index` subcommand"
+            )));
+        }
+        let record_len = u32::from_le_bytes(header[12..16].try_into().unwrap());
+        if record_len as usize != RECORD_LEN {
+            return Err(invalid("unexpected record size"));
+        }
+        let records = u64::from_le_bytes(header[16..24].try_into().unwrap());
+        if file.metadata()?.len() != HEADER_LEN + records * RECORD_LEN as u64 {
+            return Err(invalid("file size does not match its record count"));
+        }
+
+        Ok(BinaryIndex {
+            path: path.to_string(),
+            file,
+            records,
+        })
+    }
+
+    /// Read the record at the given index
+    fn record(&mut self, index: u64) -> io::Result<([u8; 16], u32)> {
+        let mut record = [0; RECORD_LEN];
+        self.file
+            .seek(SeekFrom::Start(HEADER_LEN + index * RECORD_LEN as u64))?;
+        self.file.read_exact(&mut record)?;
+        Ok((
+            record[..16].try_into().unwrap(),
+            u32::from_le_bytes(record[16..].try_into().unwrap()),
+        ))
+    }
+
+    /// Find a hash among the records from `low` onwards, which are no smaller than `low_hash`
+    ///
+    /// Returns the pwned count if found, and the index of the first record not below the hash,
+    /// from which the search for the next, larger hash can start.
+    fn find(
+        &mut self,
+        hash: &[u8; 16],
+        mut low: u64,
+        mut low_hash: [u8; 16],
+    ) -> io::Result<(u32, u64)> {
+        let mut high = self.records;
+        // No record is larger than the largest possible hash
+        let mut high_hash = [0xff; 16];
+        // Interpolation is only as good as the distribution of the hashes, so fall back to
+        // halving the range if it does not converge quickly
+        let mut probes = 0;
+        let max_probes = 2 * (64 - self.records.leading_zeros());
+
+        while low < high {
+            let width = high - low;
+            let probe = if probes < max_probes {
+                low + ((width as f64 * interpolate(&low_hash, &high_hash, hash)) as u64)
+                    .min(width - 1)
+            } else {
+                low + width / 2
+            };
+            probes += 1;
+
+            let (probe_hash, count) = self.record(probe)?;
+            match probe_hash.cmp(hash) {
+                std::cmp::Ordering::Equal => return Ok((count, probe)),
+                std::cmp::Ordering::Less => {
+                    low = probe + 1;
+                    low_hash = probe_hash;
+                }
+                std::cmp::Ordering::Greater => {
+                    high = probe;
+                    high_hash = probe_hash;
+                }
+            }
+        }
+
+        Ok((0, low))
+    }
+}
+
+impl Search for BinaryIndex {
+    fn search(&mut self, hashes: &[&str]) -> io::Result<Vec<usize>> {
+        let mut counts = Vec::with_capacity(hashes.len());
+        // The hashes are sorted, so each search can start where the last one left off
+        let mut low = 0;
+        let mut low_hash = [0x00; 16];
+
+        for hash in hashes {
+            let Some(hash) = parse_hash(hash) else {
+                counts.push(0);
+                continue;
+            };
+            let (count, next) = self.find(&hash, low, low_hash)?;
+            counts.push(count as usize);
+            low = next;
+            low_hash = hash;
+        }
+
+        Ok(counts)
+    }
+
+    fn strategy(&self) -> &'static str {
+        "binary index interpolation search"
+    }
+
+    fn reopen(&self) -> io::Result<Option<Box<dyn Search>>> {
+        Ok(Some(Box::new(BinaryIndex::open(&self.path)?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+
+    /// Build the index of a corpus in a temporary directory, returning its path
+    fn build_index(dir: &TempDir, corpus: &[(String, usize)]) -> String {
+        let text = dir.write("pwned.txt", testing::lines(corpus));
+        let index = dir.join("pwned.txt.bin");
+        assert_eq!(build(&text, &index).unwrap(), corpus.len() as u64);
+        index
+    }
+
+    #[test]
+    fn interpolates_between_bounds() {
+        let low = [0x00; 16];
+        let high = [0xff; 16];
+        let mut middle = [0x00; 16];
+        middle[0] = 0x80;
+        assert!((interpolate(&low, &high, &middle) - 0.5).abs() < 0.01);
+        assert_eq!(interpolate(&middle, &high, &low), 0.0);
+        assert_eq!(interpolate(&high, &high, &middle), 0.5);
+    }
+
+    #[test]
+    fn writes_the_documented_layout() {
+        let dir = TempDir::new();
+        let corpus = [("8846F7EAEE8FB117AD06BDD830B7586C".to_string(), 0x0102)];
+        let index = fs::read(build_index(&dir, &corpus)).unwrap();
+        assert_eq!(&index[..8], MAGIC);
+        assert_eq!(index[8..12], VERSION.to_le_bytes());
+        assert_eq!(index[12..16], (RECORD_LEN as u32).to_le_bytes());
+        assert_eq!(index[16..24], 1u64.to_le_bytes());
+        assert_eq!(index[24..40], parse_hash(&corpus[0].0).unwrap());
+        assert_eq!(index[40..44], 0x0102u32.to_le_bytes());
+        assert_eq!(index.len(), 44);
+    }
+
+    #[test]
+    fn finds_every_hash_and_misses_its_neighbours() {
+        let dir = TempDir::new();
+        let corpus = testing::corpus(5000);
+        let mut index = BinaryIndex::open(&build_index(&dir, &corpus)).unwrap();
+        for step in [1, 7, 1000] {
+            let (hashes, expected) = testing::lookups(&corpus, step);
+            let hashes: Vec<&str> = hashes.iter().map(String::as_str).collect();
+            assert_eq!(index.search(&hashes).unwrap(), expected);
+        }
+        let mut reopened = index.reopen().unwrap().unwrap();
+        assert_eq!(
+            reopened.search(&[corpus[42].0.as_str()]).unwrap(),
+            [corpus[42].1]
+        );
+    }
+
+    #[test]
+    fn searches_hashes_beyond_either_end() {
+        let dir = TempDir::new();
+        let corpus = testing::corpus(100);
+        let mut index = BinaryIndex::open(&build_index(&dir, &corpus)).unwrap();
+        let hashes = [
+            "00000000000000000000000000000000",
+            "not a hash",
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF",
+        ];
+        assert_eq!(index.search(&hashes).unwrap(), [0, 0, 0]);
+
+        let empty = build_index(&dir, &[]);
+        let mut index = BinaryIndex::open(&empty).unwrap();
+        assert_eq!(index.search(&hashes).unwrap(), [0, 0, 0]);
+    }
+
+    #[test]
+    fn refuses_to_index_unsorted_or_malformed_files() {
+        let dir = TempDir::new();
+        let index = dir.join("pwned.txt.bin");
+        let corpus = testing::corpus(3);
+        for lines in [
+            format!("{}:1\n{}:1\n", corpus[1].0, corpus[0].0),
+            format!("{}:1\n{}:2\n", corpus[0].0, corpus[0].0),
+            format!("{}:1\nABC:2\n", corpus[0].0),
+            format!("{}:one\n", corpus[0].0),
+        ] {
+            let text = dir.write("pwned.txt", &lines);
+            assert!(build(&text, &index).is_err(), "indexed {lines:?}");
+            // No partial index is left behind to be picked up
+            assert!(fs::metadata(&index).is_err());
+            assert!(fs::metadata(format!("{index}.partial")).is_err());
+        }
+    }
+
+    #[test]
+    fn refuses_malformed_indexes() {
+        let dir = TempDir::new();
+        let index = fs::read(build_index(&dir, &testing::corpus(10))).unwrap();
+
+        let mut wrong_version = index.clone();
+        wrong_version[8] = 2;
+        let mut wrong_count = index.clone();
+        wrong_count[16] += 1;
+        for (name, data) in [
+            ("truncated", &index[..20]),
+            ("magic", &[b"NOTANIDX", &index[8..]].concat()[..]),
+            ("version", &wrong_version[..]),
+            ("count", &wrong_count[..]),
+            ("short", &index[..index.len() - 1]),
+        ] {
+            let path = dir.write(name, data);
+            assert!(BinaryIndex::open(&path).is_err(), "opened {name}");
+        }
+    }
+}
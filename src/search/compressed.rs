@@ -0,0 +1,170 @@
+//! Detection and decompression of gzip, zstd and xz compressed corpora
+//!
+//! A compressed corpus cannot be seeked into, so it can only be read from start to finish; see
+//! [`MergeJoin`](super::MergeJoin).
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+
+/// Compression formats a corpus may be stored in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Xz => "xz",
+        })
+    }
+}
+
+/// Detect the compression format of a file from its first bytes
+pub(crate) fn sniff(start: &[u8]) -> Option<Compression> {
+    if start.starts_with(&[0x1f, 0x8b]) {
+        Some(Compression::Gzip)
+    } else if start.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+        Some(Compression::Zstd)
+    } else if start.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        Some(Compression::Xz)
+    } else {
+        None
+    }
+}
+
+/// Detect the compression format of a file from its first bytes
+pub(crate) fn detect(path: &str) -> io::Result<Option<Compression>> {
+    let mut start = Vec::new();
+    File::open(path)?.take(6).read_to_end(&mut start)?;
+    Ok(sniff(&start))
+}
+
+/// Open a file for reading from start to finish, decompressing it if it is compressed
+pub(crate) fn reader(path: &str) -> io::Result<Box<dyn BufRead + Send>> {
+    decompress(BufReader::new(File::open(path)?))
+}
+
+/// Wrap a stream in a decompressor if its first bytes show it is compressed
+///
+/// Input made of several concatenated streams, as written by parallel compressors such as
+/// `pigz`, `zstdmt` and `pixz`, are read in full.
+pub(crate) fn decompress<R: BufRead + Send + 'static>(
+    mut reader: R,
+) -> io::Result<Box<dyn BufRead + Send>> {
+    Ok(match sniff(reader.fill_buf()?) {
+        None => Box::new(reader),
+        Some(Compression::Gzip) => {
+            Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(reader)))
+        }
+        Some(Compression::Zstd) => Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?)),
+        Some(Compression::Xz) => Box::new(BufReader::new(
+            xz2::bufread::XzDecoder::new_multi_decoder(reader),
+        )),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    use crate::search::{self, RangeOptions, SearchStrategy};
+    use crate::testing::{self, TempDir};
+
+    const FORMATS: [Compression; 3] = [Compression::Gzip, Compression::Zstd, Compression::Xz];
+
+    /// Compress some data in a format
+    fn compress(compression: Compression, data: &[u8]) -> Vec<u8> {
+        match compression {
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Compression::Zstd => zstd::encode_all(data, 0).unwrap(),
+            Compression::Xz => {
+                let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+        }
+    }
+
+    #[test]
+    fn detects_and_reads_each_format() {
+        let dir = TempDir::new();
+        let lines = testing::lines(&testing::corpus(100));
+        for compression in FORMATS {
+            let path = dir.write(
+                &compression.to_string(),
+                compress(compression, lines.as_bytes()),
+            );
+            assert_eq!(detect(&path).unwrap(), Some(compression));
+            let mut text = String::new();
+            reader(&path).unwrap().read_to_string(&mut text).unwrap();
+            assert_eq!(text, lines);
+        }
+
+        let path = dir.write("pwned.txt", &lines);
+        assert_eq!(detect(&path).unwrap(), None);
+        let mut text = String::new();
+        reader(&path).unwrap().read_to_string(&mut text).unwrap();
+        assert_eq!(text, lines);
+    }
+
+    #[test]
+    fn reads_concatenated_streams_in_full() {
+        let corpus = testing::corpus(100);
+        let (first, second) = corpus.split_at(50);
+        for compression in FORMATS {
+            let data = [
+                compress(compression, testing::lines(first).as_bytes()),
+                compress(compression, testing::lines(second).as_bytes()),
+            ]
+            .concat();
+            let mut text = String::new();
+            decompress(io::Cursor::new(data))
+                .unwrap()
+                .read_to_string(&mut text)
+                .unwrap();
+            assert_eq!(text, testing::lines(&corpus), "{compression}");
+        }
+    }
+
+    #[test]
+    fn searches_compressed_corpora_in_a_single_pass() {
+        let dir = TempDir::new();
+        let corpus = testing::corpus(1000);
+        let (hashes, expected) = testing::lookups(&corpus, 7);
+        let hashes: Vec<&str> = hashes.iter().map(String::as_str).collect();
+        let range = RangeOptions {
+            cache_dir: None,
+            concurrency: 1,
+            retries: 0,
+        };
+        for compression in FORMATS {
+            let data = compress(compression, testing::lines(&corpus).as_bytes());
+            let path = dir.write(&format!("pwned.txt.{compression}"), data);
+
+            let mut found = search::open(&path, SearchStrategy::Auto, &range).unwrap();
+            assert_eq!(found.strategy(), "merge-join");
+            // The hashes are searched in batches, and the join picks up where it stopped
+            let (first, second) = hashes.split_at(hashes.len() / 2);
+            let mut counts = found.search(first).unwrap();
+            counts.extend(found.search(second).unwrap());
+            assert_eq!(counts, expected);
+
+            for strategy in [SearchStrategy::BinarySearch, SearchStrategy::Jump] {
+                let err = search::open(&path, strategy, &range).err().unwrap();
+                assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            }
+        }
+    }
+}
@@ -0,0 +1,240 @@
+//! Jump search over a single sorted `<hash>:<count>` file
+
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+
+use super::{parse_line, Search};
+
+/// A sorted `<hash>:<count>` file, searched with [`jump_search`]
+pub(crate) struct JumpSearch {
+    path: String,
+    reader: BufReader<File>,
+}
+
+impl JumpSearch {
+    /// Open a sorted `<hash>:<count>` file
+    pub(crate) fn open(path: &str) -> io::Result<JumpSearch> {
+        Ok(JumpSearch {
+            path: path.to_string(),
+            reader: BufReader::new(File::open(path)?),
+        })
+    }
+}
+
+impl Search for JumpSearch {
+    fn search(&mut self, hashes: &[&str]) -> io::Result<Vec<usize>> {
+        let mut counts = Vec::with_capacity(hashes.len());
+
+        let mut last_hash = String::new();
+        let mut last_pwned = 0;
+
+        for &hash in hashes {
+            match hash.cmp(last_hash.as_str()) {
+                // The previous search stopped on a larger hash after passing its own, so this hash
+                // lies between the two and is not in the file; searching on from the reader's
+                // position would skip past the larger hash, which may be the next one wanted
+                Ordering::Less => counts.push(0),
+                // The previous search stopped on this very hash after passing its own
+                Ordering::Equal => counts.push(last_pwned),
+                Ordering::Greater => {
+                    // Note the returned hash may not be the same one we're looking for, indicating it was not found
+                    (last_hash, last_pwned) = jump_search(&mut self.reader, hash)?;
+
+                    // Check if the returned hash is actually the one we're looking for
+                    counts.push(if hash == last_hash { last_pwned } else { 0 });
+                }
+            }
+        }
+
+        Ok(counts)
+    }
+
+    fn strategy(&self) -> &'static str {
+        "jump search"
+    }
+
+    fn reopen(&self) -> io::Result<Option<Box<dyn Search>>> {
+        Ok(Some(Box::new(JumpSearch::open(&self.path)?)))
+    }
+}
+
+/// Use a jump search to progressively search through the file for sorted hashes
+///
+/// The search file is expected to contain lines in the format `<hash>:<count>`, where `<count>`
+/// is the number of times it has been seen in breaches; this is precisely the format for the
+/// data files provided by [HaveIBeenPwned]
+///
+/// For each hash given to us, we jump forward in the sorted file until we hit a larger hash.
+/// Then, we jump back to our previous position, and do a simple linear search through that
+/// segment until we either find the hash we're looking for, or once again pass it by.
+///
+/// By leaving the reader's cursor in the position where we last read it, we can pre-sort the
+/// hashes we're looking for and then start the search for the next one where we left off,
+/// allowing us to very quickly search a very large file for a large number of hashes. This
+/// makes this jump search much faster than doing a binary search for each hash, despite a
+/// binary search being much faster for a single search.
+///
+/// Based on the algorithm described at https://www.geeksforgeeks.org/jump-search/
+///
+/// [HaveIBeenPwned]: https://haveibeenpwned.com/Passwords
+fn jump_search<R: BufRead + Seek>(reader: &mut R, hash: &str) -> io::Result<(String, usize)> {
+    // Stash our starting point for this search
+    let mut segment_start = reader.stream_position()?;
+    // Determine the total number of bytes in our reader
+    let n = reader.seek(SeekFrom::End(0))?;
+    // Set our step size to be equal to the square root of the total bytes, to a minimum of 1 byte
+    let step = ((n as f32).sqrt() as i64).max(1);
+    // And return to our starting point
+    reader.seek(SeekFrom::Start(segment_start))?;
+
+    // String buffer to read lines into
+    let mut line = String::new();
+
+    // Jump search phase: Jump `step` bytes forward in the file, and see if we've passed our target
+    loop {
+        // Empty our buffer
+        line.clear();
+        // Read the next line into our buffer
+        if reader.read_line(&mut line)? == 0 {
+            // Reached the end of the file; our target may still be in the last segment
+            break;
+        }
+
+        // Split the line and check if we've found our target
+        let (line_hash, count) = parse_line(&line)?;
+
+        match line_hash.cmp(hash) {
+            // Found our target! Return it directly
+            Ordering::Equal => return Ok((line_hash.to_owned(), count)),
+            // Still "below" our target, keep going
+            Ordering::Less => {}
+            // Overshot our target, break out of this loop and begin the linear search phase
+            Ordering::Greater => break,
+        }
+
+        // Store our current position as the start of the next segment
+        segment_start = reader.stream_position()?;
+        // Jump forward `step` bytes
+        reader.seek(SeekFrom::Current(step))?;
+        // We probably landed in the middle of a line, so read to the end of the line
+        reader.read_line(&mut line)?;
+    }
+
+    // Found the segment where our hash may be, start looking for it linearly
+    // Start by backing up to the start of the segment
+    reader.seek(SeekFrom::Start(segment_start))?;
+    // Loop until we either pass our target or reach the end of the file
+    loop {
+        // Empty our buffer
+        line.clear();
+        // Read the next line into our buffer
+        if reader.read_line(&mut line)? == 0 {
+            // Reached the end of the file without finding our target
+            return Ok(("".to_string(), 0));
+        }
+
+        // Split the line and check if we've found our target
+        let (line_hash, count) = parse_line(&line)?;
+
+        match line_hash.cmp(hash) {
+            // Found our target! Return it directly
+            Ordering::Equal => return Ok((line_hash.to_owned(), count)),
+            // Still "below" our target, keep going
+            Ordering::Less => {}
+            // Overshot our target, means it's not here; return the last hash we did see
+            Ordering::Greater => return Ok((line_hash.to_owned(), count)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::io::Cursor;
+
+    const LOW: &str = "00010000000000000000000000000000";
+    const MIDDLE: &str = "50000000000000000000000000000000";
+    const HIGH: &str = "90000000000000000000000000000000";
+
+    fn corpus() -> String {
+        format!("{LOW}:1\n{MIDDLE}:5\n{HIGH}:9\n")
+    }
+
+    fn open(dir: &TempDir, contents: &str) -> JumpSearch {
+        JumpSearch::open(&dir.write("pwned.txt", contents)).unwrap()
+    }
+
+    #[test]
+    fn finds_hashes_and_misses_others() {
+        let dir = TempDir::new();
+        let mut search = open(&dir, &corpus());
+        let hashes = [
+            LOW,
+            "20000000000000000000000000000000",
+            HIGH,
+            "F0000000000000000000000000000000",
+        ];
+        assert_eq!(search.search(&hashes).unwrap(), [1, 0, 9, 0]);
+    }
+
+    #[test]
+    fn finds_the_hash_a_previous_search_overshot_onto() {
+        let dir = TempDir::new();
+        let mut search = open(&dir, &corpus());
+        // Both misses stop on MIDDLE, which must still be found afterwards
+        let hashes = [
+            "20000000000000000000000000000000",
+            "30000000000000000000000000000000",
+            MIDDLE,
+        ];
+        assert_eq!(search.search(&hashes).unwrap(), [0, 0, 5]);
+    }
+
+    #[test]
+    fn jump_search_stops_on_the_hash_or_the_one_after_it() {
+        let mut reader = Cursor::new(corpus());
+        assert_eq!(
+            jump_search(&mut reader, MIDDLE).unwrap(),
+            (MIDDLE.to_string(), 5)
+        );
+        let mut reader = Cursor::new(corpus());
+        assert_eq!(
+            jump_search(&mut reader, "60000000000000000000000000000000").unwrap(),
+            (HIGH.to_string(), 9)
+        );
+        // Past the last hash, the search ends at the end of the file
+        assert_eq!(
+            jump_search(&mut reader, "F0000000000000000000000000000000").unwrap(),
+            (String::new(), 0)
+        );
+    }
+
+    #[test]
+    fn searches_a_larger_file_in_one_pass() {
+        let dir = TempDir::new();
+        let lines: String = (0..2000u32)
+            .map(|i| format!("{:032X}:{}\n", (i as u128 * 2) << 96, i + 1))
+            .collect();
+        let mut search = open(&dir, &lines);
+        // Every other hash is in the file
+        let hashes: Vec<String> = (0..4000u32)
+            .step_by(3)
+            .map(|i| format!("{:032X}", (i as u128) << 96))
+            .collect();
+        let hashes: Vec<&str> = hashes.iter().map(String::as_str).collect();
+        let expected: Vec<usize> = (0..4000usize)
+            .step_by(3)
+            .map(|i| if i % 2 == 0 { i / 2 + 1 } else { 0 })
+            .collect();
+        assert_eq!(search.search(&hashes).unwrap(), expected);
+    }
+
+    #[test]
+    fn reports_malformed_lines() {
+        let dir = TempDir::new();
+        let mut search = open(&dir, &format!("{LOW}:one\n{HIGH}:9\n"));
+        assert!(search.search(&[HIGH]).is_err());
+    }
+}
@@ -0,0 +1,155 @@
+//! Merge-join over a sorted `<hash>:<count>` stream
+//!
+//! The account hashes and the corpus are both in ascending order, so they can be walked side by
+//! side, the way a merge-join walks two sorted tables: each corpus line is read once, and never
+//! read again. Unlike the other backends this needs no `Seek`, so it works on compressed corpora.
+
+use std::io::{self, BufRead};
+
+use super::{parse_line, Search};
+
+/// A sorted `<hash>:<count>` stream, read once from start to finish
+pub(crate) struct MergeJoin {
+    reader: Box<dyn BufRead + Send>,
+    /// The corpus line the join is stopped at, which no hash searched so far has passed
+    line: String,
+    line_number: usize,
+    /// Hash of the previous corpus line, to check the corpus is sorted
+    previous: String,
+    /// Whether the whole corpus has been read
+    finished: bool,
+}
+
+impl MergeJoin {
+    pub(crate) fn new(reader: Box<dyn BufRead + Send>) -> MergeJoin {
+        MergeJoin {
+            reader,
+            line: String::new(),
+            line_number: 0,
+            previous: String::new(),
+            finished: false,
+        }
+    }
+
+    /// Move on to the next non-empty corpus line
+    ///
+    /// Fails if it is not larger than the line before it, since the join would silently miss
+    /// hashes in an unsorted corpus.
+    fn advance(&mut self) -> io::Result<()> {
+        loop {
+            if let Ok((hash, _)) = parse_line(&self.line) {
+                self.previous.clear();
+                self.previous.push_str(hash);
+            }
+            self.line.clear();
+            if self.reader.read_line(&mut self.line)? == 0 {
+                self.finished = true;
+                return Ok(());
+            }
+            self.line_number += 1;
+            if self.line.trim().is_empty() {
+                continue;
+            }
+
+            let (hash, _) = parse_line(&self.line)?;
+            if !self.previous.is_empty() && hash <= self.previous.as_str() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Line {}: hashes are not in ascending order",
+                        self.line_number
+                    ),
+                ));
+            }
+            return Ok(());
+        }
+    }
+}
+
+impl Search for MergeJoin {
+    fn search(&mut self, hashes: &[&str]) -> io::Result<Vec<usize>> {
+        let mut counts = Vec::with_capacity(hashes.len());
+        if self.line_number == 0 {
+            self.advance()?;
+        }
+
+        for &hash in hashes {
+            let mut count = 0;
+            while !self.finished {
+                let (line_hash, line_count) = parse_line(&self.line)?;
+                match line_hash.cmp(hash) {
+                    // Still below this hash, so no hash to come can match this line either
+                    std::cmp::Ordering::Less => self.advance()?,
+                    std::cmp::Ordering::Equal => {
+                        count = line_count;
+                        break;
+                    }
+                    // Passed this hash, which is not in the corpus; the next may be on this line
+                    std::cmp::Ordering::Greater => break,
+                }
+            }
+            counts.push(count);
+        }
+
+        Ok(counts)
+    }
+
+    fn strategy(&self) -> &'static str {
+        "merge-join"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    /// A merge-join over some text
+    fn merge_join(text: String) -> MergeJoin {
+        MergeJoin::new(Box::new(io::Cursor::new(text)))
+    }
+
+    #[test]
+    fn finds_every_hash_and_misses_its_neighbours() {
+        let corpus = testing::corpus(5000);
+        for step in [1, 7, 1000] {
+            let (hashes, expected) = testing::lookups(&corpus, step);
+            let hashes: Vec<&str> = hashes.iter().map(String::as_str).collect();
+            let mut search = merge_join(testing::lines(&corpus));
+            assert_eq!(search.search(&hashes).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn searches_hashes_beyond_either_end() {
+        let corpus = testing::corpus(10);
+        let mut search = merge_join(format!("\n{}\n", testing::lines(&corpus)));
+        let hashes = [
+            "00000000000000000000000000000000",
+            corpus[0].0.as_str(),
+            corpus[9].0.as_str(),
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF",
+        ];
+        assert_eq!(
+            search.search(&hashes).unwrap(),
+            [0, corpus[0].1, corpus[9].1, 0]
+        );
+        // Once the corpus has been read, nothing more is found
+        assert_eq!(search.search(&hashes[3..]).unwrap(), [0]);
+        assert_eq!(merge_join(String::new()).search(&hashes).unwrap(), [0; 4]);
+    }
+
+    #[test]
+    fn refuses_unsorted_corpora() {
+        let corpus = testing::corpus(3);
+        for text in [
+            format!("{}:1\n{}:1\n", corpus[1].0, corpus[0].0),
+            format!("{}:1\n{}:2\n", corpus[0].0, corpus[0].0),
+        ] {
+            let mut search = merge_join(text);
+            let err = search.search(&[corpus[2].0.as_str()]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().starts_with("Line 2:"), "{err}");
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! Binary search over a memory map of a sorted `<hash>:<count>` file
+//!
+//! The lines are not all the same length, so the search bisects byte offsets rather than line
+//! numbers, and looks for the line around each offset it lands on. Each hash takes about log2 of
+//! the file size probes, which for a handful of hashes is far fewer reads than a jump search.
+
+use std::fs::File;
+use std::io;
+use std::sync::Arc;
+
+use memmap2::Mmap;
+
+use super::{parse_line, Search};
+
+/// A sorted `<hash>:<count>` file, memory-mapped and searched with a binary search
+pub(crate) struct MmapSearch {
+    map: Arc<Mmap>,
+}
+
+impl MmapSearch {
+    /// Memory-map a sorted `<hash>:<count>` file
+    pub(crate) fn open(path: &str) -> io::Result<MmapSearch> {
+        let file = File::open(path)?;
+        // SAFETY: the map is only ever read, and the corpus is not expected to be changed or
+        // truncated while it is being searched
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(MmapSearch { map: Arc::new(map) })
+    }
+
+    /// Look up a single hash
+    ///
+    /// Returns its pwned count, or 0 if it is not in the corpus.
+    pub(crate) fn lookup(&self, hash: &str) -> io::Result<usize> {
+        Ok(self.find(hash, 0)?.0)
+    }
+
+    /// Get the lines of every hash starting with the given prefix, as they are in the file
+    pub(crate) fn prefix_lines(&self, prefix: &str) -> io::Result<&str> {
+        let start = self.find(prefix, 0)?.1;
+        // Every hash with the prefix sorts before the prefix followed by "G", which comes after
+        // every uppercase hexadecimal digit
+        let end = self.find(&format!("{prefix}G"), start)?.1;
+        std::str::from_utf8(&self.map[start..end])
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Find a hash among the lines starting at or after `low`, which must be the start of a line
+    /// no larger than the hash
+    ///
+    /// Returns the pwned count if found, and the start of the first line not below the hash, from
+    /// which the search for the next, larger hash can start.
+    fn find(&self, hash: &str, mut low: usize) -> io::Result<(usize, usize)> {
+        let data = &self.map[..];
+        let mut high = data.len();
+
+        while low < high {
+            let middle = low + (high - low) / 2;
+            // The line holding the middle byte; `low` is the start of a line, so it starts no
+            // earlier than that
+            let start = data[low..middle]
+                .iter()
+                .rposition(|&byte| byte == b'\n')
+                .map_or(low, |i| low + i + 1);
+            let end = data[middle..]
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(data.len(), |i| middle + i + 1);
+            let line = std::str::from_utf8(&data[start..end])
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+            let (line_hash, count) = parse_line(line)?;
+            match line_hash.cmp(hash) {
+                std::cmp::Ordering::Equal => return Ok((count, start)),
+                std::cmp::Ordering::Less => low = end,
+                std::cmp::Ordering::Greater => high = start,
+            }
+        }
+
+        Ok((0, low))
+    }
+}
+
+impl Search for MmapSearch {
+    fn search(&mut self, hashes: &[&str]) -> io::Result<Vec<usize>> {
+        let mut counts = Vec::with_capacity(hashes.len());
+        // The hashes are sorted, so each search can start where the last one left off
+        let mut low = 0;
+
+        for hash in hashes {
+            let (count, next) = self.find(hash, low)?;
+            counts.push(count);
+            low = next;
+        }
+
+        Ok(counts)
+    }
+
+    fn strategy(&self) -> &'static str {
+        "memory-mapped binary search"
+    }
+
+    fn reopen(&self) -> io::Result<Option<Box<dyn Search>>> {
+        // A map can be read from any number of threads at once, so they can all share this one
+        Ok(Some(Box::new(MmapSearch {
+            map: Arc::clone(&self.map),
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+
+    #[test]
+    fn finds_every_hash_and_misses_its_neighbours() {
+        let dir = TempDir::new();
+        let corpus = testing::corpus(5000);
+        let path = dir.write("pwned.txt", testing::lines(&corpus));
+        let mut search = MmapSearch::open(&path).unwrap();
+        for step in [1, 7, 1000] {
+            let (hashes, expected) = testing::lookups(&corpus, step);
+            let hashes: Vec<&str> = hashes.iter().map(String::as_str).collect();
+            assert_eq!(search.search(&hashes).unwrap(), expected);
+            for (hash, count) in hashes.iter().zip(&expected) {
+                assert_eq!(search.lookup(hash).unwrap(), *count);
+            }
+        }
+
+        let mut reopened = search.reopen().unwrap().unwrap();
+        let hash = corpus[42].0.as_str();
+        assert_eq!(reopened.search(&[hash]).unwrap(), [corpus[42].1]);
+    }
+
+    #[test]
+    fn searches_hashes_beyond_either_end() {
+        let dir = TempDir::new();
+        let corpus = testing::corpus(10);
+        // Windows line endings, and no line ending at the end of the file
+        let lines = testing::lines(&corpus).replace('\n', "\r\n");
+        let path = dir.write("pwned.txt", lines.trim_end());
+        let mut search = MmapSearch::open(&path).unwrap();
+        let hashes = [
+            "00000000000000000000000000000000",
+            corpus[0].0.as_str(),
+            corpus[9].0.as_str(),
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF",
+        ];
+        assert_eq!(
+            search.search(&hashes).unwrap(),
+            [0, corpus[0].1, corpus[9].1, 0]
+        );
+
+        let empty = dir.write("empty.txt", "");
+        let mut search = MmapSearch::open(&empty).unwrap();
+        assert_eq!(search.search(&hashes).unwrap(), [0; 4]);
+    }
+
+    #[test]
+    fn reads_the_lines_of_a_prefix() {
+        let dir = TempDir::new();
+        let lines = "0000A000000000000000000000000000:1\n\
+                     00010000000000000000000000000000:2\n\
+                     0001F000000000000000000000000000:3\n\
+                     00020000000000000000000000000000:4\n";
+        let path = dir.write("pwned.txt", lines);
+        let search = MmapSearch::open(&path).unwrap();
+        assert_eq!(search.prefix_lines("0001").unwrap(), &lines[35..105]);
+        assert_eq!(search.prefix_lines("00020").unwrap(), &lines[105..]);
+        assert_eq!(search.prefix_lines("00015").unwrap(), "");
+        assert_eq!(search.prefix_lines("FFFFF").unwrap(), "");
+    }
+
+    #[test]
+    fn reports_malformed_lines() {
+        let dir = TempDir::new();
+        let path = dir.write("pwned.txt", "8846F7EAEE8FB117AD06BDD830B7586C:many\n");
+        let search = MmapSearch::open(&path).unwrap();
+        let err = search
+            .lookup("8846F7EAEE8FB117AD06BDD830B7586C")
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
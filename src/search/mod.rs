@@ -0,0 +1,470 @@
+//! This module holds the backends that search a pwned passwords corpus for account hashes
+//!
+//! Every backend answers the same question: given a batch of unique, uppercase hexadecimal hashes
+//! in ascending order, how many times has each been seen in breaches? Handing over the whole
+//! sorted batch at once lets each backend make a single forward pass over its corpus.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+
+use clap::ValueEnum;
+
+pub(crate) mod binary;
+pub(crate) mod compressed;
+mod jump;
+mod merge;
+mod mmap;
+pub(crate) mod offsets;
+pub(crate) mod parallel;
+pub(crate) mod prefix_dir;
+pub(crate) mod range_api;
+mod scan;
+mod source;
+pub(crate) mod verify;
+
+pub(crate) use binary::BinaryIndex;
+pub(crate) use jump::JumpSearch;
+pub(crate) use merge::MergeJoin;
+pub(crate) use mmap::MmapSearch;
+pub(crate) use offsets::BucketSearch;
+pub(crate) use prefix_dir::PrefixDir;
+pub(crate) use range_api::{RangeApi, RangeOptions};
+pub(crate) use scan::HashSetScan;
+pub(crate) use source::{read_manifest, Source};
+
+/// A pwned passwords corpus that can be searched for hashes
+pub(crate) trait Search: Send {
+    /// Look up each of the given hashes, which must be unique and in ascending order
+    ///
+    /// Returns the pwned count of each hash, in the same order, with 0 for hashes not in the
+    /// corpus.
+    fn search(&mut self, hashes: &[&str]) -> io::Result<Vec<usize>>;
+
+    /// How the corpus is searched, as reported in the statistics
+    fn strategy(&self) -> &'static str;
+
+    /// Open another, independent handle on the same corpus, to search it from another thread
+    ///
+    /// Returns `None` for a corpus that can only be read once, from start to finish.
+    fn reopen(&self) -> io::Result<Option<Box<dyn Search>>> {
+        Ok(None)
+    }
+
+    /// Get ready to search for this many hashes in all, before the first search or reopen
+    ///
+    /// A corpus that chooses how to search by the number of hashes makes its choice here, so that
+    /// the handles reopened for other threads all search the same way, however few hashes each is
+    /// handed.
+    fn prepare(&mut self, _hashes: usize) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// How to search a single sorted `<hash>:<count>` file
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum SearchStrategy {
+    /// Use the file's binary index or prefix offset sidecar if it has one, or else choose between
+    /// a binary search, a jump search and a merge-join
+    Auto,
+    /// Memory-map the file and binary search it for each hash; the file must be uncompressed
+    BinarySearch,
+    /// Jump through the file for each hash; the file must be seekable and uncompressed
+    Jump,
+    /// Read the file once from start to finish, alongside the sorted hashes
+    MergeJoin,
+    /// Read the file once from start to finish, looking each line up in a set of the hashes; the
+    /// file may be in any order, such as the one ordered by prevalence
+    Unsorted,
+}
+
+/// Open the pwned passwords corpus at the given path
+///
+/// A directory is taken to hold one file per 5-character hash prefix, as written by the
+/// PwnedPasswordsDownloader. A file is either a binary index built by the `index` subcommand, or
+/// a single sorted `<hash>:<count>` file, which may also be `-` for standard input. An `http://` or
+/// `https://` URL is taken to be the base URL of a Pwned Passwords range API, searched according
+/// to `range`.
+///
+/// With [`SearchStrategy::Auto`], a binary index of a sorted file at [`index_path`] is used
+/// instead, as long as it is not older than the file, or failing that its prefix offset sidecar,
+/// if it has one. Otherwise a file that cannot be seeked into, such as a pipe or a gzip, zstd or
+/// xz compressed file, is read with a merge-join, and any other file with whichever of a binary
+/// search, a jump search and a merge-join suits the number of hashes; see [`SortedFile`].
+///
+/// With [`SearchStrategy::Unsorted`], any file or stream is read once with a [`HashSetScan`], so
+/// it need not be sorted.
+///
+/// Directories and range APIs are always searched prefix by prefix, so any strategy but
+/// [`SearchStrategy::Auto`] is refused for them rather than ignored.
+pub(crate) fn open(
+    path: &str,
+    strategy: SearchStrategy,
+    range: &RangeOptions,
+) -> io::Result<Box<dyn Search>> {
+    if range_api::is_url(path) {
+        if strategy != SearchStrategy::Auto {
+            return Err(searched_by_prefix(path, "a range API"));
+        }
+        return Ok(Box::new(RangeApi::new(path, range)?));
+    }
+    let needs_seek = matches!(
+        strategy,
+        SearchStrategy::BinarySearch | SearchStrategy::Jump
+    );
+    // Read a stream once from start to finish, however its lines are ordered
+    let single_pass = |reader: Box<dyn BufRead + Send>| -> Box<dyn Search> {
+        if strategy == SearchStrategy::Unsorted {
+            Box::new(HashSetScan::new(reader))
+        } else {
+            Box::new(MergeJoin::new(reader))
+        }
+    };
+    if path == "-" {
+        if needs_seek {
+            return Err(unseekable(path));
+        }
+        return Ok(single_pass(compressed::decompress(BufReader::new(
+            io::stdin(),
+        ))?));
+    }
+
+    let metadata = fs::metadata(path)?;
+    if metadata.is_dir() {
+        if strategy != SearchStrategy::Auto {
+            return Err(searched_by_prefix(path, "a directory"));
+        }
+        return Ok(Box::new(PrefixDir::new(path)));
+    }
+    // Pipes, such as those of process substitution, can only be read once
+    if !metadata.is_file() {
+        if needs_seek {
+            return Err(unseekable(path));
+        }
+        return Ok(single_pass(compressed::reader(path)?));
+    }
+
+    let mut start = Vec::new();
+    File::open(path)?.take(8).read_to_end(&mut start)?;
+    if binary::sniff(&start) {
+        return Ok(Box::new(BinaryIndex::open(path)?));
+    }
+    let compression = compressed::sniff(&start);
+    if strategy == SearchStrategy::Unsorted {
+        return Ok(single_pass(compressed::reader(path)?));
+    }
+    // A file in the wrong order would give wrong answers without any error, so refuse it up front
+    if compression.is_none() {
+        verify::spot_check(path).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("{err}, or search it in any order with `--strategy unsorted`"),
+            )
+        })?;
+    }
+
+    match strategy {
+        _ if needs_seek && compression.is_some() => return Err(unseekable(path)),
+        SearchStrategy::BinarySearch => return Ok(Box::new(MmapSearch::open(path)?)),
+        SearchStrategy::Jump => return Ok(Box::new(JumpSearch::open(path)?)),
+        SearchStrategy::MergeJoin => {
+            return Ok(Box::new(MergeJoin::new(compressed::reader(path)?)))
+        }
+        SearchStrategy::Auto | SearchStrategy::Unsorted => {}
+    }
+
+    let index = index_path(path);
+    if let Ok(index_metadata) = fs::metadata(&index) {
+        if index_metadata.modified()? >= metadata.modified()? {
+            println!("Using binary index {index}");
+            return Ok(Box::new(BinaryIndex::open(&index)?));
+        }
+        eprintln!(
+            "Ignoring binary index {index}, which is older than {path}; rebuild it with the `index` subcommand"
+        );
+    }
+    if let Some(compression) = compression {
+        println!("Reading {compression} compressed {path} in a single pass");
+        return Ok(Box::new(MergeJoin::new(compressed::reader(path)?)));
+    }
+    if let Some(bucket_search) = BucketSearch::open(path)? {
+        println!("Using prefix offset sidecar {}", offsets::sidecar_path(path));
+        return Ok(Box::new(bucket_search));
+    }
+    Ok(Box::new(SortedFile::new(path, metadata.len())))
+}
+
+/// Build an error for a search that needs to seek, asked of a file that cannot be seeked into
+fn unseekable(path: &str) -> io::Error {
+    let name = if path == "-" { "Standard input" } else { path };
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{name} cannot be seeked into, so it can only be read with a merge-join"),
+    )
+}
+
+/// Build the error for a search strategy given for a corpus that is always searched by prefix
+fn searched_by_prefix(path: &str, kind: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{path} is {kind}, which is always searched by prefix; --strategy must be auto"),
+    )
+}
+
+/// Check if a binary search is likely to beat a jump search for this many hashes in a file this
+/// size
+///
+/// A binary search reads about log2 of the file size scattered bytes per hash, where a jump
+/// search reads about the square root of the file size per run, however few the hashes; so for a
+/// handful of hashes, as in a single OU or a batch of new accounts, the binary search wins.
+fn prefers_binary_search(hashes: usize, len: u64) -> bool {
+    let len = len as f64;
+    hashes as f64 * len.log2().max(1.0) <= len.sqrt()
+}
+
+/// Check if a merge-join is likely to beat a jump search for this many hashes in a file this size
+///
+/// A jump search jumps the square root of the file size at a time, then reads back over the last
+/// jump for each hash, so it reads about that much of the file per hash. Once that adds up to
+/// half the file, reading all of it once, without seeking at all, is cheaper.
+fn prefers_merge_join(hashes: usize, len: u64) -> bool {
+    let len = len as f64;
+    hashes as f64 * len.sqrt() >= len / 2.0
+}
+
+/// A sorted `<hash>:<count>` file, searched with a binary search, a jump search or a merge-join
+///
+/// The choice is made by [`Search::prepare`], or else on the first search, once the number of
+/// hashes is known.
+pub(crate) struct SortedFile {
+    path: String,
+    len: u64,
+    chosen: Option<Box<dyn Search>>,
+}
+
+impl SortedFile {
+    pub(crate) fn new(path: &str, len: u64) -> SortedFile {
+        SortedFile {
+            path: path.to_string(),
+            len,
+            chosen: None,
+        }
+    }
+
+    /// Choose how to search for this many hashes, unless the choice has already been made
+    fn choose(&mut self, hashes: usize) -> io::Result<&mut Box<dyn Search>> {
+        if self.chosen.is_none() {
+            let chosen: Box<dyn Search> = if prefers_binary_search(hashes, self.len) {
+                Box::new(MmapSearch::open(&self.path)?)
+            } else if prefers_merge_join(hashes, self.len) {
+                Box::new(MergeJoin::new(compressed::reader(&self.path)?))
+            } else {
+                Box::new(JumpSearch::open(&self.path)?)
+            };
+            self.chosen = Some(chosen);
+        }
+        Ok(self.chosen.as_mut().unwrap())
+    }
+}
+
+impl Search for SortedFile {
+    fn search(&mut self, hashes: &[&str]) -> io::Result<Vec<usize>> {
+        self.choose(hashes.len())?.search(hashes)
+    }
+
+    fn strategy(&self) -> &'static str {
+        self.chosen
+            .as_ref()
+            .map_or("no search", |chosen| chosen.strategy())
+    }
+
+    fn reopen(&self) -> io::Result<Option<Box<dyn Search>>> {
+        match &self.chosen {
+            Some(chosen) => chosen.reopen(),
+            None => Ok(Some(Box::new(SortedFile::new(&self.path, self.len)))),
+        }
+    }
+
+    fn prepare(&mut self, hashes: usize) -> io::Result<()> {
+        self.choose(hashes).map(|_| ())
+    }
+}
+
+/// Default path of the binary index of a `<hash>:<count>` file, where it is picked up
+/// automatically
+pub(crate) fn index_path(path: &str) -> String {
+    format!("{path}.bin")
+}
+
+/// Build an error for a malformed corpus line
+fn invalid(line: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Malformed pwned passwords line \"{}\"", line.trim()),
+    )
+}
+
+/// Split a `<hash>:<count>` line into its hash and count
+fn parse_line(line: &str) -> io::Result<(&str, usize)> {
+    let (hash, count) = line.trim().split_once(':').ok_or_else(|| invalid(line))?;
+    let count = count.parse().map_err(|_| invalid(line))?;
+    Ok((hash, count))
+}
+
+/// Look up hashes in a bucket of `<hash>:<count>` lines that holds every hash sharing a prefix
+///
+/// The first `skip` characters of each hash are left out of the lines, as in the per-prefix files
+/// whose lines hold only the rest of the hash. Returns the pwned count of each hash.
+fn search_bucket(bucket: &str, hashes: &[&str], skip: usize) -> io::Result<Vec<usize>> {
+    let mut entries = bucket
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(parse_line)
+        .collect::<io::Result<Vec<_>>>()?;
+    // Buckets are small, so sorting them costs little and guards against files assembled by hand
+    entries.sort_unstable();
+
+    Ok(hashes
+        .iter()
+        .map(|hash| {
+            let rest = hash.get(skip..).unwrap_or_default();
+            entries
+                .binary_search_by(|(entry, _)| (*entry).cmp(rest))
+                .map_or(0, |i| entries[i].1)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+
+    /// Options for opening corpora that are not range APIs
+    const NO_RANGE: RangeOptions = RangeOptions {
+        cache_dir: None,
+        concurrency: 1,
+        retries: 0,
+    };
+
+    #[test]
+    fn parses_corpus_lines() {
+        assert_eq!(
+            parse_line("8846F7EAEE8FB117AD06BDD830B7586C:2\r\n").unwrap(),
+            ("8846F7EAEE8FB117AD06BDD830B7586C", 2)
+        );
+        assert!(parse_line("8846F7EAEE8FB117AD06BDD830B7586C").is_err());
+        assert!(parse_line("8846F7EAEE8FB117AD06BDD830B7586C:many").is_err());
+    }
+
+    #[test]
+    fn searches_a_bucket_without_its_prefix() {
+        let bucket = "F7EAEE8FB117AD06BDD830B7586C:2\n\n0000000000000000000000000000:1\n";
+        let hashes = [
+            "88460000000000000000000000000000",
+            "8846F7EAEE8FB117AD06BDD830B7586C",
+            "8846FFFFFFFFFFFFFFFFFFFFFFFFFFFF",
+        ];
+        assert_eq!(search_bucket(bucket, &hashes, 4).unwrap(), [1, 2, 0]);
+        assert!(search_bucket("broken", &hashes, 4).is_err());
+    }
+
+    #[test]
+    fn prefers_binary_search_for_a_handful_of_hashes() {
+        // A 1 MiB file is 20 reads a hash for a binary search, and 1024 a run for a jump search
+        assert!(prefers_binary_search(51, 1 << 20));
+        assert!(!prefers_binary_search(52, 1 << 20));
+        // A 1 GiB file is 30 reads a hash, against 32768 a run
+        assert!(prefers_binary_search(1092, 1 << 30));
+        assert!(!prefers_binary_search(1093, 1 << 30));
+        // Tiny files are not worth a binary search for more than a single hash
+        assert!(prefers_binary_search(1, 1));
+        assert!(!prefers_binary_search(2, 1));
+        assert!(prefers_binary_search(0, 0));
+        assert!(!prefers_binary_search(1, 0));
+    }
+
+    #[test]
+    fn prefers_merge_join_once_jumps_add_up_to_half_the_file() {
+        // Jumps of 1024 bytes through a 1 MiB file add up to half of it after 512 hashes
+        assert!(!prefers_merge_join(511, 1 << 20));
+        assert!(prefers_merge_join(512, 1 << 20));
+        // Jumps of 32768 bytes through a 1 GiB file add up to half of it after 16384 hashes
+        assert!(!prefers_merge_join(16383, 1 << 30));
+        assert!(prefers_merge_join(16384, 1 << 30));
+        // An empty file is read in full at no cost
+        assert!(prefers_merge_join(0, 0));
+        assert!(!prefers_merge_join(0, 1));
+    }
+
+    #[test]
+    fn sorted_file_chooses_by_the_number_of_hashes() {
+        let dir = TempDir::new();
+        let corpus = testing::corpus(5000);
+        let path = dir.write("pwned.txt", testing::lines(&corpus));
+        let len = fs::metadata(&path).unwrap().len();
+        let (hashes, expected) = testing::lookups(&corpus, 1);
+        let hashes: Vec<&str> = hashes.iter().map(String::as_str).collect();
+
+        for (count, strategy) in [
+            (2, "memory-mapped binary search"),
+            (100, "jump search"),
+            (hashes.len(), "merge-join"),
+        ] {
+            let mut search = SortedFile::new(&path, len);
+            assert_eq!(search.strategy(), "no search");
+            assert_eq!(search.search(&hashes[..count]).unwrap(), expected[..count]);
+            assert_eq!(search.strategy(), strategy);
+        }
+    }
+
+    #[test]
+    fn every_strategy_finds_the_same_counts() {
+        let dir = TempDir::new();
+        let corpus = testing::corpus(5000);
+        let lines = testing::lines(&corpus);
+        let path = dir.write("pwned.txt", &lines);
+        let (hashes, expected) = testing::lookups(&corpus, 3);
+        let hashes: Vec<&str> = hashes.iter().map(String::as_str).collect();
+
+        let mut searches = Vec::new();
+        for strategy in [
+            SearchStrategy::Auto,
+            SearchStrategy::BinarySearch,
+            SearchStrategy::Jump,
+            SearchStrategy::MergeJoin,
+            SearchStrategy::Unsorted,
+        ] {
+            searches.push(open(&path, strategy, &NO_RANGE).unwrap());
+        }
+        // A file in any order, searched with a hash set scan
+        let mut shuffled: Vec<&str> = lines.lines().collect();
+        shuffled.reverse();
+        let shuffled = dir.write("shuffled.txt", shuffled.join("\n"));
+        searches.push(open(&shuffled, SearchStrategy::Unsorted, &NO_RANGE).unwrap());
+        // A directory of per-prefix files
+        let prefixes = TempDir::new();
+        for bucket in corpus.chunk_by(|a, b| a.0[..5] == b.0[..5]) {
+            let suffixes: String = bucket
+                .iter()
+                .map(|(hash, count)| format!("{}:{count}\r\n", &hash[5..]))
+                .collect();
+            prefixes.write(&format!("{}.txt", &bucket[0].0[..5]), suffixes);
+        }
+        searches.push(open(prefixes.path(), SearchStrategy::Auto, &NO_RANGE).unwrap());
+        // A directory is always searched by prefix
+        let err = open(prefixes.path(), SearchStrategy::Jump, &NO_RANGE)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        // The prefix offset sidecar, and then the binary index, are picked up automatically
+        offsets::build(&path).unwrap();
+        searches.push(open(&path, SearchStrategy::Auto, &NO_RANGE).unwrap());
+        binary::build(&path, &index_path(&path)).unwrap();
+        searches.push(open(&path, SearchStrategy::Auto, &NO_RANGE).unwrap());
+
+        for mut search in searches {
+            let counts = search.search(&hashes).unwrap();
+            assert!(counts == expected, "{} differs", search.strategy());
+        }
+    }
+}
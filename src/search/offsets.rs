@@ -0,0 +1,313 @@
+//! Prefix offset sidecar for a sorted `<hash>:<count>` text file, and a search that uses it
+//!
+//! The sidecar maps each 20-bit hash prefix (the first 5 hexadecimal characters) to the byte
+//! offset of the first line in the text file with that prefix or a larger one, so the lines of any
+//! prefix can be read with a single seek. It is about 8 MB, however large the text file is.
+//!
+//! | Offset | Size            | Contents                                            |
+//! |--------|-----------------|-----------------------------------------------------|
+//! | 0      | 8               | Magic, `ADPWNOFS`                                   |
+//! | 8      | 4               | Format version, little-endian                       |
+//! | 12     | 4               | Reserved                                            |
+//! | 16     | 8               | Size of the text file, little-endian                |
+//! | 24     | 8               | Modification time of the text file, seconds         |
+//! | 32     | 4               | Modification time of the text file, nanoseconds     |
+//! | 36     | 4               | Reserved                                            |
+//! | 40     | 8 × (2^20 + 1)  | Offset of each prefix, then the size of the file    |
+//!
+//! The size and modification time of the text file are recorded so that a sidecar that no longer
+//! matches its text file is noticed, and rebuilt.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use super::prefix_dir::PREFIX_LEN;
+use super::{compressed, search_bucket, Search};
+
+/// Magic bytes at the start of every sidecar
+const MAGIC: &[u8; 8] = b"ADPWNOFS";
+
+/// Version of the sidecar format written by this build
+const VERSION: u32 = 1;
+
+/// Size of the header, in bytes
+const HEADER_LEN: usize = 40;
+
+/// Number of distinct prefixes
+const PREFIXES: usize = 1 << (PREFIX_LEN * 4);
+
+/// Path of the sidecar of a `<hash>:<count>` file
+pub(crate) fn sidecar_path(path: &str) -> String {
+    format!("{path}.offsets")
+}
+
+/// Get the prefix of a hash as a number
+fn prefix(hash: &str) -> Option<usize> {
+    usize::from_str_radix(hash.get(..PREFIX_LEN)?, 16).ok()
+}
+
+/// Size and modification time of a text file, as recorded in the header of its sidecar
+fn stamp(path: &str) -> io::Result<(u64, u64, u32)> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok((
+        metadata.len(),
+        modified.as_secs(),
+        modified.subsec_nanos(),
+    ))
+}
+
+/// Build the sidecar of a sorted `<hash>:<count>` text file
+///
+/// This reads the whole text file once. Fails if the hashes are not sorted by prefix, since the
+/// sidecar would then point at the wrong lines, or if the file is compressed, since there is no
+/// seeking to those lines.
+pub(crate) fn build(path: &str) -> io::Result<()> {
+    if let Some(compression) = compressed::detect(path)? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{path} is {compression} compressed; build a binary index of it instead"),
+        ));
+    }
+    let (len, secs, nanos) = stamp(path)?;
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut offsets = vec![0u64; PREFIXES + 1];
+    // The next prefix whose offset is still to be filled in
+    let mut next = 0;
+    let mut offset = 0u64;
+    let mut line = String::new();
+    let mut line_number = 0;
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        if read == 0 {
+            break;
+        }
+        line_number += 1;
+
+        if let Some(prefix) = prefix(&line) {
+            if prefix + 1 < next {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Line {line_number}: hashes are not in ascending order"),
+                ));
+            }
+            // This line starts every prefix up to its own that has no lines of its own
+            while next <= prefix {
+                offsets[next] = offset;
+                next += 1;
+            }
+        }
+        offset += read as u64;
+    }
+    // Prefixes after the last line, and the end of the last prefix, are at the end of the file
+    for entry in &mut offsets[next..] {
+        *entry = offset;
+    }
+
+    let partial_path = format!("{}.partial", sidecar_path(path));
+    let mut writer = BufWriter::new(File::create(&partial_path)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&[0; 4])?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(&secs.to_le_bytes())?;
+    writer.write_all(&nanos.to_le_bytes())?;
+    writer.write_all(&[0; 4])?;
+    for offset in &offsets {
+        writer.write_all(&offset.to_le_bytes())?;
+    }
+    writer.flush()?;
+    fs::rename(&partial_path, sidecar_path(path))
+}
+
+/// Read the offsets from the sidecar of a text file, if it is present and matches the text file
+fn load(path: &str) -> io::Result<Option<Vec<u64>>> {
+    let mut data = Vec::new();
+    File::open(sidecar_path(path))?.read_to_end(&mut data)?;
+    if data.len() != HEADER_LEN + (PREFIXES + 1) * 8 || !data.starts_with(MAGIC) {
+        return Ok(None);
+    }
+    let u32_at = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+    let u64_at = |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+    if u32_at(8) != VERSION || (u64_at(16), u64_at(24), u32_at(32)) != stamp(path)? {
+        return Ok(None);
+    }
+
+    Ok(Some(
+        (0..=PREFIXES)
+            .map(|prefix| u64_at(HEADER_LEN + prefix * 8))
+            .collect(),
+    ))
+}
+
+/// A sorted `<hash>:<count>` text file with a prefix offset sidecar
+pub(crate) struct BucketSearch {
+    path: String,
+    file: File,
+    offsets: Arc<Vec<u64>>,
+}
+
+impl BucketSearch {
+    /// Open a text file using its sidecar, rebuilding the sidecar if it no longer matches
+    ///
+    /// Returns `None` if the text file has no sidecar at all.
+    pub(crate) fn open(path: &str) -> io::Result<Option<BucketSearch>> {
+        let sidecar = sidecar_path(path);
+        let offsets = match load(path) {
+            Ok(Some(offsets)) => offsets,
+            Ok(None) => {
+                println!("Prefix offset sidecar {sidecar} does not match {path}; rebuilding it");
+                build(path)?;
+                load(path)?.ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{path} changed while its sidecar was being built"),
+                    )
+                })?
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        Ok(Some(BucketSearch {
+            path: path.to_string(),
+            file: File::open(path)?,
+            offsets: Arc::new(offsets),
+        }))
+    }
+}
+
+impl Search for BucketSearch {
+    fn search(&mut self, hashes: &[&str]) -> io::Result<Vec<usize>> {
+        let mut counts = Vec::with_capacity(hashes.len());
+
+        for group in hashes.chunk_by(|a, b| prefix(a) == prefix(b)) {
+            let Some(prefix) = prefix(group[0]) else {
+                counts.extend(std::iter::repeat_n(0, group.len()));
+                continue;
+            };
+            let (start, end) = (self.offsets[prefix], self.offsets[prefix + 1]);
+            let mut bucket = vec![0; (end - start) as usize];
+            self.file.seek(SeekFrom::Start(start))?;
+            self.file.read_exact(&mut bucket)?;
+            let bucket = String::from_utf8(bucket)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            counts.extend(search_bucket(&bucket, group, 0)?);
+        }
+
+        Ok(counts)
+    }
+
+    fn strategy(&self) -> &'static str {
+        "prefix offset sidecar lookup"
+    }
+
+    fn reopen(&self) -> io::Result<Option<Box<dyn Search>>> {
+        Ok(Some(Box::new(BucketSearch {
+            path: self.path.clone(),
+            file: File::open(&self.path)?,
+            offsets: Arc::clone(&self.offsets),
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+
+    #[test]
+    fn finds_every_hash_and_misses_its_neighbours() {
+        let dir = TempDir::new();
+        let corpus = testing::corpus(5000);
+        let path = dir.write("pwned.txt", testing::lines(&corpus));
+        build(&path).unwrap();
+        let mut search = BucketSearch::open(&path).unwrap().unwrap();
+        for step in [1, 7, 1000] {
+            let (hashes, expected) = testing::lookups(&corpus, step);
+            let hashes: Vec<&str> = hashes.iter().map(String::as_str).collect();
+            assert_eq!(search.search(&hashes).unwrap(), expected);
+        }
+        let hashes = ["00000000000000000000000000000000", "not a hash"];
+        assert_eq!(search.search(&hashes).unwrap(), [0, 0]);
+
+        let mut reopened = search.reopen().unwrap().unwrap();
+        let hash = corpus[42].0.as_str();
+        assert_eq!(reopened.search(&[hash]).unwrap(), [corpus[42].1]);
+    }
+
+    #[test]
+    fn records_the_offset_of_each_prefix() {
+        let dir = TempDir::new();
+        let lines = "00001000000000000000000000000000:1\n\
+                     00003000000000000000000000000000:2\n\
+                     00003100000000000000000000000000:3\n";
+        let path = dir.write("pwned.txt", lines);
+        build(&path).unwrap();
+        let offsets = load(&path).unwrap().unwrap();
+        assert_eq!(offsets[..5], [0, 0, 35, 35, 105]);
+        assert_eq!(offsets[PREFIXES], 105);
+    }
+
+    #[test]
+    fn opens_nothing_without_a_sidecar() {
+        let dir = TempDir::new();
+        let path = dir.write("pwned.txt", testing::lines(&testing::corpus(10)));
+        assert!(BucketSearch::open(&path).unwrap().is_none());
+    }
+
+    #[test]
+    fn rebuilds_a_sidecar_that_no_longer_matches() {
+        let dir = TempDir::new();
+        let corpus = testing::corpus(100);
+        let path = dir.write("pwned.txt", testing::lines(&corpus[..50]));
+        build(&path).unwrap();
+
+        // Growing the text file changes its size, so the old sidecar is noticed and rebuilt
+        fs::write(&path, testing::lines(&corpus)).unwrap();
+        assert!(load(&path).unwrap().is_none());
+        let mut search = BucketSearch::open(&path).unwrap().unwrap();
+        let hash = corpus[99].0.as_str();
+        assert_eq!(search.search(&[hash]).unwrap(), [corpus[99].1]);
+        assert!(load(&path).unwrap().is_some());
+    }
+
+    #[test]
+    fn ignores_a_malformed_sidecar() {
+        let dir = TempDir::new();
+        let path = dir.write("pwned.txt", testing::lines(&testing::corpus(10)));
+        build(&path).unwrap();
+        let sidecar = fs::read(sidecar_path(&path)).unwrap();
+
+        let mut wrong_version = sidecar.clone();
+        wrong_version[8] = 2;
+        for data in [&sidecar[..sidecar.len() - 8], &wrong_version[..]] {
+            fs::write(sidecar_path(&path), data).unwrap();
+            assert!(load(&path).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn refuses_unsorted_or_compressed_files() {
+        let dir = TempDir::new();
+        let corpus = testing::corpus(100);
+        let path = dir.write(
+            "unsorted.txt",
+            format!("{}:1\n{}:1\n", corpus[99].0, corpus[0].0),
+        );
+        let err = build(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(fs::metadata(sidecar_path(&path)).is_err());
+
+        let path = dir.write("pwned.txt.gz", [0x1f, 0x8b, 0x08, 0x00]);
+        let err = build(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
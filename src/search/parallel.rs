@@ -0,0 +1,157 @@
+//! Searching a corpus from several threads at once
+//!
+//! The sorted hashes are cut into contiguous ranges of hash prefixes, one per thread, and each
+//! range is searched with its own handle on the corpus. The ranges are in ascending order, so
+//! putting their results back together in the same order gives exactly what a single search of
+//! every hash would have.
+
+use std::io;
+use std::thread;
+
+use super::prefix_dir::PREFIX_LEN;
+use super::Search;
+
+/// Cut sorted hashes into at most `shards` contiguous ranges of about the same size
+///
+/// Hashes sharing a 5-character prefix always end up in the same range, so that no two threads
+/// read the same part of the corpus.
+fn shard<'a, 'h>(hashes: &'a [&'h str], shards: usize) -> Vec<&'a [&'h str]> {
+    let mut ranges = Vec::with_capacity(shards);
+    let mut rest = hashes;
+
+    for remaining in (1..=shards).rev() {
+        if rest.is_empty() {
+            break;
+        }
+        let mut end = rest.len().div_ceil(remaining);
+        // Move the end of the range past the hashes that share a prefix with its last hash
+        while end < rest.len() && rest[end].get(..PREFIX_LEN) == rest[end - 1].get(..PREFIX_LEN) {
+            end += 1;
+        }
+        let (range, tail) = rest.split_at(end);
+        ranges.push(range);
+        rest = tail;
+    }
+
+    ranges
+}
+
+/// Search a corpus for sorted hashes, using up to `threads` threads
+///
+/// The corpus is first prepared for the full number of hashes with [`Search::prepare`]. The
+/// calling thread then searches the first range with `corpus` itself, and every other thread
+/// searches with a handle from [`Search::reopen`]. A corpus that cannot be reopened, such as a
+/// compressed file, is searched from the calling thread alone. Returns the pwned count of each
+/// hash, and the number of threads used.
+pub(crate) fn search(
+    corpus: &mut dyn Search,
+    hashes: &[&str],
+    threads: usize,
+) -> io::Result<(Vec<usize>, usize)> {
+    corpus.prepare(hashes.len())?;
+    let ranges = shard(hashes, threads.max(1));
+    let mut handles = Vec::with_capacity(ranges.len().saturating_sub(1));
+    for _ in 1..ranges.len() {
+        match corpus.reopen()? {
+            Some(handle) => handles.push(handle),
+            None => return Ok((corpus.search(hashes)?, 1)),
+        }
+    }
+    let Some((first, others)) = ranges.split_first() else {
+        return Ok((corpus.search(hashes)?, 1));
+    };
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = others
+            .iter()
+            .zip(handles)
+            .map(|(range, mut handle)| scope.spawn(move || handle.search(range)))
+            .collect();
+
+        let mut counts = corpus.search(first)?;
+        for worker in workers {
+            let range_counts = worker
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))?;
+            counts.extend(range_counts);
+        }
+        Ok((counts, ranges.len()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    use crate::search::{self, RangeOptions, SearchStrategy, SortedFile};
+    use crate::testing::{self, TempDir};
+
+    #[test]
+    fn shards_without_splitting_a_prefix() {
+        let hashes = [
+            "00000A", "00000B", "00000C", "11111A", "22222A", "22222B", "33333A",
+        ];
+        assert_eq!(
+            shard(&hashes, 3),
+            [&hashes[..3], &hashes[3..6], &hashes[6..]]
+        );
+        assert_eq!(shard(&hashes, 1), [&hashes[..]]);
+        // Never more ranges than there are prefixes
+        assert_eq!(shard(&hashes[..3], 4), [&hashes[..3]]);
+        assert!(shard(&[], 4).is_empty());
+    }
+
+    #[test]
+    fn threads_find_the_same_counts_as_a_single_thread() {
+        let dir = TempDir::new();
+        let corpus = testing::corpus(5000);
+        let path = dir.write("pwned.txt", testing::lines(&corpus));
+        let len = fs::metadata(&path).unwrap().len();
+        let range = RangeOptions {
+            cache_dir: None,
+            concurrency: 1,
+            retries: 0,
+        };
+
+        for step in [1, 100] {
+            let (hashes, expected) = testing::lookups(&corpus, step);
+            let hashes: Vec<&str> = hashes.iter().map(String::as_str).collect();
+            for strategy in [
+                SearchStrategy::BinarySearch,
+                SearchStrategy::Jump,
+                SearchStrategy::MergeJoin,
+            ] {
+                let mut single = search::open(&path, strategy, &range).unwrap();
+                let mut threaded = search::open(&path, strategy, &range).unwrap();
+                assert_eq!(
+                    search(single.as_mut(), &hashes, 1).unwrap(),
+                    (expected.clone(), 1)
+                );
+                assert_eq!(search(threaded.as_mut(), &hashes, 4).unwrap().0, expected);
+            }
+
+            // The strategy is chosen for all the hashes, not for the few in each thread's range
+            let mut single = SortedFile::new(&path, len);
+            let mut threaded = SortedFile::new(&path, len);
+            assert_eq!(search(&mut single, &hashes, 1).unwrap().0, expected);
+            assert_eq!(search(&mut threaded, &hashes, 4).unwrap().0, expected);
+            assert_eq!(threaded.strategy(), single.strategy());
+        }
+    }
+
+    #[test]
+    fn jump_search_is_kept_when_sharded() {
+        let dir = TempDir::new();
+        let corpus = testing::corpus(5000);
+        let path = dir.write("pwned.txt", testing::lines(&corpus));
+        let len = fs::metadata(&path).unwrap().len();
+        // Enough hashes for a jump search, but few enough in each range for a binary search
+        let (hashes, expected) = testing::lookups(&corpus, 100);
+        let hashes: Vec<&str> = hashes.iter().map(String::as_str).collect();
+
+        let mut sorted_file = SortedFile::new(&path, len);
+        assert_eq!(search(&mut sorted_file, &hashes, 8).unwrap(), (expected, 8));
+        assert_eq!(sorted_file.strategy(), "jump search");
+    }
+}
@@ -0,0 +1,116 @@
+//! Search over a directory of per-prefix files, as written by the PwnedPasswordsDownloader
+//!
+//! https://github.com/HaveIBeenPwned/PwnedPasswordsDownloader can write the corpus as 1,048,576
+//! files, one per 5-character hash prefix, named `<PREFIX>.txt` and holding `<SUFFIX>:<count>`
+//! lines; these are exactly the responses of the Pwned Passwords range API.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use super::{search_bucket, Search};
+
+/// Number of hash characters in a prefix file's name
+pub(crate) const PREFIX_LEN: usize = 5;
+
+/// A directory of per-prefix files
+pub(crate) struct PrefixDir {
+    dir: PathBuf,
+}
+
+impl PrefixDir {
+    pub(crate) fn new(dir: &str) -> PrefixDir {
+        PrefixDir {
+            dir: PathBuf::from(dir),
+        }
+    }
+
+    /// Read the `<SUFFIX>:<count>` lines of a prefix file
+    fn read_prefix(&self, prefix: &str) -> io::Result<String> {
+        let path = self.dir.join(format!("{prefix}.txt"));
+        fs::read_to_string(&path).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("Unable to read prefix file {}: {err}", path.display()),
+            )
+        })
+    }
+}
+
+impl Search for PrefixDir {
+    fn search(&mut self, hashes: &[&str]) -> io::Result<Vec<usize>> {
+        let mut counts = Vec::with_capacity(hashes.len());
+
+        // The hashes are sorted, so those sharing a prefix are next to each other and each prefix
+        // file is read once
+        for group in hashes.chunk_by(|a, b| a.get(..PREFIX_LEN) == b.get(..PREFIX_LEN)) {
+            let Some(prefix) = group[0].get(..PREFIX_LEN) else {
+                counts.extend(std::iter::repeat_n(0, group.len()));
+                continue;
+            };
+            let contents = self.read_prefix(prefix)?;
+            counts.extend(search_bucket(&contents, group, PREFIX_LEN)?);
+        }
+
+        Ok(counts)
+    }
+
+    fn strategy(&self) -> &'static str {
+        "per-prefix file lookup"
+    }
+
+    fn reopen(&self) -> io::Result<Option<Box<dyn Search>>> {
+        Ok(Some(Box::new(PrefixDir {
+            dir: self.dir.clone(),
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    const SUFFIX_A: &str = "00000000000000000000000000A";
+    const SUFFIX_B: &str = "00000000000000000000000000B";
+    const SUFFIX_C: &str = "00000000000000000000000000C";
+
+    #[test]
+    fn reads_each_prefix_file_for_its_hashes() {
+        let dir = TempDir::new();
+        // Range API responses have Windows line endings, and may be padded with counts of 0
+        dir.write("00000.txt", format!("{SUFFIX_A}:3\r\n{SUFFIX_C}:0\r\n"));
+        dir.write("ABCDE.txt", format!("{SUFFIX_B}:7\n{SUFFIX_A}:2\n"));
+        let mut search = PrefixDir::new(dir.path());
+
+        let hashes = [
+            format!("00000{SUFFIX_A}"),
+            format!("00000{SUFFIX_B}"),
+            format!("00000{SUFFIX_C}"),
+            format!("ABCDE{SUFFIX_A}"),
+            format!("ABCDE{SUFFIX_B}"),
+        ];
+        let hashes: Vec<&str> = hashes.iter().map(String::as_str).collect();
+        assert_eq!(search.search(&hashes).unwrap(), [3, 0, 0, 2, 7]);
+    }
+
+    #[test]
+    fn reports_a_missing_prefix_file() {
+        let dir = TempDir::new();
+        let mut search = PrefixDir::new(dir.path());
+        let hash = format!("12345{SUFFIX_A}");
+        let err = search.search(&[hash.as_str()]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().contains("12345.txt"));
+    }
+
+    #[test]
+    fn reopens_the_same_directory() {
+        let dir = TempDir::new();
+        dir.write("ABCDE.txt", format!("{SUFFIX_B}:7\n"));
+        let search = PrefixDir::new(dir.path());
+        let mut reopened = search.reopen().unwrap().unwrap();
+        let hash = format!("ABCDE{SUFFIX_B}");
+        assert_eq!(reopened.search(&[hash.as_str()]).unwrap(), [7]);
+    }
+}
@@ -0,0 +1,357 @@
+//! Search through a Pwned Passwords range API, such as an internal mirror of HaveIBeenPwned's
+//!
+//! For each 5-character prefix of the account hashes, `{base_url}/range/{prefix}?mode=ntlm` is
+//! requested, and the hashes are matched against the `<SUFFIX>:<count>` lines of the response
+//! locally; only the prefixes ever leave this machine. Responses may be cached on disk in the same
+//! per-prefix layout the PwnedPasswordsDownloader writes, so that later runs need not ask again.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use super::prefix_dir::PREFIX_LEN;
+use super::{search_bucket, Search};
+
+/// Options for searching a range API
+#[derive(Clone, Debug)]
+pub(crate) struct RangeOptions {
+    /// Directory to cache responses in, if any
+    pub(crate) cache_dir: Option<String>,
+    /// Largest number of requests in flight at once
+    pub(crate) concurrency: usize,
+    /// Number of times a failed request is retried
+    pub(crate) retries: u32,
+}
+
+/// Check if a corpus path is the base URL of a range API
+pub(crate) fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// A Pwned Passwords range API
+pub(crate) struct RangeApi {
+    base_url: String,
+    agent: ureq::Agent,
+    cache_dir: Option<PathBuf>,
+    concurrency: usize,
+    retries: u32,
+}
+
+impl RangeApi {
+    pub(crate) fn new(base_url: &str, options: &RangeOptions) -> io::Result<RangeApi> {
+        let cache_dir = options.cache_dir.as_ref().map(PathBuf::from);
+        if let Some(cache_dir) = &cache_dir {
+            fs::create_dir_all(cache_dir)?;
+        }
+        Ok(RangeApi {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            agent: agent(),
+            cache_dir,
+            concurrency: options.concurrency.max(1),
+            retries: options.retries,
+        })
+    }
+
+    /// Get the `<SUFFIX>:<count>` lines of a prefix, from the cache or else from the API
+    fn range(&self, prefix: &str) -> io::Result<String> {
+        let cache_path = self
+            .cache_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{prefix}.txt")));
+        if let Some(cache_path) = &cache_path {
+            match fs::read_to_string(cache_path) {
+                Ok(body) => return Ok(body),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        let body = self.fetch(prefix)?;
+        if let Some(cache_path) = &cache_path {
+            write_whole(cache_path, &body)?;
+        }
+        Ok(body)
+    }
+
+    /// Request the `<SUFFIX>:<count>` lines of a prefix from the API
+    ///
+    /// Responses are requested with padding, so that their size does not give away the prefix.
+    fn fetch(&self, prefix: &str) -> io::Result<String> {
+        let url = format!("{}/range/{prefix}?mode=ntlm", self.base_url);
+        let request = self.agent.get(&url).set("Add-Padding", "true");
+        call(&request, self.retries)
+            .map_err(|err| io::Error::other(format!("Range {prefix}: {err}")))?
+            .into_string()
+    }
+}
+
+/// Build the agent range API requests are made with
+pub(crate) fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .user_agent(concat!("adpwned/", env!("CARGO_PKG_VERSION")))
+        .timeout(Duration::from_secs(60))
+        .build()
+}
+
+/// Write a response to a file, through a temporary `.partial` file that is renamed into place
+/// once complete, so that a partial response is never kept
+pub(crate) fn write_whole(path: &Path, body: &str) -> io::Result<()> {
+    let mut partial_path = path.as_os_str().to_owned();
+    partial_path.push(".partial");
+    fs::write(&partial_path, body)?;
+    fs::rename(&partial_path, path)
+}
+
+/// Run a task for each of `0..count` from up to `concurrency` threads at once, each taking the
+/// next item nobody has taken yet, until they run out or a task fails
+///
+/// Returns the result of each item in order, or the error of a failed task; once a task has
+/// failed, the items nobody has taken yet are not run.
+pub(crate) fn run_concurrently<T, F>(
+    count: usize,
+    concurrency: usize,
+    task: F,
+) -> io::Result<Vec<T>>
+where
+    T: Send,
+    F: Fn(usize) -> io::Result<T> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let mut results: Vec<Option<T>> = (0..count).map(|_| None).collect();
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..concurrency.max(1).min(count))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut finished = Vec::new();
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        if i >= count || failed.load(Ordering::Relaxed) {
+                            return Ok(finished);
+                        }
+                        match task(i) {
+                            Ok(result) => finished.push((i, result)),
+                            Err(err) => {
+                                failed.store(true, Ordering::Relaxed);
+                                return Err(err);
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        let mut outcome = Ok(());
+        for worker in workers {
+            match worker
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            {
+                Ok(finished) => {
+                    for (i, result) in finished {
+                        results[i] = Some(result);
+                    }
+                }
+                Err(err) => outcome = Err(err),
+            }
+        }
+        outcome
+    })?;
+    Ok(results.into_iter().flatten().collect())
+}
+
+/// Largest number of times a request is retried, however many retries are asked for
+const MAX_RETRIES: u32 = 10;
+
+/// Longest delay before retrying a request, however long the server asks for
+const MAX_DELAY: Duration = Duration::from_secs(64);
+
+/// Delay before retrying a request: as long as the server asks for with `Retry-After`, or else
+/// one that doubles with each attempt, up to [`MAX_DELAY`] either way
+fn retry_delay(attempt: u32, retry_after: Option<&str>) -> Duration {
+    let delay = retry_after
+        .and_then(|secs| secs.trim().parse().ok())
+        .map_or(Duration::from_secs(1 << attempt.min(6)), Duration::from_secs);
+    delay.min(MAX_DELAY)
+}
+
+/// Make a request, retrying it if it fails
+///
+/// Connection errors, rate limiting and server errors are retried up to `retries` times, but no
+/// more than [`MAX_RETRIES`], after a growing delay, or after as long as the server asks for with
+/// `Retry-After`.
+pub(crate) fn call(request: &ureq::Request, retries: u32) -> io::Result<ureq::Response> {
+    let retries = retries.min(MAX_RETRIES);
+    let mut attempt = 0;
+    loop {
+        let retry_after = match request.clone().call() {
+            Err(ureq::Error::Status(status, response))
+                if (status == 429 || status >= 500) && attempt < retries =>
+            {
+                response.header("Retry-After").map(str::to_string)
+            }
+            Err(ureq::Error::Transport(_)) if attempt < retries => None,
+            result => return result.map_err(io::Error::other),
+        };
+        thread::sleep(retry_delay(attempt, retry_after.as_deref()));
+        attempt += 1;
+    }
+}
+
+impl Search for RangeApi {
+    fn search(&mut self, hashes: &[&str]) -> io::Result<Vec<usize>> {
+        let groups: Vec<&[&str]> = hashes
+            .chunk_by(|a, b| a.get(..PREFIX_LEN) == b.get(..PREFIX_LEN))
+            .collect();
+
+        // Request the prefixes from a few threads at once; hashes too short to have a prefix are
+        // in no range
+        let bodies = run_concurrently(groups.len(), self.concurrency, |i| {
+            groups[i][0]
+                .get(..PREFIX_LEN)
+                .map(|prefix| self.range(prefix))
+                .transpose()
+        })?;
+
+        let mut counts = Vec::with_capacity(hashes.len());
+        for (group, body) in groups.iter().zip(&bodies) {
+            match body {
+                Some(body) => counts.extend(search_bucket(body, group, PREFIX_LEN)?),
+                None => counts.extend(std::iter::repeat_n(0, group.len())),
+            }
+        }
+
+        Ok(counts)
+    }
+
+    fn strategy(&self) -> &'static str {
+        "range API requests"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    use crate::search::PrefixDir;
+    use crate::testing::{self, Served, TempDir};
+
+    #[test]
+    fn waits_as_asked_up_to_the_backoff_ceiling() {
+        assert_eq!(retry_delay(0, None), Duration::from_secs(1));
+        assert_eq!(retry_delay(3, None), Duration::from_secs(8));
+        assert_eq!(retry_delay(20, None), MAX_DELAY);
+        assert_eq!(retry_delay(0, Some("5")), Duration::from_secs(5));
+        assert_eq!(retry_delay(0, Some(" 0 ")), Duration::ZERO);
+        assert_eq!(retry_delay(0, Some("86400")), MAX_DELAY);
+        // An HTTP date, or anything else, falls back to the growing delay
+        let date = "Wed, 21 Oct 2015 07:28:00 GMT";
+        assert_eq!(retry_delay(2, Some(date)), Duration::from_secs(4));
+    }
+
+    #[test]
+    fn searches_and_caches_ranges() {
+        let dir = TempDir::new();
+        let corpus = testing::corpus(1000);
+        let (hashes, expected) = testing::lookups(&corpus, 50);
+        let hashes: Vec<&str> = hashes.iter().map(String::as_str).collect();
+        let served = Served::start(&dir.write("pwned.txt", testing::lines(&corpus)));
+        let options = RangeOptions {
+            cache_dir: Some(dir.join("cache")),
+            concurrency: 4,
+            retries: 0,
+        };
+
+        let mut api = RangeApi::new(&served.base_url(), &options).unwrap();
+        assert_eq!(api.search(&hashes).unwrap(), expected);
+        // Each response is renamed into place once it is complete
+        let prefix = &hashes[0][..PREFIX_LEN];
+        let cached = fs::read_to_string(dir.join(&format!("cache/{prefix}.txt"))).unwrap();
+        assert!(cached.contains(&hashes[0][PREFIX_LEN..]));
+        assert!(fs::metadata(dir.join(&format!("cache/{prefix}.txt.partial"))).is_err());
+
+        // Once the server is gone, the cache answers for it
+        let base_url = served.base_url();
+        drop(served);
+        let mut api = RangeApi::new(&base_url, &options).unwrap();
+        assert_eq!(api.search(&hashes).unwrap(), expected);
+        let uncached = format!("{}{}", "F".repeat(PREFIX_LEN), &hashes[0][PREFIX_LEN..]);
+        assert!(api.search(&[&uncached]).is_err());
+
+        // Searched as a directory of prefix files, the cache refuses the prefixes it lacks rather
+        // than report their hashes as not pwned
+        let mut cache = PrefixDir::new(&dir.join("cache"));
+        assert_eq!(cache.search(&hashes).unwrap(), expected);
+        let err = cache.search(&[&uncached]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn runs_every_task_until_one_fails() {
+        for concurrency in [1, 3, 100] {
+            let squares = run_concurrently(50, concurrency, |i| Ok(i * i)).unwrap();
+            assert_eq!(squares, (0..50).map(|i| i * i).collect::<Vec<_>>());
+        }
+        assert!(run_concurrently(0, 4, |_| Ok(())).unwrap().is_empty());
+
+        let run = AtomicUsize::new(0);
+        let err = run_concurrently(1000, 1, |i| {
+            run.fetch_add(1, Ordering::Relaxed);
+            if i == 10 {
+                Err(io::Error::other("failed"))
+            } else {
+                Ok(i)
+            }
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "failed");
+        // Nothing is taken after the failure
+        assert_eq!(run.load(Ordering::Relaxed), 11);
+    }
+
+    #[test]
+    fn retries_a_failed_request() {
+        let dir = TempDir::new();
+        let corpus = testing::corpus(10);
+        let path = dir.write("pwned.txt", testing::lines(&corpus));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+
+        // The first connection is closed without an answer, and the next ones are served
+        let hash = corpus[3].0.clone();
+        let search = std::thread::spawn(move || {
+            let options = RangeOptions {
+                cache_dir: None,
+                concurrency: 1,
+                retries: 2,
+            };
+            RangeApi::new(&base_url, &options).unwrap().search(&[&hash])
+        });
+        drop(listener.accept().unwrap());
+        let _served = Served::from_listener(&path, listener);
+        assert_eq!(search.join().unwrap().unwrap(), [corpus[3].1]);
+    }
+
+    #[test]
+    fn does_not_retry_client_errors() {
+        let dir = TempDir::new();
+        let served = Served::start(&dir.write("pwned.txt", testing::lines(&testing::corpus(10))));
+        let options = RangeOptions {
+            cache_dir: None,
+            concurrency: 1,
+            retries: MAX_RETRIES,
+        };
+        // Not a range API, so every request is answered with a 404
+        let mut api = RangeApi::new(&format!("{}/missing", served.base_url()), &options).unwrap();
+        let start = std::time::Instant::now();
+        let err = api
+            .search(&["8846F7EAEE8FB117AD06BDD830B7586C"])
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Range 8846F:"), "{err}");
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,133 @@
+//! Hash set scan over a `<hash>:<count>` stream in any order
+//!
+//! Corpora that are not sorted by hash, like HaveIBeenPwned's file ordered by prevalence or lists
+//! put together by hand, cannot be searched; instead, every line is read once and looked up in a
+//! hash set of the account hashes. Only the matching lines are kept, so memory is bounded by the
+//! number of hashes to look up rather than the size of the corpus.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+
+use super::{parse_line, Search};
+
+/// A `<hash>:<count>` stream in any order, read once from start to finish
+pub(crate) struct HashSetScan {
+    reader: Box<dyn BufRead + Send>,
+}
+
+impl HashSetScan {
+    pub(crate) fn new(reader: Box<dyn BufRead + Send>) -> HashSetScan {
+        HashSetScan { reader }
+    }
+}
+
+impl Search for HashSetScan {
+    fn search(&mut self, hashes: &[&str]) -> io::Result<Vec<usize>> {
+        let positions: HashMap<&str, usize> = hashes
+            .iter()
+            .enumerate()
+            .map(|(i, &hash)| (hash, i))
+            .collect();
+        let mut counts = vec![0; hashes.len()];
+
+        let mut line = String::new();
+        let mut upper = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let (hash, count) = parse_line(&line)?;
+            // Lists put together by hand may hold lower case hashes
+            let hash = if hash.bytes().any(|b| b.is_ascii_lowercase()) {
+                upper.clear();
+                upper.push_str(hash);
+                upper.make_ascii_uppercase();
+                upper.as_str()
+            } else {
+                hash
+            };
+            // A hash listed more than once is reported with its largest count
+            if let Some(&i) = positions.get(hash) {
+                counts[i] = counts[i].max(count);
+            }
+        }
+
+        Ok(counts)
+    }
+
+    fn strategy(&self) -> &'static str {
+        "hash set scan"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::{self, RangeOptions, SearchStrategy};
+    use crate::testing::{self, TempDir};
+
+    /// A scan over some text
+    fn scan(text: String) -> HashSetScan {
+        HashSetScan::new(Box::new(io::Cursor::new(text)))
+    }
+
+    #[test]
+    fn finds_hashes_in_any_order() {
+        let corpus = testing::corpus(5000);
+        let mut lines: Vec<String> = corpus
+            .iter()
+            .map(|(hash, count)| format!("{hash}:{count}"))
+            .collect();
+        lines.sort_by_key(|line| line.rsplit(':').next().unwrap().parse::<usize>().unwrap());
+        let (hashes, expected) = testing::lookups(&corpus, 7);
+        let hashes: Vec<&str> = hashes.iter().map(String::as_str).collect();
+        assert_eq!(scan(lines.join("\r\n")).search(&hashes).unwrap(), expected);
+    }
+
+    #[test]
+    fn matches_lower_case_and_repeated_hashes() {
+        let high = "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF0";
+        let middle = "8846F7EAEE8FB117AD06BDD830B7586C";
+        let text = format!(
+            "{high}:2\n\n{}:5\n{middle}:3\n{high}:1\n",
+            middle.to_ascii_lowercase()
+        );
+        assert_eq!(scan(text).search(&[middle, high]).unwrap(), [5, 2]);
+    }
+
+    #[test]
+    fn reports_malformed_lines() {
+        let err = scan("8846F7EAEE8FB117AD06BDD830B7586C\n".to_string())
+            .search(&["8846F7EAEE8FB117AD06BDD830B7586C"])
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn is_used_for_unsorted_files_only_when_asked_for() {
+        let dir = TempDir::new();
+        let mut corpus = testing::corpus(5000);
+        let (hashes, expected) = testing::lookups(&corpus, 7);
+        let hashes: Vec<&str> = hashes.iter().map(String::as_str).collect();
+        corpus.reverse();
+        let path = dir.write("pwned.txt", testing::lines(&corpus));
+        let range = RangeOptions {
+            cache_dir: None,
+            concurrency: 1,
+            retries: 0,
+        };
+
+        let err = search::open(&path, SearchStrategy::Auto, &range)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("--strategy unsorted"), "{err}");
+        let mut found = search::open(&path, SearchStrategy::Unsorted, &range).unwrap();
+        assert_eq!(found.strategy(), "hash set scan");
+        assert_eq!(found.search(&hashes).unwrap(), expected);
+    }
+}
@@ -0,0 +1,177 @@
+//! Named pwned passwords corpora
+//!
+//! Several corpora can be searched at once, such as HaveIBeenPwned's alongside passwords cracked
+//! in past audits; each is given a name, so that findings can say which corpora a hash was found
+//! in.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// A pwned passwords corpus and the name it is reported under
+#[derive(Clone, Debug)]
+pub(crate) struct Source {
+    pub(crate) name: String,
+    pub(crate) path: String,
+}
+
+impl Source {
+    /// Parse a `[NAME=]PATH` argument
+    ///
+    /// Without a name, the corpus is named after its file, less its extension. An `=` is only
+    /// taken to end a name if there is no path separator before it.
+    pub(crate) fn parse(arg: &str) -> Result<Source, String> {
+        let (name, path) = match arg.split_once('=') {
+            Some((name, path)) if !name.contains(['/', '\\']) => (name.to_string(), path),
+            _ => (default_name(arg), arg),
+        };
+        if name.is_empty() || path.is_empty() {
+            return Err(format!("\"{arg}\" is not in the format [NAME=]PATH"));
+        }
+        if name.contains(['\t', '\n', ';']) {
+            return Err(format!("corpus name \"{name}\" may not hold tabs, newlines or `;`"));
+        }
+        Ok(Source {
+            name,
+            path: path.to_string(),
+        })
+    }
+}
+
+/// Name a corpus after its file, less its extension
+fn default_name(path: &str) -> String {
+    if path == "-" {
+        return "stdin".to_string();
+    }
+    // A range API is named after its host
+    if let Some((_, rest)) = path.split_once("://") {
+        return rest.split(['/', '?']).next().unwrap_or(rest).to_string();
+    }
+    Path::new(path)
+        .file_stem()
+        .map_or_else(|| path.to_string(), |stem| stem.to_string_lossy().into_owned())
+}
+
+/// Read a manifest of corpora
+///
+/// Each line holds a `[NAME=]PATH` corpus; blank lines and lines starting with `#` are skipped.
+/// Relative paths are taken relative to the manifest's own directory.
+pub(crate) fn read_manifest(path: &str) -> io::Result<Vec<Source>> {
+    let contents = fs::read_to_string(path)?;
+    let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+
+    let mut sources = Vec::new();
+    for (idx, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut source = Source::parse(line).map_err(|err| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("Line {}: {err}", idx + 1))
+        })?;
+        // Standard input and range APIs are not files
+        let remote = source.path == "-" || source.path.contains("://");
+        if !remote && Path::new(&source.path).is_relative() {
+            source.path = dir.join(&source.path).to_string_lossy().into_owned();
+        }
+        sources.push(source);
+    }
+
+    Ok(sources)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    /// Parse an argument into its name and path
+    fn parse(arg: &str) -> (String, String) {
+        let source = Source::parse(arg).unwrap();
+        (source.name, source.path)
+    }
+
+    #[test]
+    fn parses_named_corpora() {
+        assert_eq!(
+            parse("hibp=/data/pwned.txt"),
+            ("hibp".to_string(), "/data/pwned.txt".to_string())
+        );
+        // Only the first `=` ends the name
+        assert_eq!(
+            parse("cracked=/data/a=b.txt"),
+            ("cracked".to_string(), "/data/a=b.txt".to_string())
+        );
+    }
+
+    #[test]
+    fn names_corpora_after_their_files() {
+        assert_eq!(
+            parse("/data/pwned-passwords-ntlm.txt.gz"),
+            (
+                "pwned-passwords-ntlm.txt".to_string(),
+                "/data/pwned-passwords-ntlm.txt.gz".to_string()
+            )
+        );
+        // An `=` after a path separator is part of the path
+        assert_eq!(
+            parse("./audit=2024/cracked.txt"),
+            (
+                "cracked".to_string(),
+                "./audit=2024/cracked.txt".to_string()
+            )
+        );
+        assert_eq!(parse("-"), ("stdin".to_string(), "-".to_string()));
+        assert_eq!(
+            parse("https://api.pwnedpasswords.com/range/?mode=ntlm").0,
+            "api.pwnedpasswords.com"
+        );
+    }
+
+    #[test]
+    fn refuses_malformed_arguments() {
+        for arg in ["", "name=", "=/data/pwned.txt", "a;b=/data/pwned.txt"] {
+            assert!(Source::parse(arg).is_err(), "parsed {arg:?}");
+        }
+    }
+
+    #[test]
+    fn reads_manifests_relative_to_their_directory() {
+        let dir = TempDir::new();
+        let manifest = dir.write(
+            "corpora.txt",
+            "# Corpora for the audit\n\
+             hibp=pwned.txt\n\
+             \n\
+             cracked=/data/cracked.txt\n\
+             \x20 mirror=https://pwned.example.com/range/\n\
+             stdin=-\n",
+        );
+        let sources: Vec<(String, String)> = read_manifest(&manifest)
+            .unwrap()
+            .into_iter()
+            .map(|source| (source.name, source.path))
+            .collect();
+        assert_eq!(
+            sources,
+            [
+                ("hibp".to_string(), dir.join("pwned.txt")),
+                ("cracked".to_string(), "/data/cracked.txt".to_string()),
+                (
+                    "mirror".to_string(),
+                    "https://pwned.example.com/range/".to_string()
+                ),
+                ("stdin".to_string(), "-".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_the_line_of_a_malformed_manifest_entry() {
+        let dir = TempDir::new();
+        let manifest = dir.write("corpora.txt", "hibp=pwned.txt\nbroken=\n");
+        let err = read_manifest(&manifest).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("Line 2:"), "{err}");
+    }
+}
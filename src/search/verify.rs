@@ -0,0 +1,287 @@
+//! Checks that a `<hash>:<count>` file is fit to be searched
+//!
+//! Every search of a single file assumes it is sorted by hash, and a file in any other order,
+//! such as HaveIBeenPwned's file ordered by prevalence, gives wrong answers without any error.
+//! [`verify`] reads a whole file and reports everything wrong with it, while [`spot_check`] reads a
+//! few lines spread across it, cheaply enough to do before every search.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+
+/// Number of lines a spot-check reads
+const SPOT_CHECK_LINES: u64 = 64;
+
+/// Something wrong with a line of a `<hash>:<count>` file
+#[derive(Debug)]
+pub(crate) struct Violation {
+    /// Byte offset of the start of the line
+    pub(crate) offset: u64,
+    /// Line number, counting from 1
+    pub(crate) line_number: usize,
+    pub(crate) message: String,
+}
+
+/// Outcome of verifying a `<hash>:<count>` file
+#[derive(Debug, Default)]
+pub(crate) struct Report {
+    /// Number of lines holding a hash
+    pub(crate) hashes: usize,
+    /// The first violations found
+    pub(crate) violations: Vec<Violation>,
+    /// Number of violations found, including those not kept
+    pub(crate) total_violations: usize,
+}
+
+/// Check a single line, returning its hash if it is well formed
+fn check_line(line: &str) -> Result<&str, String> {
+    let (hash, count) = line
+        .split_once(':')
+        .ok_or_else(|| format!("\"{line}\" is not in the format <hash>:<count>"))?;
+    if hash.len() != 32 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("hash \"{hash}\" is not 32 hexadecimal characters"));
+    }
+    if hash.bytes().any(|b| b.is_ascii_lowercase()) {
+        return Err(format!("hash \"{hash}\" is not in upper case"));
+    }
+    if count.parse::<u64>().is_err() {
+        return Err(format!("count \"{count}\" is not a whole number"));
+    }
+    Ok(hash)
+}
+
+/// Check that a hash comes after the hash before it, on the given line
+fn check_order(hash: &str, previous: &str, previous_line: usize) -> Option<String> {
+    if hash == previous {
+        Some(format!("hash {hash} repeats line {previous_line}"))
+    } else if hash < previous {
+        Some(format!(
+            "hash {hash} is out of order, after {previous} on line {previous_line}"
+        ))
+    } else {
+        None
+    }
+}
+
+/// Read a whole `<hash>:<count>` file, checking that every line is well formed, and that the
+/// hashes are in strictly ascending order, with no duplicates
+///
+/// Up to `limit` violations are kept, in the order they were found. Blank lines are allowed, as
+/// every search skips them. Offsets are of the decompressed contents of a compressed file.
+pub(crate) fn verify(mut reader: impl BufRead, limit: usize) -> io::Result<Report> {
+    let mut report = Report::default();
+    let mut offset = 0;
+    let mut line_number = 0;
+    let mut buf = Vec::new();
+    // The last well-formed hash, and the line it was on
+    let mut previous: Option<(String, usize)> = None;
+
+    loop {
+        buf.clear();
+        let read = reader.read_until(b'\n', &mut buf)?;
+        if read == 0 {
+            break;
+        }
+        line_number += 1;
+        let line = String::from_utf8_lossy(&buf);
+        let line = line.trim_end_matches(['\n', '\r']);
+
+        let problem = if line.trim().is_empty() {
+            None
+        } else {
+            match check_line(line) {
+                Err(message) => Some(message),
+                Ok(hash) => {
+                    report.hashes += 1;
+                    let problem = previous
+                        .as_ref()
+                        .and_then(|(previous, line)| check_order(hash, previous, *line));
+                    previous = Some((hash.to_string(), line_number));
+                    problem
+                }
+            }
+        };
+
+        if let Some(message) = problem {
+            report.total_violations += 1;
+            if report.violations.len() < limit {
+                report.violations.push(Violation {
+                    offset,
+                    line_number,
+                    message,
+                });
+            }
+        }
+        offset += read as u64;
+    }
+
+    Ok(report)
+}
+
+/// Check a few lines spread evenly across an uncompressed `<hash>:<count>` file, refusing a file
+/// whose lines are malformed or out of order
+///
+/// This catches a file that is in the wrong order altogether, such as one ordered by prevalence,
+/// without reading more than a few blocks of it; the `verify` subcommand checks every line.
+pub(crate) fn spot_check(path: &str) -> io::Result<()> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let len = reader.seek(SeekFrom::End(0))?;
+
+    let mut previous: Option<(String, u64)> = None;
+    let mut line = String::new();
+    for i in 0..SPOT_CHECK_LINES {
+        // Skip the rest of whichever line the sample falls in, to start on a whole line
+        let mut offset = len * i / SPOT_CHECK_LINES;
+        if offset > 0 {
+            reader.seek(SeekFrom::Start(offset - 1))?;
+            line.clear();
+            offset += reader.read_line(&mut line)? as u64 - 1;
+        } else {
+            reader.seek(SeekFrom::Start(0))?;
+        }
+        // Samples falling in the same line as the last are not checked twice
+        if previous
+            .as_ref()
+            .is_some_and(|(_, previous)| offset <= *previous)
+        {
+            continue;
+        }
+
+        // Blank lines are skipped, as they are by every search
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 || !line.trim().is_empty() {
+                break;
+            }
+            offset += read as u64;
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+
+        let hash = check_line(line).map_err(|message| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{path}, byte {offset}: {message}; check it with the `verify` subcommand"),
+            )
+        })?;
+        if let Some((previous, previous_offset)) = &previous {
+            if hash <= previous.as_str() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{path} is not sorted by hash: hash {hash} at byte {offset} does not come \
+                         after hash {previous} at byte {previous_offset}. It may be the file \
+                         ordered by prevalence, rather than the one ordered by hash; check it \
+                         with the `verify` subcommand"
+                    ),
+                ));
+            }
+        }
+        previous = Some((hash.to_string(), offset));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, TempDir};
+
+    const LOW: &str = "0000000000000000000000000000000A";
+    const MIDDLE: &str = "8846F7EAEE8FB117AD06BDD830B7586C";
+    const HIGH: &str = "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF0";
+
+    /// Line number and message of each violation kept by a report
+    fn violations(report: &Report) -> Vec<(usize, &str)> {
+        report
+            .violations
+            .iter()
+            .map(|violation| (violation.line_number, violation.message.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn checks_lines() {
+        assert_eq!(check_line(&format!("{MIDDLE}:10437277")), Ok(MIDDLE));
+        assert!(check_line(MIDDLE).unwrap_err().contains("<hash>:<count>"));
+        assert!(check_line("8846F7EA:1")
+            .unwrap_err()
+            .contains("32 hexadecimal"));
+        let lower = MIDDLE.to_ascii_lowercase();
+        assert!(check_line(&format!("{lower}:1"))
+            .unwrap_err()
+            .contains("upper case"));
+        assert!(check_line(&format!("{MIDDLE}:-1"))
+            .unwrap_err()
+            .contains("whole number"));
+    }
+
+    #[test]
+    fn accepts_a_sorted_file() {
+        let corpus = testing::corpus(1000);
+        // Blank lines and Windows line endings are allowed
+        let lines = format!("\r\n{}", testing::lines(&corpus).replace('\n', "\r\n"));
+        let report = verify(lines.as_bytes(), 10).unwrap();
+        assert_eq!(report.hashes, corpus.len());
+        assert_eq!(report.total_violations, 0);
+    }
+
+    #[test]
+    fn reports_every_violation_with_its_offset() {
+        let lines = format!("{MIDDLE}:1\n{LOW}:2\n{LOW}:3\nbroken\n\n{HIGH}:many\n{HIGH}:4\n");
+        let report = verify(lines.as_bytes(), 10).unwrap();
+        assert_eq!(report.hashes, 4);
+        assert_eq!(
+            violations(&report),
+            [
+                (
+                    2,
+                    format!("hash {LOW} is out of order, after {MIDDLE} on line 1").as_str()
+                ),
+                (3, format!("hash {LOW} repeats line 2").as_str()),
+                (4, "\"broken\" is not in the format <hash>:<count>"),
+                (6, "count \"many\" is not a whole number"),
+            ]
+        );
+        let offsets: Vec<u64> = report.violations.iter().map(|v| v.offset).collect();
+        assert_eq!(offsets, [35, 70, 105, 113]);
+        assert_eq!(report.total_violations, 4);
+
+        // Only the first violations are kept, but all are counted
+        let report = verify(lines.as_bytes(), 1).unwrap();
+        assert_eq!(report.violations.len(), 1);
+        assert_eq!(report.total_violations, 4);
+    }
+
+    #[test]
+    fn spot_checks_a_sorted_file() {
+        let dir = TempDir::new();
+        let path = dir.write("pwned.txt", testing::lines(&testing::corpus(5000)));
+        spot_check(&path).unwrap();
+        // Files too small to sample 64 lines from are checked too
+        spot_check(&dir.write("small.txt", format!("{LOW}:1\n\n{HIGH}:2\n"))).unwrap();
+        spot_check(&dir.write("empty.txt", "")).unwrap();
+    }
+
+    #[test]
+    fn spot_check_refuses_a_file_ordered_by_prevalence() {
+        let dir = TempDir::new();
+        let mut corpus = testing::corpus(5000);
+        corpus.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        let path = dir.write("pwned.txt", testing::lines(&corpus));
+        let err = spot_check(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("is not sorted by hash"), "{err}");
+    }
+
+    #[test]
+    fn spot_check_refuses_malformed_lines() {
+        let dir = TempDir::new();
+        let path = dir.write("pwned.txt", format!("{MIDDLE}:1:2\n"));
+        let err = spot_check(&path).unwrap_err();
+        assert!(err.to_string().contains("byte 0: count"), "{err}");
+    }
+}
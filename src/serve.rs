@@ -0,0 +1,323 @@
+//! This module serves a sorted pwned passwords file as a local copy of the HaveIBeenPwned range API
+//!
+//! `GET /range/{prefix}?mode=ntlm` answers with every hash in the file starting with the given
+//! 5-character prefix, as `<SUFFIX>:<count>` lines, just like the
+//! [Pwned Passwords API](https://haveibeenpwned.com/API/v3#SearchingPwnedPasswordsByRange). Only
+//! the prefix is ever sent to the server, so it never learns which hash a client is checking.
+//!
+//! Unpadded responses carry an ETag, so that a copy made with `fetch` can be refreshed by asking
+//! only for the prefixes that have changed.
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use rand::Rng;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::search::prefix_dir::PREFIX_LEN;
+use crate::search::{verify, MmapSearch};
+
+/// Smallest and largest number of lines a padded response is padded to, as the real API does
+const PADDED_LINES: (usize, usize) = (800, 1000);
+
+/// Responses for the most recently requested prefixes
+struct Cache {
+    capacity: usize,
+    /// Body of each cached prefix's response, and when it was last used
+    entries: HashMap<String, (Arc<str>, u64)>,
+    clock: u64,
+}
+
+impl Cache {
+    fn new(capacity: usize) -> Cache {
+        Cache {
+            capacity,
+            entries: HashMap::with_capacity(capacity),
+            clock: 0,
+        }
+    }
+
+    fn get(&mut self, prefix: &str) -> Option<Arc<str>> {
+        self.clock += 1;
+        let (body, last_used) = self.entries.get_mut(prefix)?;
+        *last_used = self.clock;
+        Some(Arc::clone(body))
+    }
+
+    /// Cache a prefix's response, making room by dropping the least recently used one if needed
+    fn insert(&mut self, prefix: &str, body: Arc<str>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(prefix, _)| prefix.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(prefix.to_string(), (body, self.clock));
+    }
+}
+
+/// Build the response body for a prefix: each hash's suffix and count, one per line
+fn range(corpus: &MmapSearch, prefix: &str) -> io::Result<String> {
+    let mut body = String::new();
+    for line in corpus.prefix_lines(prefix)?.lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let suffix = line.get(PREFIX_LEN..).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Malformed pwned passwords line \"{line}\""),
+            )
+        })?;
+        if !body.is_empty() {
+            body.push_str("\r\n");
+        }
+        body.push_str(suffix);
+    }
+    Ok(body)
+}
+
+/// Pad a response body with random suffixes with a count of 0, so that its size does not give
+/// away the prefix
+fn pad(body: &str) -> String {
+    let mut rng = rand::thread_rng();
+    let mut lines: Vec<String> = body.lines().map(str::to_string).collect();
+    let target = rng.gen_range(PADDED_LINES.0..=PADDED_LINES.1);
+    while lines.len() < target {
+        let suffix: u128 = rng.gen::<u128>() >> 20;
+        lines.push(format!("{suffix:027X}:0"));
+    }
+    lines.sort_unstable();
+    lines.join("\r\n")
+}
+
+/// Entity tag of a response body, so that clients can ask for a prefix only if it has changed
+fn etag(body: &str) -> String {
+    // FNV-1a, which is stable from one build to the next, unlike the standard library's hasher
+    let hash = body.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    });
+    format!("\"{hash:016x}\"")
+}
+
+/// Answer a single request
+fn handle(corpus: &MmapSearch, cache: &Mutex<Cache>, request: Request) -> io::Result<()> {
+    let text = |status: u16, body: &str| {
+        Response::from_string(body)
+            .with_status_code(status)
+            .with_header(Header::from_bytes("Content-Type", "text/plain").unwrap())
+    };
+
+    if *request.method() != Method::Get {
+        return request.respond(text(405, "Only GET is supported"));
+    }
+    let (path, query) = request
+        .url()
+        .split_once('?')
+        .unwrap_or((request.url(), ""));
+    let Some(prefix) = path.strip_prefix("/range/") else {
+        return request.respond(text(404, "Not found"));
+    };
+    if prefix.len() != PREFIX_LEN || !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return request.respond(text(400, "The hash prefix was not in a valid format"));
+    }
+    let prefix = prefix.to_ascii_uppercase();
+    if !query.split('&').any(|param| param.eq_ignore_ascii_case("mode=ntlm")) {
+        return request.respond(text(400, "Only NTLM hashes are served; add ?mode=ntlm"));
+    }
+    let padding = request.headers().iter().any(|header| {
+        header.field.equiv("Add-Padding") && header.value.as_str().eq_ignore_ascii_case("true")
+    });
+
+    let cached = cache.lock().unwrap().get(&prefix);
+    let body = match cached {
+        Some(body) => body,
+        None => match range(corpus, &prefix) {
+            Ok(body) => {
+                let body: Arc<str> = body.into();
+                cache.lock().unwrap().insert(&prefix, Arc::clone(&body));
+                body
+            }
+            Err(err) => {
+                eprintln!("Failed to read range {prefix}: {err}");
+                return request.respond(text(500, "Failed to read the pwned passwords file"));
+            }
+        },
+    };
+
+    // Padded responses differ every time, so only unpadded ones are tagged
+    if padding {
+        return request.respond(text(200, &pad(&body)));
+    }
+    let etag = etag(&body);
+    let unchanged = request.headers().iter().any(|header| {
+        header.field.equiv("If-None-Match")
+            && header
+                .value
+                .as_str()
+                .split(',')
+                .any(|tag| tag.trim() == etag || tag.trim() == "*")
+    });
+    let etag_header = Header::from_bytes("ETag", etag.as_bytes()).unwrap();
+    if unchanged {
+        request.respond(Response::empty(304).with_header(etag_header))
+    } else {
+        request.respond(text(200, &body).with_header(etag_header))
+    }
+}
+
+/// Serve a sorted pwned passwords file on the given address until the process is stopped
+///
+/// Requests are answered by `threads` threads, and the responses for up to `cache_size` of the
+/// most recently requested prefixes are kept in memory.
+pub(crate) fn run(path: &str, address: &str, threads: usize, cache_size: usize) -> io::Result<()> {
+    verify::spot_check(path)?;
+    let corpus = MmapSearch::open(path)?;
+    let server = Server::http(address).map_err(io::Error::other)?;
+    println!("Serving {path} on http://{}/range/{{prefix}}?mode=ntlm", server.server_addr());
+    serve(&corpus, &server, threads, cache_size);
+    Ok(())
+}
+
+/// Answer a server's requests from a corpus on `threads` threads, until the server has been
+/// unblocked once for each thread
+pub(crate) fn serve(corpus: &MmapSearch, server: &Server, threads: usize, cache_size: usize) {
+    let cache = Mutex::new(Cache::new(cache_size));
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| {
+                for request in server.incoming_requests() {
+                    if let Err(err) = handle(corpus, &cache, request) {
+                        eprintln!("Failed to respond: {err}");
+                    }
+                }
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Served, TempDir};
+
+    const LINES: &str = "8846F00000000000000000000000000A:1\n\
+                         8846F7EAEE8FB117AD06BDD830B7586C:10437277\n\
+                         88470000000000000000000000000000:2\n";
+
+    /// Request a URL, whatever the status of the response
+    fn get(request: ureq::Request) -> ureq::Response {
+        match request.call() {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    #[test]
+    fn cache_drops_the_least_recently_used_prefix() {
+        let mut cache = Cache::new(2);
+        cache.insert("00000", "a".into());
+        cache.insert("00001", "b".into());
+        assert_eq!(cache.get("00000").as_deref(), Some("a"));
+        cache.insert("00002", "c".into());
+        assert_eq!(cache.get("00001"), None);
+        assert_eq!(cache.get("00000").as_deref(), Some("a"));
+        assert_eq!(cache.get("00002").as_deref(), Some("c"));
+
+        let mut cache = Cache::new(0);
+        cache.insert("00000", "a".into());
+        assert_eq!(cache.get("00000"), None);
+    }
+
+    #[test]
+    fn answers_with_the_suffixes_of_a_prefix() {
+        let dir = TempDir::new();
+        let corpus = MmapSearch::open(&dir.write("pwned.txt", LINES)).unwrap();
+        assert_eq!(
+            range(&corpus, "8846F").unwrap(),
+            "00000000000000000000000000A:1\r\n7EAEE8FB117AD06BDD830B7586C:10437277"
+        );
+        assert_eq!(range(&corpus, "12345").unwrap(), "");
+    }
+
+    #[test]
+    fn pads_responses_with_counts_of_0() {
+        let body = "00000000000000000000000000A:1\r\n7EAEE8FB117AD06BDD830B7586C:10437277";
+        let padded = pad(body);
+        let lines: Vec<&str> = padded.lines().collect();
+        assert!((PADDED_LINES.0..=PADDED_LINES.1).contains(&lines.len()));
+        assert!(lines.is_sorted());
+        for line in body.lines() {
+            assert!(lines.contains(&line), "{line} missing");
+        }
+        let padding = lines.iter().filter(|line| line.ends_with(":0")).count();
+        assert_eq!(padding, lines.len() - 2);
+        assert!(lines
+            .iter()
+            .all(|line| line.len() > 28 && line.as_bytes()[27] == b':'));
+    }
+
+    #[test]
+    fn tags_each_body() {
+        assert_eq!(etag(""), "\"cbf29ce484222325\"");
+        assert_eq!(etag("a"), etag("a"));
+        assert_ne!(etag("a"), etag("b"));
+    }
+
+    #[test]
+    fn serves_ranges_over_http() {
+        let dir = TempDir::new();
+        let served = Served::start(&dir.write("pwned.txt", LINES));
+        let url = format!("{}/range/8846f?mode=ntlm", served.base_url());
+
+        let response = get(ureq::get(&url));
+        assert_eq!(response.status(), 200);
+        let etag = response.header("ETag").unwrap().to_string();
+        assert_eq!(
+            response.into_string().unwrap(),
+            "00000000000000000000000000A:1\r\n7EAEE8FB117AD06BDD830B7586C:10437277"
+        );
+
+        // Asked again with its ETag, the unchanged prefix is not sent again
+        let response = get(ureq::get(&url).set("If-None-Match", &etag));
+        assert_eq!(response.status(), 304);
+        assert_eq!(response.header("ETag"), Some(etag.as_str()));
+        let response = get(ureq::get(&url).set("If-None-Match", "\"0000000000000000\""));
+        assert_eq!(response.status(), 200);
+
+        // Padded responses differ every time, so are not tagged
+        let response = get(ureq::get(&url).set("Add-Padding", "true"));
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.header("ETag"), None);
+        assert!(response.into_string().unwrap().lines().count() >= PADDED_LINES.0);
+    }
+
+    #[test]
+    fn refuses_malformed_requests() {
+        let dir = TempDir::new();
+        let served = Served::start(&dir.write("pwned.txt", LINES));
+        let base_url = served.base_url();
+        for (path, status) in [
+            ("/range/8846?mode=ntlm", 400),
+            ("/range/8846G?mode=ntlm", 400),
+            ("/range/8846F", 400),
+            ("/range/8846F?mode=sha1", 400),
+            ("/8846F?mode=ntlm", 404),
+        ] {
+            let response = get(ureq::get(&format!("{base_url}{path}")));
+            assert_eq!(response.status(), status, "{path}");
+        }
+        let response = get(ureq::post(&format!("{base_url}/range/8846F?mode=ntlm")));
+        assert_eq!(response.status(), 405);
+    }
+}
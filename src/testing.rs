@@ -0,0 +1,157 @@
+//! Helpers shared by the unit tests
+
+use std::fs;
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use tiny_http::Server;
+
+use crate::search::MmapSearch;
+use crate::serve;
+
+/// Path of a file under `tests/fixtures`
+pub(crate) fn fixture(name: &str) -> String {
+    format!("{}/tests/fixtures/{name}", env!("CARGO_MANIFEST_DIR"))
+}
+
+/// A directory under the system's temporary directory, removed with everything in it when dropped
+pub(crate) struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub(crate) fn new() -> TempDir {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "adpwned-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    /// Path of the directory, as a string
+    pub(crate) fn path(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+
+    /// Path of a file in the directory, as a string
+    pub(crate) fn join(&self, name: &str) -> String {
+        self.path.join(name).to_str().unwrap().to_string()
+    }
+
+    /// Write a file in the directory, returning its path
+    pub(crate) fn write(&self, name: &str, contents: impl AsRef<[u8]>) -> String {
+        let path = self.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// Advance a SplitMix64 generator, for pseudo-random test data that is the same on every run
+fn split_mix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// A sorted corpus of about `len` uniformly distributed hashes, with their counts
+///
+/// Every hash in the corpus is even, so the odd hash just above each one is known not to be in it.
+pub(crate) fn corpus(len: usize) -> Vec<(String, usize)> {
+    let mut state = len as u64;
+    let mut values: Vec<u128> = (0..len)
+        .map(|_| {
+            let high = split_mix(&mut state) as u128;
+            ((high << 64) | split_mix(&mut state) as u128) & !1
+        })
+        .collect();
+    values.sort_unstable();
+    values.dedup();
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| (format!("{value:032X}"), i % 1000 + 1))
+        .collect()
+}
+
+/// The `<hash>:<count>` lines of a corpus
+pub(crate) fn lines(corpus: &[(String, usize)]) -> String {
+    corpus
+        .iter()
+        .map(|(hash, count)| format!("{hash}:{count}\n"))
+        .collect()
+}
+
+/// Sorted hashes to look up in a corpus, with the count each should be found with: every `step`th
+/// hash of the corpus, each followed by the hash just above it, which is not in the corpus
+pub(crate) fn lookups(corpus: &[(String, usize)], step: usize) -> (Vec<String>, Vec<usize>) {
+    let mut hashes = Vec::new();
+    let mut counts = Vec::new();
+    for (hash, count) in corpus.iter().step_by(step) {
+        let value = u128::from_str_radix(hash, 16).unwrap();
+        hashes.push(hash.clone());
+        counts.push(*count);
+        hashes.push(format!("{:032X}", value + 1));
+        counts.push(0);
+    }
+    (hashes, counts)
+}
+
+/// Number of threads a [`Served`] corpus is served on
+const SERVE_THREADS: usize = 2;
+
+/// A sorted `<hash>:<count>` file served as a range API on a local port, stopped when dropped
+pub(crate) struct Served {
+    server: Arc<Server>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Served {
+    /// Serve a file on an ephemeral port
+    pub(crate) fn start(path: &str) -> Served {
+        Served::from_listener(path, TcpListener::bind("127.0.0.1:0").unwrap())
+    }
+
+    /// Serve a file on a port that is already being listened on
+    pub(crate) fn from_listener(path: &str, listener: TcpListener) -> Served {
+        let corpus = MmapSearch::open(path).unwrap();
+        let server = Arc::new(Server::from_listener(listener, None).unwrap());
+        let thread = thread::spawn({
+            let server = Arc::clone(&server);
+            move || serve::serve(&corpus, &server, SERVE_THREADS, 16)
+        });
+        Served {
+            server,
+            thread: Some(thread),
+        }
+    }
+
+    /// Base URL of the range API
+    pub(crate) fn base_url(&self) -> String {
+        format!("http://{}", self.server.server_addr())
+    }
+}
+
+impl Drop for Served {
+    fn drop(&mut self) {
+        for _ in 0..SERVE_THREADS {
+            self.server.unblock();
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
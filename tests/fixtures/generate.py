@@ -0,0 +1,704 @@
+#!/usr/bin/env python3
+"""Generate the offline database fixtures used by the unit tests
+
+The fixtures are built from scratch, with encryption done by the `cryptography` package rather
+than by adpwned's own code, so that the tests check adpwned against an independent implementation
+of each format. Every account's password is known, and the tests check for its published NT hash.
+Run from this directory, with `python3 generate.py`; the output is the same on every run.
+"""
+
+import hashlib
+import struct
+
+from cryptography.hazmat.decrepit.ciphers.algorithms import ARC4, TripleDES
+from cryptography.hazmat.primitives.ciphers import Cipher, algorithms, modes
+
+# Published NT and LM hashes of the passwords used
+NT = {
+    "password": bytes.fromhex("8846F7EAEE8FB117AD06BDD830B7586C"),
+    "Password1": bytes.fromhex("64F12CDDAA88057E06A81B54E73B949B"),
+    "123456": bytes.fromhex("32ED87BDB5FDC5E9CBA88547376818D4"),
+    "admin": bytes.fromhex("209C6174DA490CAEB422F3FA5A7AE634"),
+}
+LM = {
+    "PASSWORD": bytes.fromhex("E52CAC67419A9A224A3B108F3FA6CB6D"),
+}
+
+
+def material(label, length=16):
+    """Deterministic stand-in for random key material"""
+    return hashlib.sha256(label.encode()).digest()[:length]
+
+
+def u16(n):
+    return struct.pack("<H", n)
+
+
+def u32(n):
+    return struct.pack("<I", n)
+
+
+# Cryptography
+
+
+def rc4(key, data):
+    return Cipher(ARC4(key), mode=None).encryptor().update(data)
+
+
+def aes_cbc_encrypt(key, iv, data):
+    encryptor = Cipher(algorithms.AES(key), modes.CBC(iv)).encryptor()
+    return encryptor.update(data) + encryptor.finalize()
+
+
+def pkcs7(data):
+    pad = 16 - len(data) % 16
+    return data + bytes([pad]) * pad
+
+
+def str_to_key(key):
+    """Spread 7 bytes over the top 7 bits of 8, as in MS-SAMR 2.2.11.1.2"""
+    bits = int.from_bytes(key, "big")
+    return bytes(((bits >> (49 - 7 * i)) & 0x7F) << 1 for i in range(8))
+
+
+def des_rid_encrypt(hash, rid):
+    """Add the DES layer keyed on an account's RID, as in MS-SAMR 2.2.11.1.3"""
+    i = u32(rid)
+    keys = [
+        str_to_key(bytes([i[0], i[1], i[2], i[3], i[0], i[1], i[2]])),
+        str_to_key(bytes([i[3], i[0], i[1], i[2], i[3], i[0], i[1]])),
+    ]
+    out = b""
+    for half, key in enumerate(keys):
+        # Triple DES with the same key three times over is single DES
+        encryptor = Cipher(TripleDES(key * 3), modes.ECB()).encryptor()
+        out += encryptor.update(hash[half * 8 : half * 8 + 8]) + encryptor.finalize()
+    return out
+
+
+# Registry hives
+
+
+class Key:
+    def __init__(self, name, children=(), values=(), class_name=None, list_kind="lf"):
+        self.name = name
+        self.children = list(children)
+        self.values = list(values)
+        self.class_name = class_name
+        self.list_kind = list_kind
+
+
+REG_SZ = 1
+REG_BINARY = 3
+REG_DWORD = 4
+
+
+class HiveWriter:
+    """Lays out the cells of a hive in a single hive bin"""
+
+    def __init__(self):
+        # Cell offsets are relative to the start of the hive bin, whose header takes 32 bytes
+        self.cells = bytearray(32)
+
+    def alloc(self, size):
+        size = (size + 4 + 7) & ~7
+        offset = len(self.cells)
+        self.cells += struct.pack("<i", -size) + bytes(size - 4)
+        return offset
+
+    def put(self, offset, data):
+        self.cells[offset + 4 : offset + 4 + len(data)] = data
+
+    def cell(self, data):
+        offset = self.alloc(len(data))
+        self.put(offset, data)
+        return offset
+
+    def key(self, key, parent, flags=0x20):
+        name = key.name.encode("ascii")
+        offset = self.alloc(76 + len(name))
+
+        class_offset, class_len = 0xFFFFFFFF, 0
+        if key.class_name is not None:
+            class_name = key.class_name.encode("utf-16-le")
+            class_offset, class_len = self.cell(class_name), len(class_name)
+
+        value_offsets = []
+        for value_name, kind, data in key.values:
+            value_name = value_name.encode("ascii")
+            if len(data) <= 4:
+                size, data_offset = len(data) | 0x80000000, data.ljust(4, b"\0")
+            else:
+                size, data_offset = len(data), u32(self.cell(data))
+            value_offsets.append(
+                self.cell(
+                    b"vk"
+                    + u16(len(value_name))
+                    + u32(size)
+                    + data_offset
+                    + u32(kind)
+                    + u16(1)
+                    + u16(0)
+                    + value_name
+                )
+            )
+        values_list = self.cell(b"".join(map(u32, value_offsets))) if value_offsets else 0xFFFFFFFF
+
+        children = sorted(key.children, key=lambda child: child.name.upper())
+        child_offsets = [self.key(child, offset) for child in children]
+        subkeys_list = 0xFFFFFFFF
+        if child_offsets:
+            if key.list_kind == "lf":
+                subkeys_list = self.cell(
+                    b"lf"
+                    + u16(len(children))
+                    + b"".join(
+                        u32(child_offset) + child.name.encode("ascii")[:4].ljust(4, b"\0")
+                        for child, child_offset in zip(children, child_offsets)
+                    )
+                )
+            else:
+                # An index root over index leaves of at most 2 keys each
+                leaves = [
+                    self.cell(b"li" + u16(len(chunk)) + b"".join(map(u32, chunk)))
+                    for chunk in (child_offsets[i : i + 2] for i in range(0, len(child_offsets), 2))
+                ]
+                subkeys_list = self.cell(b"ri" + u16(len(leaves)) + b"".join(map(u32, leaves)))
+
+        self.put(
+            offset,
+            b"nk"
+            + u16(flags)
+            + bytes(8)  # last written
+            + u32(0)  # access bits
+            + u32(parent)
+            + u32(len(children))
+            + u32(0)
+            + u32(subkeys_list)
+            + u32(0xFFFFFFFF)
+            + u32(len(value_offsets))
+            + u32(values_list)
+            + u32(0xFFFFFFFF)  # security
+            + u32(class_offset)
+            + u32(max((len(c.name) * 2 for c in children), default=0))
+            + u32(0)
+            + u32(max((len(v[0]) * 2 for v in key.values), default=0))
+            + u32(max((len(v[2]) for v in key.values), default=0))
+            + u32(0)
+            + u16(len(name))
+            + u16(class_len)
+            + name,
+        )
+        return offset
+
+
+def hive(root, file_name):
+    writer = HiveWriter()
+    # The root key is flagged as the hive's entry key
+    root_offset = writer.key(root, 0xFFFFFFFF, flags=0x2C)
+
+    size = (len(writer.cells) + 8 + 0xFFF) & ~0xFFF
+    free = size - len(writer.cells)
+    bins = bytes(writer.cells) + struct.pack("<i", free) + bytes(free - 4)
+    bins = b"hbin" + u32(0) + u32(size) + bytes(20) + bins[32:]
+
+    base = bytearray(0x1000)
+    base[0:4] = b"regf"
+    base[4:12] = u32(1) + u32(1)  # sequence numbers
+    base[20:36] = u32(1) + u32(5) + u32(0) + u32(1)  # version 1.5, primary file, direct memory load
+    base[0x24:0x2C] = u32(root_offset) + u32(size)
+    base[0x2C:0x30] = u32(1)  # clustering factor
+    base[0x30:0x70] = file_name.encode("utf-16-le").ljust(64, b"\0")
+    checksum = 0
+    for i in range(0, 0x1FC, 4):
+        checksum ^= struct.unpack_from("<I", base, i)[0]
+    base[0x1FC:0x200] = u32(checksum)
+    return bytes(base) + bins
+
+
+def system_hive(boot_key, computer_name):
+    """A `SYSTEM` hive holding the given boot key, spread over the `Lsa` class names"""
+    permutation = [8, 5, 4, 2, 11, 9, 13, 3, 0, 6, 1, 12, 14, 10, 15, 7]
+    scrambled = bytearray(16)
+    for i, j in enumerate(permutation):
+        scrambled[j] = boot_key[i]
+    digits = scrambled.hex()
+
+    lsa = Key(
+        "Lsa",
+        [
+            Key(name, class_name=digits[i * 8 : i * 8 + 8])
+            for i, name in enumerate(["JD", "Skew1", "GBG", "Data"])
+        ],
+        [("LmCompatibilityLevel", REG_DWORD, u32(5))],
+    )
+    computer = Key(
+        "ComputerName",
+        [
+            Key(
+                "ComputerName",
+                values=[("ComputerName", REG_SZ, (computer_name + "\0").encode("utf-16-le"))],
+            )
+        ],
+    )
+    control_set = Key("ControlSet001", [Key("Control", [computer, lsa])])
+    select = Key(
+        "Select",
+        values=[
+            ("Current", REG_DWORD, u32(1)),
+            ("Default", REG_DWORD, u32(1)),
+            ("LastKnownGood", REG_DWORD, u32(1)),
+        ],
+    )
+    return hive(Key("ROOT", [control_set, select]), "SYSTEM")
+
+
+# ESE databases
+
+PAGE_SIZE = 8192
+PAGE_ROOT = 0x01
+PAGE_LEAF = 0x02
+PAGE_PARENT = 0x04
+PAGE_NEW_RECORD_FORMAT = 0x2000
+
+TAG_DEFUNCT = 0x02
+TAG_COMMON = 0x04
+
+
+def record(fixed, variable, tagged):
+    """Lay out a row: fixed columns from ID 1, variable columns from ID 128, and tagged columns as
+    (ID, value, flags) triples, where flags of None means the value has no flags byte"""
+    fixed_data = b"".join(fixed)
+    sizes, variable_data = b"", b""
+    for value in variable:
+        if value is None:
+            sizes += u16(len(variable_data) | 0x8000)
+        else:
+            variable_data += value
+            sizes += u16(len(variable_data))
+
+    header, values = b"", b""
+    start = 4 * len(tagged)
+    for column_id, value, flags in sorted(tagged, key=lambda tag: tag[0]):
+        offset = start + len(values)
+        if flags is not None:
+            value = bytes([flags]) + value
+            offset |= 0x4000
+        header += u16(column_id) + u16(offset)
+        values += value
+
+    return (
+        bytes([len(fixed), 127 + len(variable)])
+        + u16(4 + len(fixed_data))
+        + fixed_data
+        + sizes
+        + variable_data
+        + header
+        + values
+    )
+
+
+def entry(key, data, common=0):
+    """A B+ tree entry: its key, after the first `common` bytes shared with the page key, and data"""
+    if common:
+        return u16(common) + u16(len(key) - common) + key[common:] + data
+    return u16(len(key)) + key + data
+
+
+def page(number, flags, entries, object_id, page_key=b"", previous=0, next=0):
+    """A small page; entries are (tag flags, entry) pairs, after tag 0's page key"""
+    tags = [(0, page_key)] + entries
+    data, tag_array = b"", b""
+    for tag_flags, tag_data in tags:
+        tag_array = u16(len(tag_data)) + u16(len(data) | tag_flags << 13) + tag_array
+        data += tag_data
+
+    header = (
+        u32(0)  # checksum
+        + u32(number)
+        + struct.pack("<Q", 0x1000)  # database time
+        + u32(previous)
+        + u32(next)
+        + u32(object_id)
+        + u16(PAGE_SIZE - 40 - len(data) - len(tag_array))
+        + u16(0)
+        + u16(len(data))
+        + u16(len(tags))
+        + u32(flags | PAGE_NEW_RECORD_FORMAT)
+    )
+    body = header + data
+    return body + bytes(PAGE_SIZE - len(body) - len(tag_array)) + tag_array
+
+
+def database_header():
+    header = bytearray(PAGE_SIZE)
+    header[4:8] = bytes([0xEF, 0xCD, 0xAB, 0x89])
+    header[8:12] = u32(0x620)  # format version
+    header[12:16] = u32(0)  # database file
+    header[52:56] = u32(3)  # clean shutdown
+    header[232:236] = u32(0x14)  # format revision
+    header[236:240] = u32(PAGE_SIZE)
+    return bytes(header)
+
+
+CATALOG_PAGE = 4
+JET_COLTYP_LONG = 4
+JET_COLTYP_BINARY = 9
+JET_COLTYP_LONG_BINARY = 11
+JET_COLTYP_LONG_TEXT = 12
+
+
+def catalog(tables):
+    """The `MSysObjects` catalog, as a single root leaf; tables are (name, object ID, root page,
+    columns), and columns are (name, ID, type, size)"""
+    rows = []
+    for name, object_id, root_page, columns in tables:
+        rows.append(
+            (
+                struct.pack(">IH", object_id, 1) + name.encode(),
+                record([u32(object_id), u16(1), u32(object_id), u32(root_page), u32(80)], [name.encode()], []),
+            )
+        )
+        for column_name, column_id, kind, size in columns:
+            rows.append(
+                (
+                    struct.pack(">IH", object_id, 2) + column_name.encode(),
+                    record(
+                        [u32(object_id), u16(2), u32(column_id), u32(kind), u32(size)],
+                        [column_name.encode()],
+                        [],
+                    ),
+                )
+            )
+    entries = [(0, entry(key, data)) for key, data in sorted(rows)]
+    return page(CATALOG_PAGE, PAGE_ROOT | PAGE_LEAF, entries, 2)
+
+
+# NTDS.dit
+
+DATATABLE_ID = 8
+DATATABLE_ROOT = 5
+
+# Column IDs of the datatable; real databases use IDs from the same ranges, assigned in the order
+# the attributes were added to the schema
+DNT_COL = 1
+RDN = 128
+SAM_ACCOUNT_NAME = 256
+SAM_ACCOUNT_TYPE = 257
+USER_ACCOUNT_CONTROL = 258
+OBJECT_SID = 259
+UNICODE_PWD = 260
+NT_PWD_HISTORY = 261
+DBCS_PWD = 262
+PEK_LIST = 263
+
+DATATABLE_COLUMNS = [
+    ("DNT_col", DNT_COL, JET_COLTYP_LONG, 4),
+    ("ATTm589825", RDN, JET_COLTYP_LONG_TEXT, 0),
+    ("ATTm590045", SAM_ACCOUNT_NAME, JET_COLTYP_LONG_TEXT, 0),
+    ("ATTj590126", SAM_ACCOUNT_TYPE, JET_COLTYP_LONG, 4),
+    ("ATTj589832", USER_ACCOUNT_CONTROL, JET_COLTYP_LONG, 4),
+    ("ATTr589970", OBJECT_SID, JET_COLTYP_BINARY, 0),
+    ("ATTk589914", UNICODE_PWD, JET_COLTYP_LONG_BINARY, 0),
+    ("ATTk589918", NT_PWD_HISTORY, JET_COLTYP_LONG_BINARY, 0),
+    ("ATTk589879", DBCS_PWD, JET_COLTYP_LONG_BINARY, 0),
+    ("ATTk590689", PEK_LIST, JET_COLTYP_LONG_BINARY, 0),
+]
+
+NTDS_BOOT_KEY = material("ntds boot key")
+PEKS = [material("pek 0"), material("pek 1")]
+
+SAM_GROUP_OBJECT = 0x10000000
+SAM_NORMAL_USER_ACCOUNT = 0x30000000
+SAM_MACHINE_ACCOUNT = 0x30000001
+
+
+def sid(rid):
+    """A domain account SID; unlike the other sub-authorities, the RID is stored big-endian"""
+    return (
+        bytes([1, 5, 0, 0, 0, 0, 0, 5])
+        + u32(21)
+        + u32(1004336348)
+        + u32(1177238915)
+        + u32(682003330)
+        + struct.pack(">I", rid)
+    )
+
+
+def pek_list_rc4(boot_key, peks):
+    """The PEK list as written up to Windows Server 2012 R2, with a single key"""
+    key_material = material("rc4 pek list")
+    plain = material("pek list header", 32) + u32(0) + peks[0]
+    key = hashlib.md5(boot_key + key_material * 1000).digest()
+    return bytes([2, 0, 0, 0, 1, 0, 0, 0]) + key_material + rc4(key, plain)
+
+
+def pek_list_aes(boot_key, peks):
+    """The PEK list as written from Windows Server 2016, each key preceded by its index"""
+    key_material = material("aes pek list")
+    plain = material("pek list header", 32)
+    for i, pek in enumerate(peks):
+        plain += u32(i) + pek
+    plain += bytes(-len(plain) % 16)
+    return bytes([3, 0, 0, 0, 1, 0, 0, 0]) + key_material + aes_cbc_encrypt(boot_key, key_material, plain)
+
+
+def hashes_rc4(pek_index, hashes, rid, label):
+    """Hashes encrypted with RC4, as written up to Windows Server 2012 R2"""
+    key_material = material(label)
+    plain = b"".join(des_rid_encrypt(hash, rid) for hash in hashes)
+    key = hashlib.md5(PEKS[pek_index] + key_material).digest()
+    return bytes([0x11, 0, 0, 0, pek_index, 0, 0, 0]) + key_material + rc4(key, plain)
+
+
+def hashes_aes(pek_index, hashes, rid, label):
+    """Hashes encrypted with AES, as written from Windows Server 2016"""
+    key_material = material(label)
+    plain = b"".join(des_rid_encrypt(hash, rid) for hash in hashes)
+    encrypted = aes_cbc_encrypt(PEKS[pek_index], key_material, pkcs7(plain))
+    return bytes([0x13, 0, 0, 0, pek_index, 0, 0, 0]) + key_material + u32(len(plain)) + encrypted
+
+
+def object_row(dnt, rdn, tagged):
+    key = b"\x7f" + struct.pack(">I", dnt)
+    return key, record([u32(dnt)], [rdn.encode("utf-16-le")], tagged)
+
+
+def account_row(dnt, name, kind, uac, rid, unicode_pwd=None, history=None, lm=None):
+    tagged = [
+        (SAM_ACCOUNT_NAME, name.encode("utf-16-le"), None),
+        (SAM_ACCOUNT_TYPE, u32(kind), None),
+        (USER_ACCOUNT_CONTROL, u32(uac), None),
+        # A flags byte of 0 describes an ordinary value
+        (OBJECT_SID, sid(rid), 0),
+    ]
+    for column_id, value in [(UNICODE_PWD, unicode_pwd), (NT_PWD_HISTORY, history), (DBCS_PWD, lm)]:
+        if value is not None:
+            tagged.append((column_id, value, None))
+    return object_row(dnt, name, tagged)
+
+
+def ntds():
+    rows = [
+        object_row(3, "example", [(PEK_LIST, pek_list_aes(NTDS_BOOT_KEY, PEKS), None)]),
+        account_row(
+            4,
+            "Administrator",
+            SAM_NORMAL_USER_ACCOUNT,
+            0x10200,
+            500,
+            hashes_aes(0, [NT["Password1"]], 500, "administrator"),
+            hashes_aes(0, [NT["Password1"], NT["admin"]], 500, "administrator history"),
+        ),
+        # Never had a password set
+        account_row(5, "Guest", SAM_NORMAL_USER_ACCOUNT, 0x222, 501),
+        # Last set before the domain was upgraded to AES encryption
+        account_row(
+            6,
+            "krbtgt",
+            SAM_NORMAL_USER_ACCOUNT,
+            0x202,
+            502,
+            hashes_rc4(0, [NT["123456"]], 502, "krbtgt"),
+            hashes_rc4(0, [NT["123456"]], 502, "krbtgt history"),
+        ),
+        account_row(
+            7,
+            "ghost",
+            SAM_NORMAL_USER_ACCOUNT,
+            0x200,
+            1103,
+            hashes_aes(0, [NT["password"]], 1103, "ghost"),
+        ),
+    ]
+    second_leaf = [
+        object_row(
+            8,
+            "Domain Admins",
+            [
+                (SAM_ACCOUNT_NAME, "Domain Admins".encode("utf-16-le"), None),
+                (SAM_ACCOUNT_TYPE, u32(SAM_GROUP_OBJECT), None),
+                (OBJECT_SID, sid(512), 0),
+            ],
+        ),
+        account_row(
+            9,
+            "alice",
+            SAM_NORMAL_USER_ACCOUNT,
+            0x200,
+            1104,
+            hashes_rc4(1, [NT["password"]], 1104, "alice"),
+            hashes_rc4(1, [NT["password"], NT["Password1"], NT["123456"]], 1104, "alice history"),
+            hashes_rc4(1, [LM["PASSWORD"]], 1104, "alice lm"),
+        ),
+        account_row(
+            10,
+            "DC01$",
+            SAM_MACHINE_ACCOUNT,
+            0x82000,
+            1000,
+            hashes_aes(1, [NT["admin"]], 1000, "dc01"),
+        ),
+    ]
+
+    # The first leaf holds a deleted row, ghost, that has not been cleaned up yet; the second
+    # shares the first 4 bytes of its keys with its page key
+    first = [(TAG_DEFUNCT if key == b"\x7f\x00\x00\x00\x07" else 0, entry(key, data)) for key, data in rows]
+    page_key = b"\x7f\x00\x00\x00"
+    second = [(TAG_COMMON, entry(key, data, common=4)) for key, data in second_leaf]
+    pages = {
+        CATALOG_PAGE: catalog([("datatable", DATATABLE_ID, DATATABLE_ROOT, DATATABLE_COLUMNS)]),
+        DATATABLE_ROOT: page(
+            DATATABLE_ROOT,
+            PAGE_ROOT | PAGE_PARENT,
+            [(0, entry(second_leaf[0][0], u32(6))), (0, entry(b"", u32(7)))],
+            DATATABLE_ID,
+        ),
+        6: page(6, PAGE_LEAF, first, DATATABLE_ID, next=7),
+        7: page(7, PAGE_LEAF, second, DATATABLE_ID, page_key=page_key, previous=6),
+    }
+
+    header = database_header()
+    # Pages 1 to 3 hold the database's own root and space trees, which are not read
+    return header + header + b"".join(pages.get(n, bytes(PAGE_SIZE)) for n in range(1, 8))
+
+
+# SAM hives
+
+SAM_BOOT_KEY = material("sam boot key")
+HASHED_BOOT_KEY = material("hashed boot key")
+
+QWERTY = b"!@#$%^&*()qwertyUIOPAzxcvbnmQQQQQQQQQQQQ)(*@&%\0"
+DIGITS = b"0123456789012345678901234567890123456789\0"
+NTPASSWORD = b"NTPASSWORD\0"
+LMPASSWORD = b"LMPASSWORD\0"
+
+
+def domain_f(revision):
+    """The account domain's `F` value, holding the hashed boot key encrypted with the boot key"""
+    f = bytearray(0x68)
+    f[0:4] = u32(0x20003)  # revision
+    checksum = hashlib.md5(HASHED_BOOT_KEY + DIGITS + HASHED_BOOT_KEY + QWERTY).digest()
+    if revision == 1:
+        salt = material("rc4 domain salt")
+        key = hashlib.md5(salt + QWERTY + SAM_BOOT_KEY + DIGITS).digest()
+        f += u32(1) + u32(0x28) + salt + rc4(key, HASHED_BOOT_KEY + checksum) + bytes(8)
+    else:
+        salt = material("aes domain salt")
+        encrypted = aes_cbc_encrypt(SAM_BOOT_KEY, salt, HASHED_BOOT_KEY + checksum)
+        f += u32(2) + u32(0x20 + len(encrypted)) + u32(0x10) + u32(len(encrypted)) + salt + encrypted
+    return bytes(f)
+
+
+def sam_hash(revision, hash, rid, constant, label):
+    """An account's stored NT or LM hash; an account without one has just the header"""
+    if revision == 1:
+        if hash is None:
+            return u16(0) + u16(1)
+        key = hashlib.md5(HASHED_BOOT_KEY + u32(rid) + constant).digest()
+        return u16(0) + u16(1) + rc4(key, des_rid_encrypt(hash, rid))
+    salt = material(label)
+    if hash is None:
+        return u16(0) + u16(2) + u32(0) + salt
+    encrypted = aes_cbc_encrypt(HASHED_BOOT_KEY, salt, pkcs7(des_rid_encrypt(hash, rid)))
+    return u16(0) + u16(2) + u32(0x10) + salt + encrypted
+
+
+def user_v(name, nt, lm):
+    """An account's `V` value: a table of 17 (offset, length, unknown) entries, with offsets
+    relative to the end of the table, followed by the data"""
+    fields = [b""] * 17
+    fields[0] = bytes([1, 0, 4, 0x80]) + bytes(16)  # security descriptor
+    fields[1] = name.encode("utf-16-le")
+    fields[13] = lm
+    fields[14] = nt
+    table, data = b"", b""
+    for field in fields:
+        table += u32(len(data)) + u32(len(field)) + u32(0)
+        data += field + bytes(-len(field) % 4)
+    return table + data
+
+
+def user_f(rid, control):
+    """An account's `F` value, with its RID at 0x30 and account control bits at 0x38"""
+    f = bytearray(0x50)
+    f[0:4] = u32(0x10002)
+    f[0x30:0x34] = u32(rid)
+    f[0x38:0x3A] = u16(control)
+    return bytes(f)
+
+
+# Account control bits
+ACB_DISABLED = 0x0001
+ACB_NORMAL = 0x0010
+ACB_PWNOEXP = 0x0200
+ACB_PWNOTREQ = 0x0004
+
+
+def sam_hive(revision, accounts):
+    """A `SAM` hive; accounts are (RID, name, control bits, NT password, LM password) tuples"""
+    users = []
+    for rid, name, control, nt, lm in accounts:
+        nt = sam_hash(revision, NT.get(nt), rid, NTPASSWORD, f"{name} nt")
+        lm = sam_hash(revision, LM.get(lm), rid, LMPASSWORD, f"{name} lm")
+        users.append(
+            Key(
+                f"{rid:08X}",
+                values=[("F", REG_BINARY, user_f(rid, control)), ("V", REG_BINARY, user_v(name, nt, lm))],
+            )
+        )
+    names = Key("Names", [Key(name, values=[("", rid, b"")]) for rid, name, *_ in accounts])
+    # Larger hives split long subkey lists into an index root over index leaves
+    account = Key(
+        "Account",
+        [Key("Users", users + [names], list_kind="ri")],
+        [("F", REG_BINARY, domain_f(revision))],
+    )
+    domains = Key("Domains", [account, Key("Builtin")])
+    return hive(Key("ROOT", [Key("SAM", [domains])]), "SAM")
+
+
+def main():
+    with open("ntds.dit", "wb") as f:
+        f.write(ntds())
+    with open("ntds-system.hive", "wb") as f:
+        f.write(system_hive(NTDS_BOOT_KEY, "DC01"))
+
+    with open("sam-system.hive", "wb") as f:
+        f.write(system_hive(SAM_BOOT_KEY, "WS01"))
+    # As written from Windows 10 1607
+    with open("sam.hive", "wb") as f:
+        f.write(
+            sam_hive(
+                2,
+                [
+                    (500, "Administrator", ACB_DISABLED | ACB_NORMAL | ACB_PWNOEXP, "Password1", None),
+                    (501, "Guest", ACB_DISABLED | ACB_NORMAL | ACB_PWNOEXP | ACB_PWNOTREQ, None, None),
+                    (504, "WDAGUtilityAccount", ACB_DISABLED | ACB_NORMAL, "admin", None),
+                    (1001, "alice", ACB_NORMAL, "password", "PASSWORD"),
+                ],
+            )
+        )
+    # As written by older versions
+    with open("sam-rc4.hive", "wb") as f:
+        f.write(
+            sam_hive(
+                1,
+                [
+                    (500, "Administrator", ACB_NORMAL | ACB_PWNOEXP, "123456", None),
+                    (501, "Guest", ACB_DISABLED | ACB_NORMAL | ACB_PWNOTREQ, None, None),
+                    (1000, "alice", ACB_NORMAL, "password", "PASSWORD"),
+                ],
+            )
+        )
+
+    # Values for the unit tests of the older formats, which the database above does not use
+    print("boot key", NTDS_BOOT_KEY.hex())
+    print("pek 0", PEKS[0].hex())
+    print("rc4 pek list", pek_list_rc4(NTDS_BOOT_KEY, PEKS).hex())
+    print("sam boot key", SAM_BOOT_KEY.hex())
+    print("hashed boot key", HASHED_BOOT_KEY.hex())
+
+
+if __name__ == "__main__":
+    main()